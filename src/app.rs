@@ -1,3 +1,8 @@
+use std::fs::{OpenOptions, Permissions};
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
 use lazy_static::lazy_static;
 
 lazy_static! {
@@ -16,3 +21,28 @@ pub fn Name() -> &'static str {
 pub fn name() -> &'static str {
     NAME.as_str()
 }
+
+/// Writes a file only its owner can read, for files that may hold passwords.
+pub fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    // The mode is only applied to files created here
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn private_files_are_owner_only() {
+        let path = std::env::temp_dir().join(format!("crust-private-{}", std::process::id()));
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+        write_private(&path, b"secret").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "secret");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,15 +1,71 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::io::{ErrorKind, Read, Write};
-use std::net::{TcpStream, ToSocketAddrs};
-use std::ops::DerefMut;
+use std::io::{self, ErrorKind, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use mio::{Registry, Token};
+
+pub use crate::client::capabilities::Capabilities;
+use crate::client::capabilities::{CapChange, DEFAULT_CAPABILITIES};
+pub use crate::client::casemapping::{CaseMapping, Identifier};
+pub use crate::client::channel::{Channel, PrefixModes};
+use crate::client::connection::{format_fingerprint, Connection};
+pub use crate::client::connection::{parse_fingerprint, TlsConfig, TlsVerify};
+pub use crate::client::ctcp::CtcpPolicy;
+use crate::client::ctcp::CtcpReplies;
+use crate::client::dcc::DccRequest;
+use crate::client::dccsessions::DccSessions;
+pub use crate::client::dccsessions::{format_size, Chat, ChatState, DccPolicy, Direction, Transfer, TransferState};
+pub use crate::client::event::ClientEvent;
+use crate::client::framer::LineFramer;
+use crate::client::isupport::ISupport;
+pub use crate::client::lag::LagPolicy;
+use crate::client::lag::{Lag, LagCheck};
+use crate::client::message::{IrcMessage, Prefix};
+use crate::client::nick::Nicknames;
+use crate::client::reactor::Reactor;
+use crate::client::reconnect::Reconnect;
+pub use crate::client::reconnect::ReconnectPolicy;
 use crate::client::repliestypes::Replies;
-use crate::client::ringbuffer::RingBuffer;
+use crate::client::sasl::Sasl;
+pub use crate::client::sasl::{SaslCredentials, SaslMechanism};
+pub use crate::client::sendqueue::FloodPolicy;
+use crate::client::sendqueue::{Priority, SendQueue};
+use crate::client::split::{Line, MultilineLimits, MAX_HOST_LEN, MAX_LINE_LEN};
 
+mod capabilities;
+mod casemapping;
+mod channel;
+mod connection;
+mod ctcp;
+mod dcc;
+mod dccsessions;
+mod event;
+mod framer;
+mod isupport;
+mod lag;
+mod message;
+mod nick;
+mod reactor;
+mod reconnect;
 mod repliestypes;
-mod ringbuffer;
+mod sasl;
+mod sendqueue;
+mod split;
+
+/// Longest line accepted from a server: 8191 bytes of IRCv3 tags and the 512 of the message.
+const MAX_INCOMING_LINE_LEN: usize = 8191 + 512;
+
+/// Keeps rejoin lines well below the 512 byte limit.
+const MAX_JOIN_LEN: usize = 400;
+
+/// Length of `@batch=00000001;draft/multiline-concat `, the most tags a line of an outgoing batch has.
+const BATCH_TAGS_LEN: usize = 39;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum ClientError {
     NoNickDefined,
     NoUserDefined,
@@ -32,63 +88,134 @@ pub struct UserInfo {
     nick: String,
     user: String,
     name: String,
+    host: String,
 }
 
 impl UserInfo {
     pub fn new(nick: String, user: String, name: String) -> Result<Self, ClientError> {
-        if nick.len() == 0 {
+        if nick.is_empty() {
             return Err(ClientError::NoNickDefined);
         }
-        if user.len() == 0 {
+        if user.is_empty() {
             return Err(ClientError::NoUserDefined);
         }
-        if name.len() == 0 {
+        if name.is_empty() {
             return Err(ClientError::NoNameDefined);
         }
-        Ok(UserInfo { nick, user, name })
+        Ok(UserInfo {
+            nick,
+            user,
+            name,
+            host: String::new(),
+        })
+    }
+
+    /// Builds the information we know about someone else from the prefix of a message.
+    fn from_prefix(prefix: &Prefix) -> Self {
+        UserInfo {
+            nick: prefix.nick.clone(),
+            user: prefix.user.clone().unwrap_or_default(),
+            name: String::new(),
+            host: prefix.host.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Someone we only know by nick, like the target of a kick or a mode change.
+    pub fn from_nick(nick: String) -> Self {
+        UserInfo {
+            nick,
+            user: String::new(),
+            name: String::new(),
+            host: String::new(),
+        }
+    }
+
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    /// `user@host` when both are known, used when showing joins and parts.
+    pub fn mask(&self) -> Option<String> {
+        if self.user.is_empty() || self.host.is_empty() {
+            None
+        } else {
+            Some(format!("{}@{}", self.user, self.host))
+        }
     }
 
-    fn get_user_msg(&self) -> String {
-        format!("USER {} 0 * :{}", self.user, self.name).to_string()
+    fn get_user_msg(&self) -> IrcMessage {
+        IrcMessage::new("USER", vec![self.user.clone(), "0".into(), "*".into(), self.name.clone()])
     }
 
-    fn get_nick_msg(&self) -> String {
-        format!("NICK {}", self.nick).to_string()
+    fn get_nick_msg(&self) -> IrcMessage {
+        IrcMessage::new("NICK", vec![self.nick.clone()])
     }
 }
 
-#[derive(Debug)]
-pub struct MessageFromServer {
-    source: String,
-    reply_type: Replies,
-    dest: String,
-    content: String,
+/// A draft/multiline batch being received.
+struct Multiline {
+    from: UserInfo,
+    target: String,
+    lines: Vec<String>,
 }
 
-#[derive(Debug)]
-pub struct Channel {
-    name: String,
-    topic: String,
-    user_list: Vec<UserInfo>,
+/// Where the last `/connect` went, so the client can reconnect there.
+struct Target {
+    host: String,
+    port: u16,
+    tls: Option<TlsConfig>,
 }
 
 pub struct Client {
-    stream: Option<TcpStream>,
-    buffer: RingBuffer<u8>,
+    stream: Option<Connection>,
+    /// Event loop the socket is registered with, woken up when it can be read or written.
+    reactor: Reactor,
+    queue: SendQueue,
+    lag: Lag,
+    ctcp: CtcpReplies,
+    /// DCC chats and file transfers, which outlive the server connection.
+    dcc: DccSessions,
+    target: Option<Target>,
+    reconnect: Reconnect,
+    /// Channels and their keys to join again once the reconnect registers.
+    rejoin: Vec<(String, Option<String>)>,
+    /// Channels and their keys to join on every new connection.
+    autojoin: Vec<(String, Option<String>)>,
+    /// Raw lines sent every time we register, before any channel is joined.
+    on_connect: Vec<String>,
+    /// Keys of channels we asked to join, until the server confirms the JOIN.
+    pending_keys: HashMap<Identifier, String>,
+    nicknames: Nicknames,
+    /// True between RPL_WELCOME and the end of the connection.
+    registered: bool,
+    buffer: LineFramer,
+    /// True once the connect and the TLS handshake are done, until the connection ends.
     connected: bool,
     user_info: UserInfo,
-    return_lines: Vec<String>,
+    return_lines: Vec<ClientEvent>,
+    /// Our `user@host` as the server shows it to others, once seen in one of our own messages.
+    own_mask: Option<String>,
+    /// Incoming draft/multiline batches by reference, until the server closes them.
+    multiline: HashMap<String, Multiline>,
+    /// Numbers the references of outgoing batches.
+    next_batch: u32,
+    channels: HashMap<Identifier, Channel>,
+    /// Nicks whose messages and notices are dropped.
+    ignored: HashSet<Identifier>,
+    isupport: ISupport,
+    capabilities: Capabilities,
+    sasl: Sasl,
 }
 
 macro_rules! chat_msg {
     ($chat:expr, $($arg:tt)*) => {
-        $chat.push(format!($($arg)*));
+        $chat.push(ClientEvent::Info(format!($($arg)*)));
     }
 }
 
 macro_rules! parse_error {
     ($chat:expr, $($arg:tt)*) => {
-        $chat.push(format!($($arg)*));
+        $chat.push(ClientEvent::Info(format!($($arg)*)));
     }
 }
 
@@ -96,133 +223,894 @@ impl Client {
     pub fn new(user_info: UserInfo) -> Self {
         Client {
             stream: None,
-            buffer: RingBuffer::new(1024 * 8), //8kb
+            reactor: Reactor::default(),
+            queue: SendQueue::new(FloodPolicy::default()),
+            lag: Lag::new(LagPolicy::default()),
+            ctcp: CtcpReplies::new(CtcpPolicy::default()),
+            dcc: DccSessions::new(DccPolicy::default()),
+            target: None,
+            reconnect: Reconnect::new(ReconnectPolicy::default()),
+            rejoin: Vec::new(),
+            autojoin: Vec::new(),
+            on_connect: Vec::new(),
+            pending_keys: HashMap::new(),
+            nicknames: Nicknames::new(vec![user_info.nick.clone()]),
+            registered: false,
+            buffer: LineFramer::new(MAX_INCOMING_LINE_LEN),
             connected: false,
             user_info,
             return_lines: Vec::new(),
+            own_mask: None,
+            multiline: HashMap::new(),
+            next_batch: 0,
+            channels: HashMap::new(),
+            ignored: HashSet::new(),
+            isupport: ISupport::default(),
+            capabilities: Capabilities::new(DEFAULT_CAPABILITIES.iter().map(|cap| cap.to_string()).collect()),
+            sasl: Sasl::new(),
+        }
+    }
+
+    /// Capabilities to request from servers instead of the default set.
+    pub fn set_capabilities(&mut self, wanted: Vec<String>) {
+        self.capabilities.set_wanted(wanted);
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Nicks to register with, the primary one first and then the alternatives.
+    pub fn set_nicknames(&mut self, nicknames: Vec<String>) {
+        self.nicknames.set_configured(nicknames);
+    }
+
+    /// Who we are on the next connection, the nick being replaced by the first of [`Client::set_nicknames`].
+    pub fn set_user_info(&mut self, user_info: UserInfo) {
+        self.user_info = user_info;
+    }
+
+    /// Channels to join after connecting, reconnects joining whatever we were on instead.
+    pub fn set_autojoin(&mut self, channels: Vec<(String, Option<String>)>) {
+        self.autojoin = channels;
+    }
+
+    /// Raw IRC lines, like `PRIVMSG NickServ :IDENTIFY secret`, sent as soon as we register.
+    pub fn set_on_connect(&mut self, lines: Vec<String>) {
+        self.on_connect = lines;
+    }
+
+    /// Registers every connection this client opens with an event loop, under `token`.
+    pub fn set_reactor(&mut self, registry: Registry, token: Token) {
+        if let Ok(registry) = registry.try_clone() {
+            self.dcc.set_reactor(registry, token);
         }
+        self.reactor = Reactor::new(registry, token);
+    }
+
+    pub fn set_flood_policy(&mut self, policy: FloodPolicy) {
+        self.queue.set_policy(policy);
+    }
+
+    /// Which CTCP requests are answered and how often.
+    pub fn set_ctcp_policy(&mut self, policy: CtcpPolicy) {
+        self.ctcp.set_policy(policy);
+    }
+
+    /// Where downloads go and how DCC offers are made.
+    pub fn set_dcc_policy(&mut self, policy: DccPolicy) {
+        self.dcc.set_policy(policy);
+    }
+
+    pub fn set_lag_policy(&mut self, policy: LagPolicy) {
+        self.lag.set_policy(policy);
+    }
+
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.reconnect.set_policy(policy);
+    }
+
+    /// Credentials used to authenticate on the next connection, if any.
+    pub fn set_sasl(&mut self, credentials: Option<SaslCredentials>) {
+        self.sasl.set_credentials(credentials);
     }
 
     fn identify(&mut self) {
-        self.send_string(self.user_info.get_nick_msg());
-        self.send_string(self.user_info.get_user_msg());
+        self.sasl.reset();
+        self.user_info.nick = self.nicknames.first();
+        let extra = if self.sasl.is_configured() { vec!["sasl".to_string()] } else { Vec::new() };
+        let cap_ls = self.capabilities.start(extra);
+        self.send(cap_ls);
+        self.send(self.user_info.get_nick_msg());
+        self.send(self.user_info.get_user_msg());
     }
 
-    pub fn join(&mut self, channel: &str) {
-        self.send_string(format!("JOIN #{channel}"));
+    pub fn join(&mut self, channel: &str, key: Option<&str>) {
+        let channel = if self.is_channel(channel) {
+            channel.to_string()
+        } else {
+            format!("{}{channel}", self.isupport.default_chantype())
+        };
+        match key {
+            Some(key) => {
+                self.pending_keys.insert(self.id(&channel), key.to_string());
+                self.send(IrcMessage::new("JOIN", vec![channel, key.to_string()]));
+            }
+            None => self.send(IrcMessage::new("JOIN", vec![channel])),
+        }
+    }
+
+    /// Joins the channels we were on before the connection dropped, keyed ones first as JOIN
+    /// pairs keys with channels by position. Runs after the MOTD so TARGMAX is known.
+    fn rejoin_channels(&mut self) {
+        let mut channels = std::mem::take(&mut self.rejoin);
+        channels.sort_by_key(|(_, key)| key.is_none());
+        for (channel, key) in &channels {
+            if let Some(key) = key {
+                self.pending_keys.insert(self.id(channel), key.clone());
+            }
+        }
+
+        let mut names: Vec<&str> = Vec::new();
+        let mut keys: Vec<&str> = Vec::new();
+        let mut len = 0;
+        let max_targets = self.isupport.max_targets("JOIN").unwrap_or(usize::MAX);
+        for (channel, key) in &channels {
+            let too_long = len + channel.len() + key.as_ref().map_or(0, |key| key.len()) + 2 > MAX_JOIN_LEN;
+            if !names.is_empty() && (too_long || names.len() >= max_targets) {
+                self.send(join_message(&names, &keys));
+                names.clear();
+                keys.clear();
+                len = 0;
+            }
+            names.push(channel);
+            len += channel.len() + 1;
+            if let Some(key) = key {
+                keys.push(key);
+                len += key.len() + 1;
+            }
+        }
+        if !names.is_empty() {
+            self.send(join_message(&names, &keys));
+        }
+    }
+
+    /// Asks the server for `nick`. While registering it is ours unless the server refuses it,
+    /// afterwards the server confirms it with a NICK.
+    pub fn change_nick(&mut self, nick: &str) {
+        if !self.registered {
+            self.user_info.nick = nick.to_string();
+        }
+        self.send(IrcMessage::new("NICK", vec![nick.to_string()]));
+    }
+
+    pub fn part(&mut self, channel: &str) {
+        self.send(IrcMessage::new("PART", vec![channel.to_string()]));
+    }
+
+    pub fn user_info(&self) -> &UserInfo {
+        &self.user_info
+    }
+
+    pub fn nick(&self) -> &str {
+        &self.user_info.nick
+    }
+
+    pub fn is_channel(&self, name: &str) -> bool {
+        self.isupport.is_channel(name)
+    }
+
+    /// Compares nicks the way the server does, following its CASEMAPPING.
+    pub fn same_nick(&self, a: &str, b: &str) -> bool {
+        self.isupport.casemapping().equals(a, b)
     }
 
-    pub fn connect<A: ToSocketAddrs>(&mut self, addr: A) -> Result<(), String> {
+    pub fn casemapping(&self) -> CaseMapping {
+        self.isupport.casemapping()
+    }
+
+    /// A nick or channel name as the server identifies it.
+    pub fn id(&self, name: &str) -> Identifier {
+        Identifier::new(name, self.isupport.casemapping())
+    }
+
+    /// Re-keys everything stored by name after the server announced its CASEMAPPING.
+    fn set_casemapping(&mut self, mapping: CaseMapping) {
+        self.channels = self
+            .channels
+            .drain()
+            .map(|(mut name, mut channel)| {
+                name.remap(mapping);
+                channel.set_casemapping(mapping);
+                (name, channel)
+            })
+            .collect();
+        self.pending_keys = self
+            .pending_keys
+            .drain()
+            .map(|(mut name, key)| {
+                name.remap(mapping);
+                (name, key)
+            })
+            .collect();
+        self.ignored = self
+            .ignored
+            .drain()
+            .map(|mut nick| {
+                nick.remap(mapping);
+                nick
+            })
+            .collect();
+    }
+
+    pub fn ignore(&mut self, nick: &str) -> bool {
+        self.ignored.insert(self.id(nick))
+    }
+
+    pub fn unignore(&mut self, nick: &str) -> bool {
+        self.ignored.remove(&self.id(nick))
+    }
+
+    pub fn ignored(&self) -> impl Iterator<Item = &Identifier> {
+        self.ignored.iter()
+    }
+
+    pub fn is_ignored(&self, nick: &str) -> bool {
+        self.ignored.contains(&self.id(nick))
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&Channel> {
+        self.channels.get(&self.id(name))
+    }
+
+    pub fn prefix_modes(&self) -> &PrefixModes {
+        self.isupport.prefix_modes()
+    }
+
+    /// Names of the channels we share with `nick`.
+    fn channels_with(&self, nick: &str) -> Vec<String> {
+        self.channels
+            .values()
+            .filter(|channel| channel.member(nick).is_some())
+            .map(|channel| channel.name().to_string())
+            .collect()
+    }
+
+    pub fn connect(&mut self, host: &str, port: u16, tls: Option<&TlsConfig>) -> Result<(), String> {
         if self.stream.is_some() {
             return Err("Already connected".to_string());
-        } else {
-            match TcpStream::connect(addr) {
-                Ok(stream) => match stream.set_nonblocking(true) {
-                    Ok(_) => {
-                        self.connected = true;
-                        self.stream = Some(stream);
-                        self.identify();
-                    }
-                    Err(e) => {
-                        return Err(e.to_string());
-                    }
-                },
-                Err(e) => {
-                    return Err(e.to_string());
+        }
+        self.reconnect.cancel();
+        // Joined with the same batching as a rejoin, once the MOTD is over
+        self.rejoin = self.autojoin.clone();
+        self.target = Some(Target {
+            host: host.to_string(),
+            port,
+            tls: tls.cloned(),
+        });
+        self.open()
+    }
+
+    fn open(&mut self) -> Result<(), String> {
+        let Some(target) = &self.target else {
+            return Err("No server to connect to".to_string());
+        };
+        self.stream = Some(Connection::open(&target.host, target.port, target.tls.as_ref(), &self.reactor)?);
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Finishes opening the connection as its socket becomes ready, registering once it can be used.
+    fn finish_open(&mut self) {
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+        match stream.ready(&self.reactor) {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(reason) => {
+                self.close();
+                if self.reconnect.attempt() > 0 {
+                    chat_msg!(self.return_lines, "Reconnect failed: {reason}");
+                    self.schedule_reconnect();
+                } else {
+                    self.return_lines.push(ClientEvent::ConnectFailed { reason });
                 }
+                return;
             }
         }
-        Ok(())
+        if let (Some(fingerprint), Some(target)) = (stream.peer_fingerprint(), &self.target) {
+            chat_msg!(
+                self.return_lines,
+                "TLS connection to {}:{}, certificate SHA-256 {}",
+                target.host,
+                target.port,
+                format_fingerprint(&fingerprint)
+            );
+        }
+        self.connected = true;
+        self.lag.reset(Instant::now());
+        self.identify();
     }
 
-    pub fn is_connected(&self) -> bool {
-        match &self.stream {
-            None => false,
-            Some(stream) => {
-                let mut buff = [0u8];
-                match stream.peek(&mut buff) {
-                    Ok(n) => n > 0,
-                    Err(e) => e.kind() == ErrorKind::WouldBlock,
+    pub fn disconnect(&mut self, reason: &str) {
+        if self.stream.is_some() {
+            self.send(IrcMessage::new("QUIT", vec![reason.to_string()]));
+        }
+        self.reconnect.cancel();
+        self.rejoin.clear();
+        self.close();
+    }
+
+    fn close(&mut self) {
+        if let Some(stream) = &self.stream {
+            self.reactor.deregister(stream);
+        }
+        self.stream = None;
+        self.queue.clear();
+        self.connected = false;
+        self.registered = false;
+        self.isupport = ISupport::default();
+        self.channels.clear();
+        self.pending_keys.clear();
+        self.own_mask = None;
+        self.multiline.clear();
+        self.capabilities.abort();
+        self.sasl.reset();
+    }
+
+    /// The connection dropped without us asking for it, try to get back.
+    fn connection_lost(&mut self, reason: String) {
+        if self.stream.is_none() {
+            return;
+        }
+        if !self.channels.is_empty() {
+            self.rejoin = self
+                .channels
+                .values()
+                .map(|channel| (channel.name().to_string(), channel.key().map(|key| key.to_string())))
+                .collect();
+        }
+        self.close();
+        self.return_lines.push(ClientEvent::Disconnected { reason });
+        self.schedule_reconnect();
+    }
+
+    fn schedule_reconnect(&mut self) {
+        match self.reconnect.schedule() {
+            Some(delay) => {
+                chat_msg!(
+                    self.return_lines,
+                    "Reconnecting in {:.1}s (attempt {}/{})",
+                    delay.as_secs_f32(),
+                    self.reconnect.attempt(),
+                    self.reconnect.max_attempts()
+                );
+            }
+            None if self.reconnect.max_attempts() > 0 => {
+                chat_msg!(self.return_lines, "Giving up after {} reconnect attempts", self.reconnect.max_attempts());
+            }
+            None => {}
+        }
+    }
+
+    fn try_reconnect(&mut self) {
+        if let Some(target) = &self.target {
+            chat_msg!(
+                self.return_lines,
+                "Reconnecting to {}:{} (attempt {}/{})",
+                target.host,
+                target.port,
+                self.reconnect.attempt(),
+                self.reconnect.max_attempts()
+            );
+        }
+        if let Err(e) = self.open() {
+            chat_msg!(self.return_lines, "Reconnect failed: {e}");
+            self.schedule_reconnect();
+        }
+    }
+
+    /// Address of the server we are connected or reconnecting to.
+    pub fn server_address(&self) -> Option<(&str, u16)> {
+        self.target.as_ref().map(|target| (target.host.as_str(), target.port))
+    }
+
+    /// RPL_WELCOME: the connection is usable, restore what the previous one had.
+    fn registered(&mut self, nick: String) {
+        self.registered = true;
+        self.user_info.nick = nick.clone();
+        self.return_lines.push(ClientEvent::Registered { nick });
+        for line in self.on_connect.clone() {
+            match IrcMessage::parse(&line) {
+                // These lines often carry passwords, like `PRIVMSG NickServ :IDENTIFY secret`
+                Ok(message) => self.send_hidden(message, true),
+                Err(e) => {
+                    parse_error!(self.return_lines, "Invalid on-connect command: {e}");
                 }
             }
         }
+        if self.reconnect.attempt() > 0 {
+            self.reconnect.reset();
+            self.return_lines.push(ClientEvent::Reconnected);
+        }
     }
 
-    pub fn poll(&mut self) -> Vec<String> {
-        let mut new_data = false;
-        if let Some(ref mut stream) = self.stream {
-            match stream.read_vectored(self.buffer.slices().deref_mut()) {
-                Ok(n) => {
-                    if n > 0 {
-                        self.buffer.wrote(n);
-                        new_data = true;
-                    }
+    /// When [`Client::poll`] has timed work to do: a reconnect attempt, a connect to give up on,
+    /// a line held back by the flood limit, a lag check, DCC progress to report or a DCC connect
+    /// to give up on.
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        let reconnect = self.reconnect.due_at().filter(|_| self.stream.is_none());
+        let connect = self.stream.as_ref().and_then(Connection::deadline);
+        let send = self.stream.as_ref().filter(|_| self.connected).and(self.queue.next_send_at());
+        let lag = Some(self.lag.next_check(Instant::now())).filter(|_| self.registered);
+        reconnect.into_iter().chain(connect).chain(send).chain(lag).chain(self.dcc.next_wakeup()).min()
+    }
+
+    /// Lines waiting for the flood limit or the socket.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// True from the start of a connect until the connection ends, a dead one is noticed by the
+    /// lag check and closed.
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Round trip to the server, once measured.
+    pub fn lag(&self) -> Option<Duration> {
+        self.stream.as_ref().and(self.lag.lag(Instant::now()))
+    }
+
+    pub fn poll(&mut self) -> Vec<ClientEvent> {
+        if self.stream.is_none() && self.reconnect.take_due() {
+            self.try_reconnect();
+        }
+        if !self.connected {
+            self.finish_open();
+        }
+
+        let mut lost = None;
+        // Readiness is only reported when it changes, so read until the socket has nothing left
+        while let Some(ref mut stream) = self.stream.as_mut().filter(|_| self.connected) {
+            match self.buffer.read_from(stream) {
+                Ok(0) => {
+                    lost = Some("Connection closed by the server".to_string());
+                    break;
                 }
+                Ok(_) => {
+                    self.lag.received(Instant::now());
+                    self.try_read_server_data();
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
                 Err(e) => {
-                    if e.kind() != ErrorKind::WouldBlock {
-                        parse_error!(self.return_lines, "{}", e);
-                        self.connected = false;
-                    }
+                    lost = Some(e.to_string());
+                    break;
                 }
             }
         }
 
-        if new_data {
-            self.try_read_server_data();
+        if lost.is_none() && self.registered {
+            match self.lag.check(Instant::now()) {
+                LagCheck::Fine => {}
+                // Sent quietly, a PING every half minute would only clutter the server buffer
+                LagCheck::Ping(token) => self.send_bytes(IrcMessage::new("PING", vec![token]).to_string().as_bytes(), Priority::Urgent),
+                LagCheck::Dead => lost = Some("The server stopped answering".to_string()),
+            }
+        }
+        if lost.is_none() {
+            lost = self.flush().err().map(|e| e.to_string());
+        }
+        if let Some(reason) = lost {
+            self.connection_lost(reason);
         }
 
+        self.return_lines.extend(self.dcc.poll());
         std::mem::take(&mut self.return_lines)
     }
 
     fn try_read_server_data(&mut self) {
-        while let Some(up_to) = self.buffer.find_first(&[13u8, 10u8]) {
-            if let Some(message_bytes) = self.buffer.consume(up_to) {
-                self.buffer.discard(2); // Discard the line break
-                self.try_parse_server_data(String::from_utf8_lossy(&message_bytes).to_string());
+        while let Some(line) = self.buffer.next_line() {
+            match line {
+                Ok(bytes) => self.try_parse_server_data(String::from_utf8_lossy(&bytes).to_string()),
+                Err(e) => {
+                    parse_error!(self.return_lines, "<<< {e}");
+                }
             }
         }
     }
 
-    fn try_parse_server_data(&mut self, message: String) {
-        if self.process_ping(&message) {
+    fn try_parse_server_data(&mut self, line: String) {
+        let message = match IrcMessage::parse(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                parse_error!(self.return_lines, "<<< {line} ({e})");
+                return;
+            }
+        };
+
+        if self.process_pong(&message) || self.process_ping(&message) || self.process_cap(&message) || self.process_authenticate(&message) {
             return;
         }
-        if let Some((source, rest)) = message.strip_prefix(':').and_then(|m| m.split_once(' ')) {
-            if let Some((msg_type, rest)) = rest.split_once(' ') {
-                if msg_type.len() == 3 {
-                    if let Some((dest, content)) = rest.split_once(' ') {
-                        if self.try_parse_server_reply(source, Replies::from_str(msg_type), dest, content) {
-                            return;
-                        }
-                    }
-                } else {
-                    if Self::try_parse_server_message(source, msg_type, rest) {
-                        return;
-                    }
-                }
+        if message.is_numeric() {
+            if self.try_parse_server_reply(Replies::from_str(&message.command), &message) {
+                return;
             }
+        } else if self.try_parse_server_message(&message) {
+            return;
         }
 
-        parse_error!(self.return_lines, "<<< {message}");
+        parse_error!(self.return_lines, "<<< {line}");
     }
 
-    fn process_ping(&mut self, message: &str) -> bool {
-        if let Some(part) = message.strip_prefix("PING ") {
-            if let Some(host) = part.split_whitespace().next() {
-                chat_msg!(self.return_lines, "<<< {}", message);
-                self.send_string(format!("PONG {}", host));
-                return true;
-            }
+    fn process_ping(&mut self, message: &IrcMessage) -> bool {
+        if message.command == "PING" {
+            chat_msg!(self.return_lines, "<<< {}", message);
+            self.send(IrcMessage::new("PONG", message.params.clone()));
+            return true;
         }
         false
     }
 
-    fn try_parse_server_message(_source: &str, _msg_type: &str, _rest: &str) -> bool {
-        false
+    /// Answers to our lag checks are not shown.
+    fn process_pong(&mut self, message: &IrcMessage) -> bool {
+        message.command == "PONG" && message.params.last().is_some_and(|token| self.lag.pong(token, Instant::now()))
     }
 
-    fn try_parse_server_reply(&mut self, _source: &str, msg_type: Replies, _dest: &str, content: &str) -> bool {
-        match msg_type {
+    fn process_cap(&mut self, message: &IrcMessage) -> bool {
+        if message.command != "CAP" {
+            return false;
+        }
+
+        let change = self.capabilities.handle(message);
+        let (what, caps) = match &change {
+            Some(CapChange::Available(caps)) => ("available", caps.clone()),
+            Some(CapChange::Enabled(caps)) => ("enabled", caps.clone()),
+            Some(CapChange::Rejected(caps)) => ("rejected", caps.clone()),
+            Some(CapChange::Removed(caps)) => ("removed", caps.clone()),
+            Some(CapChange::Listed(caps)) => ("in use", caps.clone()),
+            None => ("", Vec::new()),
+        };
+        if !caps.is_empty() {
+            chat_msg!(self.return_lines, "Capabilities {what}: {}", caps.join(" "));
+        }
+
+        let negotiating = self.capabilities.is_negotiating();
+        match change {
+            Some(CapChange::Available(_)) if negotiating && self.sasl.is_configured() && self.capabilities.value("sasl").is_none() => {
+                self.sasl_failed("Server does not support SASL");
+            }
+            Some(CapChange::Enabled(caps)) if caps.iter().any(|cap| cap == "sasl") && self.sasl.is_configured() => {
+                match self.sasl.start(self.capabilities.value("sasl").unwrap_or_default()) {
+                    Ok(authenticate) => self.send(authenticate),
+                    Err(e) => self.sasl_failed(&e),
+                }
+            }
+            Some(CapChange::Rejected(caps)) if caps.iter().any(|cap| cap == "sasl") && self.sasl.is_configured() => {
+                self.sasl_failed("Server rejected the SASL capability");
+            }
+            _ => {}
+        }
+
+        for request in self.capabilities.take_request() {
+            self.send(request);
+        }
+        self.try_end_negotiation();
+        true
+    }
+
+    fn try_end_negotiation(&mut self) {
+        if self.capabilities.can_end() && !self.sasl.in_progress() && self.stream.is_some() {
+            let end = self.capabilities.end();
+            self.send(end);
+        }
+    }
+
+    fn process_authenticate(&mut self, message: &IrcMessage) -> bool {
+        if message.command != "AUTHENTICATE" {
+            return false;
+        }
+        for response in self.sasl.respond(message.param(0).unwrap_or_default()) {
+            self.send(response);
+        }
+        true
+    }
+
+    fn sasl_failed(&mut self, reason: &str) {
+        self.sasl.finish();
+        if self.sasl.abort_on_failure() {
+            chat_msg!(self.return_lines, "SASL authentication failed: {reason}. Disconnecting.");
+            self.disconnect("SASL authentication failed");
+        } else {
+            chat_msg!(self.return_lines, "SASL authentication failed: {reason}. Continuing without it.");
+            self.try_end_negotiation();
+        }
+    }
+
+    fn try_parse_server_message(&mut self, message: &IrcMessage) -> bool {
+        let Some(prefix) = &message.prefix else {
+            return false;
+        };
+        let source = UserInfo::from_prefix(prefix);
+        let param = |index: usize| message.param(index).unwrap_or_default().to_string();
+
+        let own = self.same_nick(&source.nick, &self.user_info.nick);
+        if own {
+            if let (Some(user), Some(host)) = (&prefix.user, &prefix.host) {
+                self.own_mask = Some(format!("{user}@{host}"));
+            }
+        }
+
+        if let Some(batch) = message.tag("batch").and_then(|reference| self.multiline.get_mut(reference)) {
+            if message.command == "PRIVMSG" {
+                let text = param(1);
+                match batch.lines.last_mut() {
+                    Some(last) if message.tag("draft/multiline-concat").is_some() => last.push_str(&text),
+                    _ => batch.lines.push(text),
+                }
+            }
+            return true;
+        }
+
+        let event = match message.command.as_str() {
+            "BATCH" if !message.params.is_empty() => {
+                let reference = param(0);
+                if let Some(reference) = reference.strip_prefix('+') {
+                    if message.param(1) == Some("draft/multiline") && message.params.len() >= 3 {
+                        let target = self.isupport.strip_statusmsg(&message.params[2]).to_string();
+                        self.multiline.insert(
+                            reference.to_string(),
+                            Multiline {
+                                from: source,
+                                target,
+                                lines: Vec::new(),
+                            },
+                        );
+                    }
+                } else if let Some(batch) = self.multiline.remove(reference.trim_start_matches('-')) {
+                    if !self.is_ignored(&batch.from.nick) {
+                        for text in batch.lines {
+                            self.return_lines.push(ClientEvent::Message {
+                                from: batch.from.clone(),
+                                target: batch.target.clone(),
+                                text,
+                            });
+                        }
+                    }
+                }
+                // Other batch types only group messages that make sense on their own
+                return true;
+            }
+            "PRIVMSG" | "NOTICE" if self.is_ignored(&source.nick) => return true,
+            "PRIVMSG" if message.params.len() >= 2 => {
+                let target = self.isupport.strip_statusmsg(&message.params[0]).to_string();
+                if let Some(channel) = self.channels.get_mut(&self.id(&target)) {
+                    channel.spoke(&source.nick);
+                }
+                match ctcp::decode(&message.params[1]) {
+                    Some((command, text)) if command == "ACTION" => ClientEvent::Action { from: source, target, text },
+                    Some((command, params)) if command == "DCC" => match DccRequest::parse(&params) {
+                        Some(request) => {
+                            let local = self.local_address();
+                            if let Some(reply) = self.dcc.request(&source.nick, request, local) {
+                                self.send_dcc(&source.nick, reply);
+                            }
+                            return true;
+                        }
+                        None => ClientEvent::Ctcp { from: source, command, params },
+                    },
+                    Some((command, params)) => {
+                        if let Some(reply) = self.ctcp.reply(&command, &params, Instant::now()) {
+                            let reply = IrcMessage::new("NOTICE", vec![source.nick.clone(), reply]).to_string();
+                            self.send_bytes(reply.as_bytes(), Priority::Normal);
+                        }
+                        ClientEvent::Ctcp { from: source, command, params }
+                    }
+                    None => ClientEvent::Message {
+                        from: source,
+                        target,
+                        text: param(1),
+                    },
+                }
+            }
+            "NOTICE" if message.params.len() >= 2 => match ctcp::decode(&message.params[1]) {
+                Some((command, params)) => {
+                    let params = if command == "PING" {
+                        ping_round_trip(&params).unwrap_or(params)
+                    } else {
+                        params
+                    };
+                    ClientEvent::CtcpReply { from: source, command, params }
+                }
+                None => ClientEvent::Notice {
+                    from: source,
+                    target: self.isupport.strip_statusmsg(&message.params[0]).to_string(),
+                    text: param(1),
+                },
+            },
+            "JOIN" if !message.params.is_empty() => {
+                let channel = param(0);
+                let mut source = source;
+                if message.params.len() >= 3 {
+                    // extended-join adds the account name and the real name
+                    source.name = param(2);
+                }
+                if own {
+                    let mut state = Channel::new(channel.clone(), self.isupport.casemapping());
+                    if let Some(key) = self.pending_keys.remove(&self.id(&channel)) {
+                        state.apply_modes("+k", &[key], self.isupport.prefix_modes(), self.isupport.channel_modes());
+                    }
+                    self.channels.insert(self.id(&channel), state);
+                    self.send(IrcMessage::new("MODE", vec![channel.clone()]));
+                }
+                if let Some(state) = self.channels.get_mut(&self.id(&channel)) {
+                    state.add_member(source.clone(), String::new());
+                }
+                ClientEvent::Join { user: source, channel }
+            }
+            "PART" if !message.params.is_empty() => {
+                let channel = param(0);
+                if own {
+                    self.channels.remove(&self.id(&channel));
+                } else if let Some(state) = self.channels.get_mut(&self.id(&channel)) {
+                    state.remove_member(&source.nick);
+                }
+                ClientEvent::Part {
+                    user: source,
+                    channel,
+                    reason: param(1),
+                }
+            }
+            "QUIT" => {
+                let channels = self.channels_with(&source.nick);
+                self.channels.values_mut().for_each(|channel| {
+                    channel.remove_member(&source.nick);
+                });
+                if self.same_nick(&source.nick, self.nicknames.primary()) {
+                    self.regain_nick();
+                }
+                ClientEvent::Quit {
+                    user: source,
+                    reason: param(0),
+                    channels,
+                }
+            }
+            "KICK" if message.params.len() >= 2 => {
+                let channel = param(0);
+                let kicked = param(1);
+                if self.same_nick(&kicked, &self.user_info.nick) {
+                    self.channels.remove(&self.id(&channel));
+                } else if let Some(state) = self.channels.get_mut(&self.id(&channel)) {
+                    state.remove_member(&kicked);
+                }
+                ClientEvent::Kick {
+                    user: UserInfo::from_nick(kicked),
+                    channel,
+                    kicked_by: source,
+                    reason: param(2),
+                }
+            }
+            "NICK" if !message.params.is_empty() => {
+                let new_nick = param(0);
+                if own {
+                    self.user_info.nick = new_nick.clone();
+                    if self.isupport.monitor() && self.same_nick(&new_nick, self.nicknames.primary()) {
+                        self.send(IrcMessage::new("MONITOR", vec!["-".into(), new_nick.clone()]));
+                    }
+                } else if self.same_nick(&source.nick, self.nicknames.primary()) {
+                    self.regain_nick();
+                }
+                let channels = self.channels_with(&source.nick);
+                self.channels.values_mut().for_each(|channel| {
+                    channel.rename_member(&source.nick, &new_nick);
+                });
+                ClientEvent::Nick {
+                    user: source,
+                    new_nick,
+                    channels,
+                }
+            }
+            "MODE" if message.params.len() >= 2 => {
+                let target = param(0);
+                if let Some(state) = self.channels.get_mut(&self.id(&target)) {
+                    state.apply_modes(
+                        &message.params[1],
+                        &message.params[2..],
+                        self.isupport.prefix_modes(),
+                        self.isupport.channel_modes(),
+                    );
+                }
+                ClientEvent::Mode {
+                    target,
+                    mode: message.params[1..].join(" "),
+                    changed_by: source,
+                }
+            }
+            "TOPIC" if message.params.len() >= 2 => {
+                let channel = param(0);
+                if let Some(state) = self.channels.get_mut(&self.id(&channel)) {
+                    state.set_topic(param(1));
+                }
+                ClientEvent::Topic {
+                    channel,
+                    topic: param(1),
+                    set_by: Some(source),
+                }
+            }
+            _ => return false,
+        };
+
+        self.return_lines.push(event);
+        true
+    }
+
+    fn try_parse_server_reply(&mut self, msg_type: Replies, message: &IrcMessage) -> bool {
+        let welcome = matches!(msg_type, Replies::RPL_WELCOME);
+        let handled = match msg_type {
+            Replies::ERR_ERRONEUSNICKNAME | Replies::ERR_NICKNAMEINUSE | Replies::ERR_NICKCOLLISION | Replies::ERR_UNAVAILRESOURCE
+                if !self.registered && message.params.len() >= 2 =>
+            {
+                self.nick_rejected(&message.params[1], message.trailing().unwrap_or_default());
+                true
+            }
+            Replies::RPL_ENDOFMOTD | Replies::ERR_NOMOTD => {
+                chat_msg!(self.return_lines, "{}", message.trailing().unwrap_or_default());
+                self.watch_primary_nick();
+                if !self.rejoin.is_empty() {
+                    self.rejoin_channels();
+                }
+                true
+            }
+            Replies::RPL_ISUPPORT if message.params.len() > 2 => {
+                let tokens = &message.params[1..message.params.len() - 1];
+                let network = self.isupport.network().map(|network| network.to_string());
+                let mapping = self.isupport.casemapping();
+                self.isupport.apply(tokens);
+                if self.isupport.casemapping() != mapping {
+                    self.set_casemapping(self.isupport.casemapping());
+                }
+                chat_msg!(self.return_lines, "Server features: {}", tokens.join(" "));
+                if let Some(name) = self.isupport.network().filter(|name| network.as_deref() != Some(*name)) {
+                    self.return_lines.push(ClientEvent::Network { name: name.to_string() });
+                }
+                true
+            }
+            Replies::RPL_MONOFFLINE => {
+                let primary = self.nicknames.primary();
+                if message.trailing().unwrap_or_default().split(',').any(|nick| self.same_nick(nick, primary)) {
+                    self.regain_nick();
+                }
+                true
+            }
+            Replies::RPL_MONONLINE | Replies::RPL_MONLIST | Replies::RPL_ENDOFMONLIST => true,
+            Replies::RPL_WELCOME if self.capabilities.is_negotiating() => {
+                // The server registered us without waiting for CAP END
+                self.capabilities.abort();
+                chat_msg!(self.return_lines, "{}", message.trailing().unwrap_or_default());
+                true
+            }
+            Replies::ERR_UNKNOWNCOMMAND if message.param(1) == Some("CAP") => {
+                self.capabilities.abort();
+                chat_msg!(self.return_lines, "Server does not support capability negotiation");
+                true
+            }
+            Replies::RPL_LOGGEDIN | Replies::RPL_LOGGEDOUT | Replies::RPL_SASLMECHS => {
+                chat_msg!(self.return_lines, "{}", message.trailing().unwrap_or_default());
+                true
+            }
+            Replies::RPL_SASLSUCCESS | Replies::ERR_SASLALREADY => {
+                chat_msg!(self.return_lines, "{}", message.trailing().unwrap_or_default());
+                self.sasl.finish();
+                self.try_end_negotiation();
+                true
+            }
+            Replies::ERR_NICKLOCKED | Replies::ERR_SASLFAIL | Replies::ERR_SASLTOOLONG | Replies::ERR_SASLABORTED => {
+                let reason = message.trailing().unwrap_or_default().to_string();
+                self.sasl_failed(&reason);
+                true
+            }
             Replies::RPL_WELCOME
             | Replies::RPL_YOURHOST
             | Replies::RPL_CREATED
@@ -231,34 +1119,448 @@ impl Client {
             | Replies::RPL_LUSERCLIENT
             | Replies::RPL_LUSERME
             | Replies::RPL_MOTDSTART
-            | Replies::RPL_MOTD
-            | Replies::RPL_ENDOFMOTD => {
-                chat_msg!(self.return_lines, "{}", content.trim_start_matches([':', ' ']));
+            | Replies::RPL_MOTD => {
+                chat_msg!(self.return_lines, "{}", message.trailing().unwrap_or_default());
+                true
+            }
+            Replies::RPL_NAMREPLY if message.params.len() >= 4 => {
+                if let Some(channel) = self.channels.get_mut(&self.id(&message.params[2])) {
+                    channel.add_names(&message.params[3], self.isupport.prefix_modes());
+                    return true;
+                }
+                false
+            }
+            Replies::RPL_ENDOFNAMES if message.params.len() >= 2 => {
+                let name = &message.params[1];
+                if let Some(channel) = self.channels.get_mut(&self.id(name)) {
+                    channel.end_of_names();
+                    let text = format!("{name}: {} nicks", channel.len());
+                    self.return_lines.push(ClientEvent::ChannelInfo { channel: name.clone(), text });
+                    return true;
+                }
+                false
+            }
+            Replies::RPL_TOPIC | Replies::RPL_NOTOPIC if message.params.len() >= 2 => {
+                let channel = message.params[1].clone();
+                let topic = if matches!(msg_type, Replies::RPL_TOPIC) {
+                    message.trailing().unwrap_or_default()
+                } else {
+                    ""
+                };
+                if let Some(state) = self.channels.get_mut(&self.id(&channel)) {
+                    state.set_topic(topic.to_string());
+                }
+                self.return_lines.push(ClientEvent::Topic {
+                    channel,
+                    topic: topic.to_string(),
+                    set_by: None,
+                });
                 true
             }
+            Replies::RPL_CHANNELMODEIS if message.params.len() >= 3 => {
+                let name = &message.params[1];
+                if let Some(channel) = self.channels.get_mut(&self.id(name)) {
+                    channel.set_modes(
+                        &message.params[2],
+                        &message.params[3..],
+                        self.isupport.prefix_modes(),
+                        self.isupport.channel_modes(),
+                    );
+                    let text = format!("{name}: modes {}", channel.mode_string());
+                    self.return_lines.push(ClientEvent::ChannelInfo { channel: name.clone(), text });
+                    return true;
+                }
+                false
+            }
             _ => false,
+        };
+        if welcome {
+            self.registered(message.param(0).unwrap_or(&self.user_info.nick).to_string());
         }
+        handled
     }
 
-    fn send_string(&mut self, command: String) {
-        chat_msg!(self.return_lines, ">>> {command}");
-        self.send_bytes(command.as_bytes());
+    /// 432/433/436/437 during registration: move on to the next nick.
+    fn nick_rejected(&mut self, nick: &str, reason: &str) {
+        match self.nicknames.next(self.isupport.nicklen()) {
+            Some(next) => {
+                chat_msg!(self.return_lines, "{nick}: {reason}, trying {next}");
+                self.user_info.nick = next;
+                self.send(self.user_info.get_nick_msg());
+            }
+            None => {
+                chat_msg!(self.return_lines, "{nick}: {reason}, no more nicknames to try, pick another one with /nick");
+            }
+        }
     }
 
-    #[inline]
-    fn send_bytes(&mut self, command: &[u8]) {
-        match self.stream {
-            None => {}
-            Some(ref mut stream) => {
-                let _ = stream.write_all(command);
-                let _ = stream.write_all(&[13u8, 10u8]);
+    /// Once registered with a fallback nick, watch for the primary one to become free.
+    fn watch_primary_nick(&mut self) {
+        let primary = self.nicknames.primary().to_string();
+        if primary.is_empty() || self.same_nick(&primary, &self.user_info.nick) {
+            return;
+        }
+        if self.isupport.monitor() {
+            self.send(IrcMessage::new("MONITOR", vec!["+".into(), primary]));
+        } else {
+            chat_msg!(self.return_lines, "Will take {primary} back if its owner leaves a shared channel");
+        }
+    }
+
+    fn regain_nick(&mut self) {
+        let primary = self.nicknames.primary().to_string();
+        if self.registered && !primary.is_empty() && !self.same_nick(&primary, &self.user_info.nick) {
+            self.send(IrcMessage::new("NICK", vec![primary]));
+        }
+    }
+
+    fn send(&mut self, message: IrcMessage) {
+        // Never show credentials on screen
+        let hidden = message.command == "AUTHENTICATE";
+        self.send_hidden(message, hidden);
+    }
+
+    /// Sends `message`, showing only its command when `hidden`.
+    fn send_hidden(&mut self, message: IrcMessage, hidden: bool) {
+        let line = message.to_string();
+        if hidden {
+            chat_msg!(self.return_lines, ">>> {} ****", message.command);
+        } else {
+            chat_msg!(self.return_lines, ">>> {line}");
+        }
+        // Registration is a handful of lines the server expects at once, no need to throttle it
+        let priority = if !self.registered || matches!(message.command.as_str(), "PONG" | "QUIT") {
+            Priority::Urgent
+        } else {
+            Priority::Normal
+        };
+        self.send_bytes(line.as_bytes(), priority);
+    }
+
+    fn send_bytes(&mut self, command: &[u8], priority: Priority) {
+        if self.stream.is_some() {
+            self.queue.push(command, priority);
+            // Errors show up again on the next poll, which handles the lost connection
+            let _ = self.flush();
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let Some(ref mut stream) = self.stream.as_mut().filter(|_| self.connected) else {
+            return Ok(());
+        };
+        self.queue.flush(stream, Instant::now())?;
+        match stream.flush() {
+            Err(e) if e.kind() != ErrorKind::WouldBlock => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends a PRIVMSG, cut into as many lines as needed to fit once the server adds our prefix.
+    ///
+    /// With draft/multiline the lines go out as one batch, so other clients can show them together.
+    pub fn send_message(&mut self, target: &str, text: &str) {
+        let limits = self.multiline_limits();
+        let tags_len = if limits.is_some() { BATCH_TAGS_LEN } else { 0 };
+        let lines = split::split_text(text, self.text_budget(target, tags_len));
+        match limits {
+            Some(limits) if lines.len() > 1 => {
+                for batch in split::batches(lines, &limits) {
+                    self.send_batch(target, batch);
+                }
+            }
+            _ => self.send_lines(target, lines, |text| text.to_string()),
+        }
+    }
+
+    /// Sends `/me` text as CTCP ACTIONs, one per line.
+    pub fn send_action(&mut self, target: &str, text: &str) {
+        let budget = self.text_budget(target, 0).saturating_sub(ctcp::encode("ACTION", " ").len());
+        let lines = split::split_text(text, budget);
+        self.send_lines(target, lines, |text| ctcp::encode("ACTION", text));
+    }
+
+    /// Sends a CTCP request, a PING carrying the time so the reply shows the round trip.
+    pub fn send_ctcp(&mut self, target: &str, command: &str, params: &str) {
+        let command = command.to_ascii_uppercase();
+        let params = match params {
+            "" if command == "PING" => SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis().to_string(),
+            params => params.to_string(),
+        };
+        self.send(IrcMessage::new("PRIVMSG", vec![target.to_string(), ctcp::encode(&command, &params)]));
+    }
+
+    pub fn dcc(&self) -> &DccSessions {
+        &self.dcc
+    }
+
+    /// Accepts the chat `nick` offered, or offers one.
+    pub fn dcc_chat(&mut self, nick: &str) -> Result<(), String> {
+        if self.dcc.accept_chat(nick)? {
+            return Ok(());
+        }
+        if self.stream.is_none() {
+            return Err("Not connected".into());
+        }
+        let offer = self.dcc.offer_chat(nick, self.local_address())?;
+        self.send_dcc(nick, offer);
+        Ok(())
+    }
+
+    /// Offers a file to `nick`, passively when `passive` or when the DCC settings say so.
+    pub fn dcc_send(&mut self, nick: &str, path: &Path, passive: bool) -> Result<(), String> {
+        if self.stream.is_none() {
+            return Err("Not connected".into());
+        }
+        let passive = passive || self.dcc.passive();
+        let offer = self.dcc.offer_file(nick, path, self.local_address(), passive)?;
+        self.send_dcc(nick, offer);
+        Ok(())
+    }
+
+    /// Downloads the file offered as transfer `id`.
+    pub fn dcc_get(&mut self, id: usize) -> Result<(), String> {
+        let nick = self
+            .dcc
+            .transfers()
+            .iter()
+            .find(|transfer| transfer.id() == id)
+            .map(|transfer| transfer.nick().to_string())
+            .ok_or(format!("No transfer {id}"))?;
+        if let Some(request) = self.dcc.get(id, self.local_address())? {
+            self.send_dcc(&nick, request);
+        }
+        Ok(())
+    }
+
+    /// Sends a line over the DCC chat with `nick`.
+    pub fn dcc_say(&mut self, nick: &str, text: &str, action: bool) -> Result<(), String> {
+        self.dcc.say(nick, text, action)
+    }
+
+    /// Closes a DCC chat or cancels a transfer by its number.
+    pub fn dcc_close(&mut self, id: usize) -> bool {
+        self.dcc.close(id)
+    }
+
+    pub fn dcc_close_chat(&mut self, nick: &str) -> bool {
+        self.dcc.close_chat(nick)
+    }
+
+    fn send_dcc(&mut self, nick: &str, request: DccRequest) {
+        self.send(IrcMessage::new("PRIVMSG", vec![nick.to_string(), ctcp::encode("DCC", &request.encode())]));
+    }
+
+    /// The address our server connection goes out from, what others on the network likely reach us at.
+    fn local_address(&self) -> IpAddr {
+        self.stream
+            .as_ref()
+            .and_then(|stream| stream.local_addr().ok())
+            .map_or(IpAddr::V4(Ipv4Addr::LOCALHOST), |address| address.ip())
+    }
+
+    /// Bytes left for the text of a PRIVMSG to `target`, see [`split::text_budget`].
+    fn text_budget(&self, target: &str, tags_len: usize) -> usize {
+        let line_len = self.isupport.token("LINELEN").and_then(|len| len.parse().ok()).unwrap_or(MAX_LINE_LEN);
+        split::text_budget(line_len, self.source_len(), "PRIVMSG", target, tags_len)
+    }
+
+    /// Sends every line as its own PRIVMSG, after `wrap` made the text of each.
+    fn send_lines(&mut self, target: &str, lines: Vec<Line>, wrap: impl Fn(&str) -> String) {
+        for (i, line) in lines.iter().enumerate() {
+            // The space a line was cut after is not needed without a batch to join them
+            let text = if lines.get(i + 1).is_some_and(|next| next.concat) {
+                line.text.trim_end()
+            } else {
+                &line.text
+            };
+            if !text.is_empty() {
+                let line = IrcMessage::new("PRIVMSG", vec![target.to_string(), wrap(text)]).to_string();
+                self.send_bytes(line.as_bytes(), Priority::Normal);
+            }
+        }
+    }
+
+    fn send_batch(&mut self, target: &str, lines: Vec<Line>) {
+        self.next_batch = self.next_batch.wrapping_add(1);
+        let reference = format!("{:08x}", self.next_batch);
+        let batch = |sign: &str| IrcMessage::new("BATCH", vec![format!("{sign}{reference}"), "draft/multiline".into(), target.to_string()]);
+        self.send_bytes(batch("+").to_string().as_bytes(), Priority::Normal);
+        for line in lines {
+            let mut message = IrcMessage::new("PRIVMSG", vec![target.to_string(), line.text]).with_tag("batch", &reference);
+            if line.concat {
+                message = message.with_tag("draft/multiline-concat", "");
             }
+            self.send_bytes(message.to_string().as_bytes(), Priority::Normal);
+        }
+        let end = IrcMessage::new("BATCH", vec![format!("-{reference}")]);
+        self.send_bytes(end.to_string().as_bytes(), Priority::Normal);
+    }
+
+    fn multiline_limits(&self) -> Option<MultilineLimits> {
+        if !self.capabilities.is_enabled("batch") || !self.capabilities.is_enabled("draft/multiline") {
+            return None;
         }
+        MultilineLimits::parse(self.capabilities.value("draft/multiline")?)
+    }
+
+    /// Length of the `nick!user@host` the server puts in front of our messages, assuming the
+    /// longest host and an ident-less `~user` until we have seen it.
+    fn source_len(&self) -> usize {
+        let mask_len = match &self.own_mask {
+            Some(mask) => mask.len(),
+            None => 1 + self.user_info.user.len() + 1 + MAX_HOST_LEN,
+        };
+        self.user_info.nick.len() + 1 + mask_len
+    }
+}
+
+/// Turns the time our CTCP PING carried back into the round trip, like `0.153s`.
+fn ping_round_trip(params: &str) -> Option<String> {
+    let sent = Duration::from_millis(params.parse().ok()?);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("{:.3}s", now.checked_sub(sent)?.as_secs_f32()))
+}
+
+fn join_message(channels: &[&str], keys: &[&str]) -> IrcMessage {
+    let mut params = vec![channels.join(",")];
+    if !keys.is_empty() {
+        params.push(keys.join(","));
+    }
+    IrcMessage::new("JOIN", params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> Client {
+        Client::new(UserInfo::new("me".into(), "user".into(), "Real Name".into()).unwrap())
+    }
+
+    /// Feeds a raw line to the client and describes the events it produced, leaving out the
+    /// informational ones.
+    fn feed(client: &mut Client, line: &str) -> Vec<String> {
+        client.try_parse_server_data(line.to_string());
+        std::mem::take(&mut client.return_lines)
+            .into_iter()
+            .filter_map(|event| match event {
+                ClientEvent::Message { from, target, text } => Some(format!("message {} {target} {text}", from.nick)),
+                ClientEvent::Notice { from, target, text } => Some(format!("notice {} {target} {text}", from.nick)),
+                ClientEvent::Action { from, target, text } => Some(format!("action {} {target} {text}", from.nick)),
+                ClientEvent::Join { user, channel } => Some(format!("join {} {channel}", user.nick)),
+                ClientEvent::Part { user, channel, reason } => Some(format!("part {} {channel} {reason}", user.nick)),
+                ClientEvent::Quit { user, reason, channels } => Some(format!("quit {} {} {reason}", user.nick, channels.join(","))),
+                ClientEvent::Kick {
+                    user,
+                    channel,
+                    kicked_by,
+                    reason,
+                } => Some(format!("kick {} {channel} {} {reason}", user.nick, kicked_by.nick)),
+                ClientEvent::Nick { user, new_nick, channels } => Some(format!("nick {} {new_nick} {}", user.nick, channels.join(","))),
+                ClientEvent::Mode { target, mode, changed_by } => Some(format!("mode {target} {mode} {}", changed_by.nick)),
+                ClientEvent::Info(_) => None,
+                event => Some(format!("{event:?}")),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn turns_messages_into_events() {
+        let mut client = client();
+        let table = [
+            (":bob!b@host PRIVMSG #rust :hello there", "message bob #rust hello there"),
+            (":bob!b@host PRIVMSG me :psst", "message bob me psst"),
+            (":bob!b@host PRIVMSG #rust :\x01ACTION waves\x01", "action bob #rust waves"),
+            (":bob!b@host NOTICE me :heads up", "notice bob me heads up"),
+            (
+                ":irc.example.org NOTICE * :*** Looking up your hostname",
+                "notice irc.example.org * *** Looking up your hostname",
+            ),
+            (":bob!b@host JOIN #rust", "join bob #rust"),
+            (":bob!b@host PART #rust :bye", "part bob #rust bye"),
+            (":bob!b@host QUIT :gone", "quit bob  gone"),
+            (":op!o@host KICK #rust bob :behave", "kick bob #rust op behave"),
+            (":bob!b@host NICK robert", "nick bob robert "),
+            (":op!o@host MODE #rust +o bob", "mode #rust +o bob op"),
+        ];
+        for (line, expected) in table {
+            assert_eq!(feed(&mut client, line), [expected], "{line}");
+        }
+    }
+
+    #[test]
+    fn tracks_our_own_joins_and_parts() {
+        let mut client = client();
+        assert_eq!(feed(&mut client, ":me!user@host JOIN #rust"), ["join me #rust"]);
+        assert!(client.channel("#rust").is_some());
+        assert_eq!(client.own_mask.as_deref(), Some("user@host"));
+
+        assert_eq!(feed(&mut client, ":bob!b@host JOIN #Rust"), ["join bob #Rust"]);
+        assert!(client.channel("#rust").unwrap().member("bob").is_some());
+        assert_eq!(feed(&mut client, ":bob!b@host NICK robert"), ["nick bob robert #rust"]);
+        assert!(client.channel("#rust").unwrap().member("robert").is_some());
+        assert_eq!(feed(&mut client, ":op!o@host MODE #rust +v robert"), ["mode #rust +v robert op"]);
+        assert!(client.channel("#rust").unwrap().member("robert").is_some_and(|member| member.prefixes == "+"));
+        assert_eq!(feed(&mut client, ":robert!b@host QUIT :gone"), ["quit robert #rust gone"]);
+        assert!(client.channel("#rust").unwrap().member("robert").is_none());
+
+        assert_eq!(feed(&mut client, ":me!user@host PART #rust :later"), ["part me #rust later"]);
+        assert!(client.channel("#rust").is_none());
+
+        feed(&mut client, ":me!user@host JOIN #rust");
+        assert_eq!(feed(&mut client, ":op!o@host KICK #rust me"), ["kick me #rust op "]);
+        assert!(client.channel("#rust").is_none());
+
+        assert_eq!(feed(&mut client, ":me!user@host NICK me_"), ["nick me me_ "]);
+        assert_eq!(client.nick(), "me_");
+    }
+
+    #[test]
+    fn drops_messages_from_ignored_nicks() {
+        let mut client = client();
+        client.ignore("Bob");
+        assert!(feed(&mut client, ":bob!b@host PRIVMSG #rust :hello").is_empty());
+        assert!(feed(&mut client, ":BOB!b@host NOTICE me :hello").is_empty());
+        assert!(feed(&mut client, ":bob!b@host PRIVMSG me :\x01ACTION waves\x01").is_empty());
+        // Ignoring only hides what they say
+        assert_eq!(feed(&mut client, ":bob!b@host JOIN #rust"), ["join bob #rust"]);
+
+        client.unignore("bob");
+        assert_eq!(feed(&mut client, ":bob!b@host PRIVMSG #rust :hello"), ["message bob #rust hello"]);
+    }
+
+    #[test]
+    fn asks_for_a_nick_once_every_fallback_is_refused() {
+        let mut client = client();
+        client.set_nicknames(vec!["me".into()]);
+        let gave_up = (0..100).any(|_| {
+            client.try_parse_server_data(":srv 433 * me :Nickname is already in use".into());
+            format!("{:?}", std::mem::take(&mut client.return_lines)).contains("pick another one with /nick")
+        });
+        assert!(gave_up);
+
+        client.change_nick("other");
+        assert_eq!(client.nick(), "other");
+        client.try_parse_server_data(":srv 001 other :Welcome".into());
+        client.change_nick("again");
+        // Only the server's NICK makes it ours once registered
+        assert_eq!(client.nick(), "other");
     }
 
-    pub fn send_message(&mut self, dest: String, msg: String) -> String {
-        let msg = format!("PRIVMSG #{dest} :{msg}");
-        self.send_bytes(msg.as_bytes());
-        msg
+    #[test]
+    fn hides_on_connect_lines() {
+        let mut client = client();
+        client.set_on_connect(vec!["PRIVMSG NickServ :IDENTIFY secret".into(), "bad line:secret".into()]);
+        client.try_parse_server_data(":srv 001 me :Welcome".into());
+        let shown: Vec<String> = std::mem::take(&mut client.return_lines)
+            .into_iter()
+            .filter_map(|event| match event {
+                ClientEvent::Info(text) => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert!(shown.contains(&">>> PRIVMSG ****".to_string()), "{shown:?}");
+        assert!(shown.iter().all(|text| !text.contains("secret")), "{shown:?}");
     }
 }
@@ -0,0 +1,320 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::client::message::IrcMessage;
+
+/// Capabilities requested when the configuration does not list any.
+pub const DEFAULT_CAPABILITIES: &[&str] = &[
+    "cap-notify",
+    "multi-prefix",
+    "userhost-in-names",
+    "extended-join",
+    "server-time",
+    "message-tags",
+    "batch",
+    "draft/multiline",
+];
+
+/// Keeps REQ lines well below the 512 byte limit even with a long server prefix echoed back in the ACK.
+const MAX_REQUEST_LEN: usize = 400;
+
+/// What changed after a CAP message from the server.
+#[derive(Debug, PartialEq)]
+pub enum CapChange {
+    Available(Vec<String>),
+    Enabled(Vec<String>),
+    Rejected(Vec<String>),
+    Removed(Vec<String>),
+    Listed(Vec<String>),
+}
+
+/// IRCv3 capability negotiation state.
+///
+/// The client starts it with [`Capabilities::start`] before sending NICK and USER, feeds it every
+/// CAP message and sends whatever [`Capabilities::take_request`] returns. Registration is held by
+/// the server until [`Capabilities::end`] is sent, which is allowed once [`Capabilities::can_end`].
+#[derive(Debug)]
+pub struct Capabilities {
+    wanted: Vec<String>,
+    extra: Vec<String>,
+    available: BTreeMap<String, String>,
+    enabled: BTreeSet<String>,
+    pending: BTreeSet<String>,
+    /// Refused with a NAK, not requested again until the server lists them anew.
+    rejected: BTreeSet<String>,
+    listing: Vec<String>,
+    ls_done: bool,
+    negotiating: bool,
+}
+
+impl Capabilities {
+    pub fn new(wanted: Vec<String>) -> Self {
+        Self {
+            wanted,
+            extra: Vec::new(),
+            available: BTreeMap::new(),
+            enabled: BTreeSet::new(),
+            pending: BTreeSet::new(),
+            rejected: BTreeSet::new(),
+            listing: Vec::new(),
+            ls_done: false,
+            negotiating: false,
+        }
+    }
+
+    pub fn set_wanted(&mut self, wanted: Vec<String>) {
+        self.wanted = wanted;
+    }
+
+    /// Forgets everything from the previous connection and returns the `CAP LS 302` to send.
+    ///
+    /// `extra` are capabilities this connection needs on top of the configured ones, like `sasl`.
+    pub fn start(&mut self, extra: Vec<String>) -> IrcMessage {
+        self.extra = extra;
+        self.available.clear();
+        self.enabled.clear();
+        self.pending.clear();
+        self.rejected.clear();
+        self.listing.clear();
+        self.ls_done = false;
+        self.negotiating = true;
+        IrcMessage::new("CAP", vec!["LS".into(), "302".into()])
+    }
+
+    pub fn is_negotiating(&self) -> bool {
+        self.negotiating
+    }
+
+    pub fn enabled(&self) -> impl Iterator<Item = &String> {
+        self.enabled.iter()
+    }
+
+    pub fn is_enabled(&self, cap: &str) -> bool {
+        self.enabled.contains(cap)
+    }
+
+    /// Value advertised with a capability, like the mechanism list in `sasl=PLAIN,EXTERNAL`.
+    pub fn value(&self, cap: &str) -> Option<&str> {
+        self.available.get(cap).map(|value| value.as_str())
+    }
+
+    /// True when every request got an answer and CAP END can be sent.
+    pub fn can_end(&self) -> bool {
+        self.negotiating && self.ls_done && self.pending.is_empty()
+    }
+
+    pub fn end(&mut self) -> IrcMessage {
+        self.negotiating = false;
+        IrcMessage::new("CAP", vec!["END".into()])
+    }
+
+    /// Called when the server does not know CAP at all or registered us without negotiating.
+    pub fn abort(&mut self) {
+        self.negotiating = false;
+        self.pending.clear();
+    }
+
+    pub fn handle(&mut self, message: &IrcMessage) -> Option<CapChange> {
+        let subcommand = message.param(1)?.to_ascii_uppercase();
+        // Multi-line replies put a `*` before the list on every line but the last one
+        let more = message.params.len() > 3 && message.params[2] == "*";
+        let list: Vec<&str> = message.trailing()?.split(' ').filter(|cap| !cap.is_empty()).collect();
+
+        match subcommand.as_str() {
+            "LS" => {
+                for cap in &list {
+                    let (name, value) = cap.split_once('=').unwrap_or((cap, ""));
+                    self.available.insert(name.to_string(), value.to_string());
+                }
+                if more {
+                    return None;
+                }
+                self.rejected.clear();
+                self.ls_done = true;
+                Some(CapChange::Available(self.available.keys().cloned().collect()))
+            }
+            "LIST" => {
+                self.listing.extend(list.iter().map(|cap| cap.to_string()));
+                if more {
+                    return None;
+                }
+                Some(CapChange::Listed(std::mem::take(&mut self.listing)))
+            }
+            "ACK" => {
+                let mut enabled = Vec::new();
+                for cap in list {
+                    match cap.strip_prefix('-') {
+                        Some(name) => {
+                            self.pending.remove(name);
+                            self.enabled.remove(name);
+                        }
+                        None => {
+                            self.pending.remove(cap);
+                            self.enabled.insert(cap.to_string());
+                            enabled.push(cap.to_string());
+                        }
+                    }
+                }
+                Some(CapChange::Enabled(enabled))
+            }
+            "NAK" => {
+                let rejected: Vec<String> = list.iter().map(|cap| cap.to_string()).collect();
+                for cap in &rejected {
+                    self.pending.remove(cap);
+                    self.rejected.insert(cap.clone());
+                }
+                Some(CapChange::Rejected(rejected))
+            }
+            "NEW" => {
+                let mut added = Vec::new();
+                for cap in list {
+                    let (name, value) = cap.split_once('=').unwrap_or((cap, ""));
+                    self.available.insert(name.to_string(), value.to_string());
+                    self.rejected.remove(name);
+                    added.push(name.to_string());
+                }
+                Some(CapChange::Available(added))
+            }
+            "DEL" => {
+                let removed: Vec<String> = list.iter().map(|cap| cap.to_string()).collect();
+                for cap in &removed {
+                    self.available.remove(cap);
+                    self.enabled.remove(cap);
+                    self.pending.remove(cap);
+                }
+                Some(CapChange::Removed(removed))
+            }
+            _ => None,
+        }
+    }
+
+    /// CAP REQ lines for every wanted capability that is available, not yet requested and not rejected.
+    pub fn take_request(&mut self) -> Vec<IrcMessage> {
+        if !self.ls_done {
+            return Vec::new();
+        }
+        let caps: Vec<String> = self
+            .wanted
+            .iter()
+            .chain(self.extra.iter())
+            .filter(|cap| self.available.contains_key(*cap) && !self.enabled.contains(*cap) && !self.pending.contains(*cap) && !self.rejected.contains(*cap))
+            .cloned()
+            .collect();
+
+        let mut requests = Vec::new();
+        let mut line = String::new();
+        for cap in caps {
+            // Configured and extra capabilities can overlap, each is only requested once
+            if !self.pending.insert(cap.clone()) {
+                continue;
+            }
+            if !line.is_empty() && line.len() + cap.len() + 1 > MAX_REQUEST_LEN {
+                requests.push(IrcMessage::new("CAP", vec!["REQ".into(), std::mem::take(&mut line)]));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&cap);
+        }
+        if !line.is_empty() {
+            requests.push(IrcMessage::new("CAP", vec!["REQ".into(), line]));
+        }
+        requests
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wanting(wanted: &[&str], extra: &[&str]) -> Capabilities {
+        let mut caps = Capabilities::new(wanted.iter().map(|cap| cap.to_string()).collect());
+        caps.start(extra.iter().map(|cap| cap.to_string()).collect());
+        caps
+    }
+
+    fn handle(caps: &mut Capabilities, line: &str) -> Option<CapChange> {
+        caps.handle(&IrcMessage::parse(line).unwrap())
+    }
+
+    fn requests(caps: &mut Capabilities) -> Vec<String> {
+        caps.take_request().iter().map(|request| request.to_string()).collect()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn waits_for_the_last_line_of_ls_302() {
+        let mut caps = wanting(&["multi-prefix", "batch", "away-notify"], &["sasl"]);
+        assert_eq!(handle(&mut caps, ":srv CAP * LS * :multi-prefix sasl=PLAIN,EXTERNAL"), None);
+        assert!(requests(&mut caps).is_empty());
+        assert!(!caps.can_end());
+
+        assert_eq!(
+            handle(&mut caps, ":srv CAP * LS :batch cap-notify"),
+            Some(CapChange::Available(names(&["batch", "cap-notify", "multi-prefix", "sasl"])))
+        );
+        assert_eq!(caps.value("sasl"), Some("PLAIN,EXTERNAL"));
+        assert_eq!(requests(&mut caps), ["CAP REQ :multi-prefix batch sasl"]);
+        assert!(!caps.can_end());
+    }
+
+    #[test]
+    fn enables_acked_and_drops_nakked_requests() {
+        let mut caps = wanting(&["multi-prefix", "batch"], &[]);
+        handle(&mut caps, ":srv CAP * LS :multi-prefix batch");
+        requests(&mut caps);
+
+        assert_eq!(
+            handle(&mut caps, ":srv CAP me ACK :multi-prefix"),
+            Some(CapChange::Enabled(names(&["multi-prefix"])))
+        );
+        assert!(caps.is_enabled("multi-prefix"));
+        assert!(!caps.can_end());
+        assert_eq!(handle(&mut caps, ":srv CAP me NAK :batch"), Some(CapChange::Rejected(names(&["batch"]))));
+        assert!(!caps.is_enabled("batch"));
+        assert!(caps.can_end());
+        assert_eq!(caps.end().to_string(), "CAP END");
+        assert!(!caps.is_negotiating());
+    }
+
+    #[test]
+    fn does_not_request_a_rejected_capability_again() {
+        let mut caps = wanting(&["batch", "sasl"], &["sasl"]);
+        handle(&mut caps, ":srv CAP * LS :batch sasl");
+        assert_eq!(requests(&mut caps), ["CAP REQ :batch sasl"]);
+        handle(&mut caps, ":srv CAP me NAK :batch sasl");
+        assert!(requests(&mut caps).is_empty());
+        assert!(caps.can_end());
+
+        // Until the server advertises it again
+        handle(&mut caps, ":srv CAP me NEW :sasl");
+        assert_eq!(requests(&mut caps), ["CAP REQ sasl"]);
+        handle(&mut caps, ":srv CAP me LS :batch sasl");
+        assert_eq!(requests(&mut caps), ["CAP REQ batch"]);
+    }
+
+    #[test]
+    fn follows_new_and_del() {
+        let mut caps = wanting(&["batch", "away-notify"], &[]);
+        handle(&mut caps, ":srv CAP * LS :batch");
+        requests(&mut caps);
+        handle(&mut caps, ":srv CAP me ACK :batch");
+        caps.end();
+
+        assert_eq!(
+            handle(&mut caps, ":srv CAP me NEW :away-notify account-tag"),
+            Some(CapChange::Available(names(&["away-notify", "account-tag"])))
+        );
+        assert_eq!(requests(&mut caps), ["CAP REQ away-notify"]);
+        handle(&mut caps, ":srv CAP me ACK :away-notify");
+        assert!(caps.is_enabled("away-notify"));
+
+        assert_eq!(handle(&mut caps, ":srv CAP me DEL :batch"), Some(CapChange::Removed(names(&["batch"]))));
+        assert!(!caps.is_enabled("batch"));
+        assert_eq!(caps.value("batch"), None);
+        assert!(requests(&mut caps).is_empty());
+        assert_eq!(caps.enabled().collect::<Vec<_>>(), ["away-notify"]);
+    }
+}
@@ -0,0 +1,172 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use unicode_normalization::UnicodeNormalization;
+
+/// How the server decides that two nicks or channel names are the same, from ISUPPORT CASEMAPPING.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum CaseMapping {
+    Ascii,
+    /// ASCII plus `{}|^` being the lower case of `[]\~`, the default of RFC 1459 servers.
+    #[default]
+    Rfc1459,
+    /// Like rfc1459 without the `~` and `^` pair.
+    Rfc1459Strict,
+    /// The PRECIS UsernameCaseMapped profile: full width characters narrowed, Unicode lower case and NFC.
+    Rfc7613,
+}
+
+impl CaseMapping {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "ascii" => Some(CaseMapping::Ascii),
+            "rfc1459" => Some(CaseMapping::Rfc1459),
+            "rfc1459-strict" | "strict-rfc1459" => Some(CaseMapping::Rfc1459Strict),
+            "rfc7613" | "precis" => Some(CaseMapping::Rfc7613),
+            _ => None,
+        }
+    }
+
+    /// The form two names have in common when the server considers them the same.
+    pub fn fold(&self, value: &str) -> String {
+        match self {
+            CaseMapping::Ascii => value.to_ascii_lowercase(),
+            CaseMapping::Rfc1459 | CaseMapping::Rfc1459Strict => value
+                .chars()
+                .map(|ch| match ch {
+                    'A'..='Z' => ch.to_ascii_lowercase(),
+                    '[' => '{',
+                    ']' => '}',
+                    '\\' => '|',
+                    '~' if *self == CaseMapping::Rfc1459 => '^',
+                    _ => ch,
+                })
+                .collect(),
+            CaseMapping::Rfc7613 => value.chars().map(narrow).flat_map(char::to_lowercase).nfc().collect(),
+        }
+    }
+
+    pub fn equals(&self, a: &str, b: &str) -> bool {
+        a == b || self.fold(a) == self.fold(b)
+    }
+}
+
+/// Maps full width forms like `Ａ` to their ASCII counterparts.
+fn narrow(ch: char) -> char {
+    match ch {
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(ch as u32 - 0xFF01 + 0x21).unwrap_or(ch),
+        '\u{3000}' => ' ',
+        _ => ch,
+    }
+}
+
+/// A nick or channel name that compares, hashes and sorts the way the server does.
+///
+/// It keeps the name as it was written for display, next to the folded form used for identity.
+/// Identifiers made with different mappings should not be mixed; call [`Identifier::remap`]
+/// on every stored one when the server announces its CASEMAPPING.
+#[derive(Debug, Clone)]
+pub struct Identifier {
+    name: String,
+    folded: String,
+}
+
+impl Identifier {
+    pub fn new(name: &str, mapping: CaseMapping) -> Self {
+        Self {
+            name: name.to_string(),
+            folded: mapping.fold(name),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    pub fn remap(&mut self, mapping: CaseMapping) {
+        self.folded = mapping.fold(&self.name);
+    }
+
+    /// Changes the name, like after a NICK, keeping the mapping the identifier was made with.
+    pub fn rename(&mut self, name: &str, mapping: CaseMapping) {
+        *self = Identifier::new(name, mapping);
+    }
+}
+
+impl PartialEq for Identifier {
+    fn eq(&self, other: &Self) -> bool {
+        self.folded == other.folded
+    }
+}
+
+impl Eq for Identifier {}
+
+impl Hash for Identifier {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.folded.hash(state);
+    }
+}
+
+impl PartialOrd for Identifier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Identifier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.folded.cmp(&other.folded)
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn ascii_only_folds_letters() {
+        let mapping = CaseMapping::Ascii;
+        assert!(mapping.equals("#Rust", "#rust"));
+        assert!(!mapping.equals("nick[]", "nick{}"));
+        assert!(!mapping.equals("Ärger", "ärger"));
+    }
+
+    #[test]
+    fn rfc1459_folds_brackets_and_tilde() {
+        let mapping = CaseMapping::Rfc1459;
+        assert!(mapping.equals("Nick[a]\\~", "nick{a}|^"));
+        assert!(!CaseMapping::Rfc1459Strict.equals("nick~", "nick^"));
+        assert!(CaseMapping::Rfc1459Strict.equals("Nick[\\]", "nick{|}"));
+    }
+
+    #[test]
+    fn rfc7613_folds_unicode_and_width() {
+        let mapping = CaseMapping::Rfc7613;
+        assert!(mapping.equals("Ärger", "ärger"));
+        assert!(mapping.equals("ＮＩＣＫ", "nick"));
+        // A + combining diaeresis composes to the same name as Ä
+        assert!(mapping.equals("A\u{0308}rger", "ärger"));
+        assert!(!mapping.equals("nick[]", "nick{}"));
+    }
+
+    #[test]
+    fn identifiers_hash_by_folded_name() {
+        let mut set = HashSet::new();
+        set.insert(Identifier::new("#Rust", CaseMapping::Rfc1459));
+        assert!(set.contains(&Identifier::new("#rust", CaseMapping::Rfc1459)));
+        assert_eq!(set.iter().next().unwrap().as_str(), "#Rust");
+
+        let mut id = Identifier::new("Nick~", CaseMapping::Ascii);
+        assert_ne!(id, Identifier::new("nick^", CaseMapping::Rfc1459));
+        id.remap(CaseMapping::Rfc1459);
+        assert_eq!(id, Identifier::new("nick^", CaseMapping::Rfc1459));
+    }
+}
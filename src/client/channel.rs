@@ -0,0 +1,388 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::client::casemapping::{CaseMapping, Identifier};
+use crate::client::message::Prefix;
+use crate::client::UserInfo;
+
+/// Channel membership modes (`o`, `v`, ...) and the symbols shown for them, highest rank first.
+#[derive(Debug, Clone)]
+pub struct PrefixModes {
+    modes: Vec<(char, char)>,
+}
+
+impl Default for PrefixModes {
+    fn default() -> Self {
+        Self {
+            modes: vec![('q', '~'), ('a', '&'), ('o', '@'), ('h', '%'), ('v', '+')],
+        }
+    }
+}
+
+impl PrefixModes {
+    /// Parses an ISUPPORT PREFIX value like `(ov)@+`, empty when the server has no prefixes.
+    pub fn parse(value: &str) -> Option<Self> {
+        if value.is_empty() {
+            return Some(Self { modes: Vec::new() });
+        }
+        let (modes, symbols) = value.strip_prefix('(')?.split_once(')')?;
+        if modes.chars().count() != symbols.chars().count() {
+            return None;
+        }
+        Some(Self {
+            modes: modes.chars().zip(symbols.chars()).collect(),
+        })
+    }
+
+    pub fn symbol(&self, mode: char) -> Option<char> {
+        self.modes.iter().find(|(m, _)| *m == mode).map(|(_, symbol)| *symbol)
+    }
+
+    pub fn is_symbol(&self, symbol: char) -> bool {
+        self.modes.iter().any(|(_, s)| *s == symbol)
+    }
+
+    /// Position of a symbol in the table, 0 being the highest rank.
+    pub fn rank(&self, symbol: char) -> usize {
+        self.modes.iter().position(|(_, s)| *s == symbol).unwrap_or(self.modes.len())
+    }
+}
+
+/// The four kinds of channel modes, which decide whether a mode change takes an argument.
+#[derive(Debug, Clone)]
+pub struct ChannelModeKinds {
+    list: String,
+    always: String,
+    on_set: String,
+}
+
+impl Default for ChannelModeKinds {
+    fn default() -> Self {
+        Self {
+            list: "beI".into(),
+            always: "k".into(),
+            on_set: "l".into(),
+        }
+    }
+}
+
+impl ChannelModeKinds {
+    /// Parses an ISUPPORT CHANMODES value like `beI,k,l,imnpst`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut kinds = value.split(',');
+        Some(Self {
+            list: kinds.next()?.into(),
+            always: kinds.next()?.into(),
+            on_set: kinds.next()?.into(),
+        })
+    }
+
+    pub fn takes_argument(&self, mode: char, adding: bool, prefixes: &PrefixModes) -> bool {
+        prefixes.symbol(mode).is_some() || self.list.contains(mode) || self.always.contains(mode) || (adding && self.on_set.contains(mode))
+    }
+
+    pub fn is_list(&self, mode: char) -> bool {
+        self.list.contains(mode)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChannelMember {
+    pub user: UserInfo,
+    /// Prefix symbols the member holds, highest rank first.
+    pub prefixes: String,
+    /// Number of the last message the member sent to the channel, 0 when they have not spoken.
+    pub last_spoke: u64,
+}
+
+impl ChannelMember {
+    pub fn highest_prefix(&self) -> Option<char> {
+        self.prefixes.chars().next()
+    }
+
+    fn set_prefix(&mut self, symbol: char, adding: bool, table: &PrefixModes) {
+        let mut prefixes: Vec<char> = self.prefixes.chars().filter(|s| *s != symbol).collect();
+        if adding {
+            prefixes.push(symbol);
+            prefixes.sort_by_key(|s| table.rank(*s));
+        }
+        self.prefixes = prefixes.into_iter().collect();
+    }
+}
+
+#[derive(Debug)]
+pub struct Channel {
+    name: String,
+    topic: String,
+    user_list: HashMap<Identifier, ChannelMember>,
+    modes: BTreeMap<char, Option<String>>,
+    receiving_names: bool,
+    mapping: CaseMapping,
+    /// Messages seen in the channel, numbering them for [`ChannelMember::last_spoke`].
+    messages: u64,
+}
+
+impl Channel {
+    pub fn new(name: String, mapping: CaseMapping) -> Self {
+        Self {
+            name,
+            topic: String::new(),
+            user_list: HashMap::new(),
+            modes: BTreeMap::new(),
+            receiving_names: false,
+            mapping,
+            messages: 0,
+        }
+    }
+
+    /// Re-keys the nick list after the server announced a different CASEMAPPING.
+    pub fn set_casemapping(&mut self, mapping: CaseMapping) {
+        self.mapping = mapping;
+        self.user_list = self
+            .user_list
+            .drain()
+            .map(|(mut nick, member)| {
+                nick.remap(mapping);
+                (nick, member)
+            })
+            .collect();
+    }
+
+    fn id(&self, nick: &str) -> Identifier {
+        Identifier::new(nick, self.mapping)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub fn set_topic(&mut self, topic: String) {
+        self.topic = topic;
+    }
+
+    /// Key needed to join, when the channel has mode `k`.
+    pub fn key(&self) -> Option<&str> {
+        self.modes.get(&'k')?.as_deref()
+    }
+
+    /// Channel modes as `+ntk key`, the way a server would show them.
+    pub fn mode_string(&self) -> String {
+        let flags: String = self.modes.keys().collect();
+        let args: Vec<&str> = self.modes.values().filter_map(|arg| arg.as_deref()).collect();
+        if flags.is_empty() {
+            String::new()
+        } else if args.is_empty() {
+            format!("+{flags}")
+        } else {
+            format!("+{flags} {}", args.join(" "))
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.user_list.len()
+    }
+
+    pub fn member(&self, nick: &str) -> Option<&ChannelMember> {
+        self.user_list.get(&self.id(nick))
+    }
+
+    /// Members ordered the way a nick list shows them, by rank and then by nick.
+    pub fn members(&self, table: &PrefixModes) -> Vec<&ChannelMember> {
+        let mut members: Vec<(&Identifier, &ChannelMember)> = self.user_list.iter().collect();
+        members.sort_by_key(|(nick, member)| (member.highest_prefix().map(|s| table.rank(s)).unwrap_or(usize::MAX), *nick));
+        members.into_iter().map(|(_, member)| member).collect()
+    }
+
+    /// Nicks of the members, the ones who spoke most recently first and then by nick.
+    pub fn nicks_by_activity(&self) -> Vec<&str> {
+        let mut members: Vec<(&Identifier, &ChannelMember)> = self.user_list.iter().collect();
+        members.sort_by_key(|(nick, member)| (std::cmp::Reverse(member.last_spoke), *nick));
+        members.into_iter().map(|(_, member)| member.user.nick()).collect()
+    }
+
+    /// Records that `nick` sent a message to the channel.
+    pub fn spoke(&mut self, nick: &str) {
+        self.messages += 1;
+        let messages = self.messages;
+        if let Some(member) = self.user_list.get_mut(&self.id(nick)) {
+            member.last_spoke = messages;
+        }
+    }
+
+    pub fn add_member(&mut self, user: UserInfo, prefixes: String) {
+        self.user_list.insert(self.id(user.nick()), ChannelMember { user, prefixes, last_spoke: 0 });
+    }
+
+    pub fn remove_member(&mut self, nick: &str) -> bool {
+        self.user_list.remove(&self.id(nick)).is_some()
+    }
+
+    pub fn rename_member(&mut self, nick: &str, new_nick: &str) -> bool {
+        match self.user_list.remove(&self.id(nick)) {
+            Some(mut member) => {
+                member.user.nick = new_nick.to_string();
+                self.user_list.insert(self.id(new_nick), member);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds the nicks of a RPL_NAMREPLY, dropping the old list on the first reply of a batch.
+    pub fn add_names(&mut self, names: &str, table: &PrefixModes) {
+        if !self.receiving_names {
+            self.receiving_names = true;
+            self.user_list.clear();
+        }
+        for name in names.split(' ').filter(|name| !name.is_empty()) {
+            let mask = name.trim_start_matches(|ch| table.is_symbol(ch));
+            let mut prefixes: Vec<char> = name[..name.len() - mask.len()].chars().collect();
+            prefixes.sort_by_key(|s| table.rank(*s));
+            self.add_member(UserInfo::from_prefix(&Prefix::parse(mask)), prefixes.into_iter().collect());
+        }
+    }
+
+    pub fn end_of_names(&mut self) {
+        self.receiving_names = false;
+    }
+
+    /// Applies a mode string like `+o-v+k nick other key` to the channel and its members.
+    pub fn apply_modes(&mut self, modes: &str, args: &[String], table: &PrefixModes, kinds: &ChannelModeKinds) {
+        let mut args = args.iter();
+        let mut adding = true;
+        for mode in modes.chars() {
+            match mode {
+                '+' => adding = true,
+                '-' => adding = false,
+                _ => {
+                    let arg = if kinds.takes_argument(mode, adding, table) { args.next() } else { None };
+                    if let Some(symbol) = table.symbol(mode) {
+                        if let Some(member) = arg.and_then(|nick| self.user_list.get_mut(&Identifier::new(nick, self.mapping))) {
+                            member.set_prefix(symbol, adding, table);
+                        }
+                    } else if kinds.is_list(mode) {
+                        // Ban, exception and invite lists are not tracked
+                    } else if adding {
+                        self.modes.insert(mode, arg.cloned());
+                    } else {
+                        self.modes.remove(&mode);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replaces the channel modes with the ones from RPL_CHANNELMODEIS.
+    pub fn set_modes(&mut self, modes: &str, args: &[String], table: &PrefixModes, kinds: &ChannelModeKinds) {
+        self.modes.clear();
+        self.apply_modes(modes, args, table, kinds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn prefixes<'a>(channel: &'a Channel, nick: &str) -> &'a str {
+        &channel.member(nick).unwrap().prefixes
+    }
+
+    #[test]
+    fn reads_multi_prefix_names() {
+        let table = PrefixModes::default();
+        let mut channel = Channel::new("#rust".into(), CaseMapping::Rfc1459);
+        channel.add_member(UserInfo::from_nick("gone".into()), String::new());
+        channel.add_names("+@alice ~bob!b@host carol", &table);
+        // The first reply of a batch replaces the old list, the next ones add to it
+        assert!(channel.member("gone").is_none());
+        channel.add_names("%+dave", &table);
+        channel.end_of_names();
+        assert_eq!(channel.len(), 4);
+        assert_eq!(prefixes(&channel, "alice"), "@+");
+        assert_eq!(prefixes(&channel, "BOB"), "~");
+        assert_eq!(channel.member("bob").unwrap().user.mask().as_deref(), Some("b@host"));
+        assert_eq!(prefixes(&channel, "carol"), "");
+        assert_eq!(prefixes(&channel, "dave"), "%+");
+
+        channel.add_names("erin", &table);
+        assert_eq!(channel.len(), 1);
+    }
+
+    #[test]
+    fn ranks_members_by_their_highest_prefix() {
+        let table = PrefixModes::parse("(ov)@+").unwrap();
+        assert_eq!(table.rank('@'), 0);
+        assert_eq!(table.rank('+'), 1);
+        assert_eq!(table.rank('%'), 2);
+
+        let mut channel = Channel::new("#rust".into(), CaseMapping::Rfc1459);
+        channel.add_names("zed +bob @carol +@alice adam", &table);
+        let nicks: Vec<&str> = channel.members(&table).iter().map(|member| member.user.nick()).collect();
+        assert_eq!(nicks, ["alice", "carol", "bob", "adam", "zed"]);
+    }
+
+    #[test]
+    fn changes_member_prefixes() {
+        let table = PrefixModes::default();
+        let kinds = ChannelModeKinds::default();
+        let mut channel = Channel::new("#rust".into(), CaseMapping::Rfc1459);
+        channel.add_names("+alice bob", &table);
+
+        channel.apply_modes("+o-v+v", &args(&["alice", "alice", "Bob"]), &table, &kinds);
+        assert_eq!(prefixes(&channel, "alice"), "@");
+        assert_eq!(prefixes(&channel, "bob"), "+");
+        channel.apply_modes("+vq", &args(&["alice", "alice"]), &table, &kinds);
+        assert_eq!(prefixes(&channel, "alice"), "~@+");
+        channel.apply_modes("-o", &args(&["alice"]), &table, &kinds);
+        assert_eq!(prefixes(&channel, "alice"), "~+");
+        // Someone who is not in the channel is skipped along with their argument
+        channel.apply_modes("+ov", &args(&["nobody", "bob"]), &table, &kinds);
+        assert_eq!(prefixes(&channel, "bob"), "+");
+        assert_eq!(channel.mode_string(), "");
+    }
+
+    #[test]
+    fn takes_mode_arguments_as_chanmodes_says() {
+        let table = PrefixModes::default();
+        let kinds = ChannelModeKinds::parse("beIq,kf,lj,imnpst").unwrap();
+        let mut channel = Channel::new("#rust".into(), CaseMapping::Rfc1459);
+        channel.add_names("alice", &table);
+
+        channel.apply_modes("+bnkl-ef", &args(&["*!*@spam", "secret", "10", "*!*@ok", "x"]), &table, &kinds);
+        assert_eq!(channel.mode_string(), "+kln secret 10");
+        assert_eq!(channel.key(), Some("secret"));
+        // Removing l takes no argument, so the next one goes to o
+        channel.apply_modes("-l+oj", &args(&["alice", "3:5"]), &table, &kinds);
+        assert_eq!(prefixes(&channel, "alice"), "@");
+        assert_eq!(channel.mode_string(), "+jkn 3:5 secret");
+        channel.apply_modes("-k", &args(&["secret"]), &table, &kinds);
+        assert_eq!(channel.key(), None);
+
+        channel.set_modes("+tl", &args(&["50"]), &table, &kinds);
+        assert_eq!(channel.mode_string(), "+lt 50");
+        assert_eq!(prefixes(&channel, "alice"), "@");
+    }
+
+    #[test]
+    fn removes_and_renames_members() {
+        let table = PrefixModes::default();
+        let mut channel = Channel::new("#rust".into(), CaseMapping::Rfc1459);
+        channel.add_names("@alice bob[m] carol", &table);
+
+        assert!(channel.remove_member("ALICE"));
+        assert!(!channel.remove_member("alice"));
+        // RFC 1459 casemapping makes [] the upper case of {}
+        assert!(channel.remove_member("bob{m}"));
+        assert!(channel.rename_member("carol", "caroline"));
+        assert!(channel.member("carol").is_none());
+        assert_eq!(channel.member("Caroline").unwrap().user.nick(), "caroline");
+        assert_eq!(channel.len(), 1);
+    }
+}
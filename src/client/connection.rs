@@ -0,0 +1,519 @@
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind, IoSlice, IoSliceMut, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mio::net::TcpStream;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme, StreamOwned};
+
+use crate::client::reactor::Reactor;
+
+/// How long connecting to a server, the TLS handshake included, may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How the certificate presented by the server is checked.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TlsVerify {
+    /// Against the root certificates installed on the system.
+    System,
+    /// Accept any certificate. Only meant for testing.
+    AcceptInvalid,
+    /// Accept only the certificate with this SHA-256 fingerprint, no matter who signed it.
+    Pinned(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub verify: TlsVerify,
+    /// PEM certificate and key presented to the server, used for CertFP and SASL EXTERNAL.
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+}
+
+impl TlsConfig {
+    pub fn new(verify: TlsVerify) -> Self {
+        Self { verify, client_cert: None }
+    }
+
+    fn client_config(&self) -> Result<ClientConfig, String> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?;
+
+        let builder = match &self.verify {
+            TlsVerify::System => {
+                let mut roots = RootCertStore::empty();
+                roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+                builder.with_root_certificates(roots)
+            }
+            TlsVerify::AcceptInvalid => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(UncheckedVerifier { pin: None, provider })),
+            TlsVerify::Pinned(pin) => builder.dangerous().with_custom_certificate_verifier(Arc::new(UncheckedVerifier {
+                pin: Some(pin.clone()),
+                provider,
+            })),
+        };
+
+        match &self.client_cert {
+            None => Ok(builder.with_no_client_auth()),
+            Some((cert, key)) => {
+                let chain = CertificateDer::pem_file_iter(cert)
+                    .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                    .map_err(|e| format!("Could not read client certificate {}: {e}", cert.display()))?;
+                let key = PrivateKeyDer::from_pem_file(key).map_err(|e| format!("Could not read client key {}: {e}", key.display()))?;
+                builder.with_client_auth_cert(chain, key).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+/// Verifier for servers whose certificate is not signed by a trusted root.
+///
+/// Handshake signatures are still checked, so the server has to own the key of the certificate
+/// it shows. With a pin only that exact certificate is accepted.
+#[derive(Debug)]
+struct UncheckedVerifier {
+    pin: Option<Vec<u8>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for UncheckedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match &self.pin {
+            Some(pin) if *pin != sha256(end_entity) => Err(rustls::Error::General(format!(
+                "certificate fingerprint {} does not match the pinned {}",
+                format_fingerprint(&sha256(end_entity)),
+                format_fingerprint(pin)
+            ))),
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA256, data).as_ref().to_vec()
+}
+
+/// Formats a fingerprint as colon separated hex, like `openssl x509 -fingerprint` does.
+pub fn format_fingerprint(fingerprint: &[u8]) -> String {
+    let mut result = String::with_capacity(fingerprint.len() * 3);
+    for (i, byte) in fingerprint.iter().enumerate() {
+        if i > 0 {
+            result.push(':');
+        }
+        let _ = write!(result, "{byte:02X}");
+    }
+    result
+}
+
+/// Parses a SHA-256 fingerprint written as hex, with or without colons.
+pub fn parse_fingerprint(value: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = value.bytes().filter(|ch| *ch != b':' && !ch.is_ascii_whitespace()).collect();
+    if digits.len() != 64 {
+        return Err(format!("Invalid SHA-256 fingerprint {value}"));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or(format!("Invalid SHA-256 fingerprint {value}"))
+        })
+        .collect()
+}
+
+/// A connection to a server, in plain text or over TLS.
+///
+/// Opening one only starts connecting, [`Connection::ready`] finishes the connect and the TLS
+/// handshake as the socket becomes ready, so a slow server never holds up the event loop.
+pub struct Connection {
+    stream: Stream,
+    progress: Progress,
+    /// When the connect and the handshake have to be done by.
+    deadline: Instant,
+}
+
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+/// How far opening a connection got.
+enum Progress {
+    /// Waiting for the TCP connect, with the addresses to try when this one fails.
+    Connecting(Vec<SocketAddr>),
+    /// Waiting for the TLS handshake to finish.
+    Handshaking,
+    Open,
+}
+
+impl Connection {
+    /// Starts connecting to `host`, registering the socket with `reactor`. Errors that can be
+    /// seen right away, like an unknown host or an unreadable client certificate, are reported
+    /// here and the others by [`Connection::ready`].
+    pub fn open(host: &str, port: u16, tls: Option<&TlsConfig>, reactor: &Reactor) -> Result<Self, String> {
+        let mut addresses: Vec<SocketAddr> = (host, port).to_socket_addrs().map_err(|e| e.to_string())?.collect();
+        // Tried in the order the resolver gave them, taken from the end
+        addresses.reverse();
+        let session = match tls {
+            Some(tls) => {
+                let name = ServerName::try_from(host.to_string()).map_err(|e| e.to_string())?;
+                Some(ClientConnection::new(Arc::new(tls.client_config()?), name).map_err(|e| e.to_string())?)
+            }
+            None => None,
+        };
+        let socket = connect_next(&mut addresses, reactor).map_err(|e| e.to_string())?;
+        let stream = match session {
+            Some(session) => Stream::Tls(Box::new(StreamOwned::new(session, socket))),
+            None => Stream::Plain(socket),
+        };
+        Ok(Self {
+            stream,
+            progress: Progress::Connecting(addresses),
+            deadline: Instant::now() + CONNECT_TIMEOUT,
+        })
+    }
+
+    /// Moves the connect and the TLS handshake along, true once the connection can be used.
+    /// Called again when the socket is ready or the deadline comes.
+    pub fn ready(&mut self, reactor: &Reactor) -> Result<bool, String> {
+        loop {
+            match &mut self.progress {
+                Progress::Open => return Ok(true),
+                Progress::Connecting(addresses) => {
+                    let socket = match &mut self.stream {
+                        Stream::Plain(socket) => socket,
+                        Stream::Tls(stream) => &mut stream.sock,
+                    };
+                    match connected(socket) {
+                        Ok(true) => {}
+                        Ok(false) if Instant::now() < self.deadline => return Ok(false),
+                        Ok(false) => return Err("Connection timed out".to_string()),
+                        Err(e) => {
+                            reactor.deregister(socket);
+                            *socket = connect_next(addresses, reactor).map_err(|_| e.to_string())?;
+                            continue;
+                        }
+                    }
+                    self.progress = match self.stream {
+                        Stream::Plain(_) => Progress::Open,
+                        Stream::Tls(_) => Progress::Handshaking,
+                    };
+                }
+                Progress::Handshaking => {
+                    let Stream::Tls(stream) = &mut self.stream else {
+                        unreachable!("only TLS connections shake hands");
+                    };
+                    while stream.conn.is_handshaking() {
+                        match stream.conn.complete_io(&mut stream.sock) {
+                            Ok(_) => {}
+                            Err(e) if e.kind() == ErrorKind::WouldBlock && Instant::now() < self.deadline => return Ok(false),
+                            Err(e) if e.kind() == ErrorKind::WouldBlock => return Err("TLS handshake timed out".to_string()),
+                            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                            Err(e) => return Err(format!("TLS handshake failed: {e}")),
+                        }
+                    }
+                    self.progress = Progress::Open;
+                }
+            }
+        }
+    }
+
+    /// When opening the connection times out, None once it is open.
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.progress {
+            Progress::Open => None,
+            _ => Some(self.deadline),
+        }
+    }
+
+    fn socket(&self) -> &TcpStream {
+        match &self.stream {
+            Stream::Plain(socket) => socket,
+            Stream::Tls(stream) => stream.get_ref(),
+        }
+    }
+
+    /// Our end of the connection, the address others on the network can likely reach us at.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket().local_addr()
+    }
+
+    /// SHA-256 fingerprint of the certificate the server presented.
+    pub fn peer_fingerprint(&self) -> Option<Vec<u8>> {
+        match &self.stream {
+            Stream::Plain(_) => None,
+            Stream::Tls(stream) => stream.conn.peer_certificates()?.first().map(|cert| sha256(cert)),
+        }
+    }
+}
+
+/// Starts connecting to the next of `addresses` that takes a connect attempt.
+fn connect_next(addresses: &mut Vec<SocketAddr>, reactor: &Reactor) -> io::Result<TcpStream> {
+    let mut error = io::Error::new(ErrorKind::NotFound, "No address found");
+    while let Some(address) = addresses.pop() {
+        match TcpStream::connect(address).and_then(|socket| reactor.register(&socket).map(|_| socket)) {
+            Ok(socket) => return Ok(socket),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+/// Whether a connect in progress went through, an error when it failed.
+fn connected(socket: &TcpStream) -> io::Result<bool> {
+    if let Some(e) = socket.take_error()? {
+        return Err(e);
+    }
+    match socket.peer_addr() {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::NotConnected => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.stream {
+            Stream::Plain(socket) => socket.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        match &mut self.stream {
+            Stream::Plain(socket) => socket.read_vectored(bufs),
+            Stream::Tls(stream) => stream.read_vectored(bufs),
+        }
+    }
+}
+
+impl AsRawFd for Connection {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket().as_raw_fd()
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.stream {
+            Stream::Plain(socket) => socket.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        match &mut self.stream {
+            Stream::Plain(socket) => socket.write_vectored(bufs),
+            Stream::Tls(stream) => stream.write_vectored(bufs),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.stream {
+            Stream::Plain(socket) => socket.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::{ServerConfig, ServerConnection};
+    use std::io::ErrorKind;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    struct TestServer {
+        port: u16,
+        fingerprint: Vec<u8>,
+        handle: thread::JoinHandle<Option<usize>>,
+    }
+
+    /// Starts a TLS server with a self-signed certificate for `localhost` that greets one client,
+    /// reads one line back and returns the number of certificates the client presented.
+    fn start_server(client_ca: Option<CertificateDer<'static>>) -> TestServer {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert = certified.cert.der().clone();
+        let key = PrivateKeyDer::try_from(certified.signing_key.serialize_der()).unwrap();
+        let fingerprint = sha256(&cert);
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap();
+        let builder = match client_ca {
+            Some(ca) => {
+                let mut roots = RootCertStore::empty();
+                roots.add(ca).unwrap();
+                builder.with_client_cert_verifier(WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build().unwrap())
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = Arc::new(builder.with_single_cert(vec![cert], key).unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (socket, _) = listener.accept().ok()?;
+            socket.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+            let mut stream = StreamOwned::new(ServerConnection::new(config).ok()?, socket);
+            stream.write_all(b":server 001 tester :Welcome\r\n").ok()?;
+            let mut line = [0u8; 6];
+            stream.read_exact(&mut line).ok()?;
+            Some(stream.conn.peer_certificates().map_or(0, |certs| certs.len()))
+        });
+        TestServer { port, fingerprint, handle }
+    }
+
+    /// Opens a connection and waits for it to be usable, as the event loop would.
+    fn open(host: &str, port: u16, tls: Option<&TlsConfig>) -> Result<Connection, String> {
+        let reactor = Reactor::default();
+        let mut connection = Connection::open(host, port, tls, &reactor)?;
+        while !connection.ready(&reactor)? {
+            thread::sleep(Duration::from_millis(5));
+        }
+        Ok(connection)
+    }
+
+    fn read_line(connection: &mut Connection) -> String {
+        let started = Instant::now();
+        let mut data = Vec::new();
+        let mut buf = [0u8; 256];
+        while !data.ends_with(b"\r\n") && started.elapsed() < Duration::from_secs(5) {
+            match connection.read(&mut buf) {
+                Ok(n) => data.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(5)),
+                Err(e) => panic!("{e}"),
+            }
+        }
+        String::from_utf8(data).unwrap()
+    }
+
+    #[test]
+    fn system_roots_reject_self_signed_certificate() {
+        let server = start_server(None);
+        let result = open("localhost", server.port, Some(&TlsConfig::new(TlsVerify::System)));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn accept_invalid_connects_to_self_signed_server() {
+        let server = start_server(None);
+        let mut connection = open("localhost", server.port, Some(&TlsConfig::new(TlsVerify::AcceptInvalid))).unwrap();
+        assert_eq!(connection.peer_fingerprint(), Some(server.fingerprint.clone()));
+        assert_eq!(read_line(&mut connection), ":server 001 tester :Welcome\r\n");
+        connection.write_all(b"QUIT\r\n").unwrap();
+        assert_eq!(server.handle.join().unwrap(), Some(0));
+    }
+
+    #[test]
+    fn pinned_fingerprint_must_match() {
+        let server = start_server(None);
+        let pin = TlsVerify::Pinned(server.fingerprint.clone());
+        let mut connection = open("localhost", server.port, Some(&TlsConfig::new(pin))).unwrap();
+        assert_eq!(read_line(&mut connection), ":server 001 tester :Welcome\r\n");
+
+        let server = start_server(None);
+        let pin = TlsVerify::Pinned(vec![0; 32]);
+        assert!(open("localhost", server.port, Some(&TlsConfig::new(pin))).is_err());
+    }
+
+    #[test]
+    fn client_certificate_is_presented() {
+        let client = rcgen::generate_simple_self_signed(vec!["tester".to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("crust-tls-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert_path = dir.join("client.pem");
+        let key_path = dir.join("client.key");
+        std::fs::write(&cert_path, client.cert.pem()).unwrap();
+        std::fs::write(&key_path, client.signing_key.serialize_pem()).unwrap();
+
+        let server = start_server(Some(client.cert.der().clone()));
+        let config = TlsConfig {
+            verify: TlsVerify::AcceptInvalid,
+            client_cert: Some((cert_path, key_path)),
+        };
+        let mut connection = open("localhost", server.port, Some(&config)).unwrap();
+        assert_eq!(read_line(&mut connection), ":server 001 tester :Welcome\r\n");
+        connection.write_all(b"QUIT\r\n").unwrap();
+        assert_eq!(server.handle.join().unwrap(), Some(1));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn handshake_waits_for_the_server_without_blocking() {
+        // Accepts the connection but never answers the TLS hello
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let reactor = Reactor::default();
+        let started = Instant::now();
+        let mut connection = Connection::open("127.0.0.1", port, Some(&TlsConfig::new(TlsVerify::AcceptInvalid)), &reactor).unwrap();
+        let _accepted = listener.accept().unwrap();
+        for _ in 0..20 {
+            assert_eq!(connection.ready(&reactor), Ok(false));
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(connection.deadline().is_some());
+
+        connection.deadline = Instant::now();
+        assert!(connection.ready(&reactor).is_err());
+    }
+
+    #[test]
+    fn refused_connect_is_reported_by_ready() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let reactor = Reactor::default();
+        let result = Connection::open("127.0.0.1", port, None, &reactor).and_then(|mut connection| {
+            let started = Instant::now();
+            while !connection.ready(&reactor)? && started.elapsed() < Duration::from_secs(5) {
+                thread::sleep(Duration::from_millis(5));
+            }
+            Ok(connection)
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn fingerprint_round_trip() {
+        let fingerprint: Vec<u8> = (0..32).collect();
+        let text = format_fingerprint(&fingerprint);
+        assert_eq!(&text[..8], "00:01:02");
+        assert_eq!(parse_fingerprint(&text), Ok(fingerprint.clone()));
+        assert_eq!(parse_fingerprint(&text.replace(':', "").to_lowercase()), Ok(fingerprint));
+        assert!(parse_fingerprint("00:11").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use time::format_description::well_known::Rfc2822;
+use time::OffsetDateTime;
+
+use crate::app;
+
+const DELIMITER: char = '\x01';
+
+/// Splits a CTCP payload like `\x01VERSION\x01` into its upper case command and its parameters.
+pub fn decode(text: &str) -> Option<(String, String)> {
+    let body = text.strip_prefix(DELIMITER)?;
+    // Some clients leave out the closing delimiter
+    let body = body.strip_suffix(DELIMITER).unwrap_or(body);
+    let (command, params) = body.split_once(' ').unwrap_or((body, ""));
+    if command.is_empty() {
+        return None;
+    }
+    Some((command.to_ascii_uppercase(), params.to_string()))
+}
+
+pub fn encode(command: &str, params: &str) -> String {
+    if params.is_empty() {
+        format!("{DELIMITER}{command}{DELIMITER}")
+    } else {
+        format!("{DELIMITER}{command} {params}{DELIMITER}")
+    }
+}
+
+/// Which CTCP requests get an automatic reply, and how many replies may go out in a while.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CtcpPolicy {
+    pub version: bool,
+    pub ping: bool,
+    pub time: bool,
+    pub source: bool,
+    pub clientinfo: bool,
+    /// Replies allowed within `period`, requests past that are ignored.
+    pub max_replies: usize,
+    pub period: Duration,
+}
+
+impl Default for CtcpPolicy {
+    fn default() -> Self {
+        Self {
+            version: true,
+            ping: true,
+            time: true,
+            source: true,
+            clientinfo: true,
+            max_replies: 3,
+            period: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Answers the CTCP requests of other users without letting them use us to flood the server.
+#[derive(Debug)]
+pub struct CtcpReplies {
+    policy: CtcpPolicy,
+    sent: VecDeque<Instant>,
+}
+
+impl CtcpReplies {
+    pub fn new(policy: CtcpPolicy) -> Self {
+        Self { policy, sent: VecDeque::new() }
+    }
+
+    pub fn set_policy(&mut self, policy: CtcpPolicy) {
+        self.policy = policy;
+    }
+
+    /// The encoded reply to a request, None when it is unknown, disabled or over the limit.
+    pub fn reply(&mut self, command: &str, params: &str, now: Instant) -> Option<String> {
+        let answer = self.answer(command, params)?;
+        while self.sent.front().is_some_and(|sent| now.saturating_duration_since(*sent) >= self.policy.period) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.policy.max_replies {
+            return None;
+        }
+        self.sent.push_back(now);
+        Some(encode(command, &answer))
+    }
+
+    fn answer(&self, command: &str, params: &str) -> Option<String> {
+        match command {
+            "VERSION" if self.policy.version => Some(format!("{} {}", app::Name(), env!("CARGO_PKG_VERSION"))),
+            "PING" if self.policy.ping => Some(params.to_string()),
+            "TIME" if self.policy.time => {
+                let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+                now.format(&Rfc2822).ok()
+            }
+            // Only answered when the package says where its source is
+            "SOURCE" if self.policy.source && !env!("CARGO_PKG_REPOSITORY").is_empty() => Some(env!("CARGO_PKG_REPOSITORY").to_string()),
+            "CLIENTINFO" if self.policy.clientinfo => Some(self.supported().join(" ")),
+            _ => None,
+        }
+    }
+
+    /// The CTCP commands we understand, ACTION always included.
+    fn supported(&self) -> Vec<&'static str> {
+        let policy = &self.policy;
+        let source = policy.source && !env!("CARGO_PKG_REPOSITORY").is_empty();
+        [
+            ("ACTION", true),
+            ("CLIENTINFO", policy.clientinfo),
+            ("PING", policy.ping),
+            ("SOURCE", source),
+            ("TIME", policy.time),
+            ("VERSION", policy.version),
+        ]
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(command, _)| command)
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_and_encodes() {
+        assert_eq!(decode("\x01ACTION waves\x01"), Some(("ACTION".into(), "waves".into())));
+        assert_eq!(decode("\x01version"), Some(("VERSION".into(), "".into())));
+        assert_eq!(decode("\x01\x01"), None);
+        assert_eq!(decode("plain text"), None);
+        assert_eq!(encode("PING", "123"), "\x01PING 123\x01");
+        assert_eq!(encode("VERSION", ""), "\x01VERSION\x01");
+    }
+
+    #[test]
+    fn replies_to_enabled_requests() {
+        let mut replies = CtcpReplies::new(CtcpPolicy {
+            time: false,
+            ..CtcpPolicy::default()
+        });
+        let now = Instant::now();
+        assert_eq!(replies.reply("PING", "42", now), Some("\x01PING 42\x01".into()));
+        assert!(replies.reply("VERSION", "", now).is_some_and(|reply| reply.contains(app::Name())));
+        let clientinfo = replies.reply("CLIENTINFO", "", now).unwrap();
+        assert!(clientinfo.contains("ACTION") && clientinfo.contains("PING") && !clientinfo.contains("TIME"));
+        assert_eq!(replies.reply("TIME", "", now + Duration::from_secs(60)), None);
+        assert_eq!(replies.reply("FINGER", "", now + Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn limits_the_reply_rate() {
+        let mut replies = CtcpReplies::new(CtcpPolicy::default());
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(replies.reply("PING", "x", now).is_some());
+        }
+        assert_eq!(replies.reply("PING", "x", now + Duration::from_secs(5)), None);
+        assert!(replies.reply("PING", "x", now + Duration::from_secs(10)).is_some());
+    }
+}
@@ -0,0 +1,217 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+/// A DCC request, the parameters of a `\x01DCC ...\x01` CTCP.
+///
+/// A port of 0 with a token asks the other side to listen instead, what is called passive or
+/// reverse DCC. RESUME and ACCEPT name the offer they answer by its port, or by its token when
+/// the offer was passive.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DccRequest {
+    Chat {
+        address: IpAddr,
+        port: u16,
+        token: Option<String>,
+    },
+    Send {
+        file: String,
+        address: IpAddr,
+        port: u16,
+        size: u64,
+        token: Option<String>,
+    },
+    Resume {
+        file: String,
+        port: u16,
+        position: u64,
+        token: Option<String>,
+    },
+    Accept {
+        file: String,
+        port: u16,
+        position: u64,
+        token: Option<String>,
+    },
+}
+
+impl DccRequest {
+    pub fn parse(params: &str) -> Option<Self> {
+        let (kind, rest) = params.trim().split_once(' ')?;
+        let (argument, rest) = split_argument(rest)?;
+        let fields: Vec<&str> = rest.split(' ').filter(|field| !field.is_empty()).collect();
+        let token = |index: usize| fields.get(index).map(|token| token.to_string());
+        match kind.to_ascii_uppercase().as_str() {
+            // The argument of a CHAT is always the word "chat"
+            "CHAT" if fields.len() >= 2 => Some(DccRequest::Chat {
+                address: parse_address(fields[0])?,
+                port: fields[1].parse().ok()?,
+                token: token(2),
+            }),
+            "SEND" if fields.len() >= 2 => Some(DccRequest::Send {
+                file: argument,
+                address: parse_address(fields[0])?,
+                port: fields[1].parse().ok()?,
+                // Some clients leave out the size of files they stream
+                size: fields.get(2).and_then(|size| size.parse().ok()).unwrap_or(0),
+                token: token(3),
+            }),
+            "RESUME" if fields.len() >= 2 => Some(DccRequest::Resume {
+                file: argument,
+                port: fields[0].parse().ok()?,
+                position: fields[1].parse().ok()?,
+                token: token(2),
+            }),
+            "ACCEPT" if fields.len() >= 2 => Some(DccRequest::Accept {
+                file: argument,
+                port: fields[0].parse().ok()?,
+                position: fields[1].parse().ok()?,
+                token: token(2),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The parameters to send after `DCC`.
+    pub fn encode(&self) -> String {
+        let with_token = |text: String, token: &Option<String>| match token {
+            Some(token) => format!("{text} {token}"),
+            None => text,
+        };
+        match self {
+            DccRequest::Chat { address, port, token } => with_token(format!("CHAT chat {} {port}", format_address(*address)), token),
+            DccRequest::Send {
+                file,
+                address,
+                port,
+                size,
+                token,
+            } => with_token(format!("SEND {} {} {port} {size}", quote(file), format_address(*address)), token),
+            DccRequest::Resume { file, port, position, token } => with_token(format!("RESUME {} {port} {position}", quote(file)), token),
+            DccRequest::Accept { file, port, position, token } => with_token(format!("ACCEPT {} {port} {position}", quote(file)), token),
+        }
+    }
+}
+
+/// Takes the first argument, a file name that is quoted when it has spaces.
+fn split_argument(text: &str) -> Option<(String, &str)> {
+    let text = text.trim_start();
+    if let Some(quoted) = text.strip_prefix('"') {
+        let (argument, rest) = quoted.split_once('"')?;
+        Some((argument.to_string(), rest))
+    } else {
+        let (argument, rest) = text.split_once(' ').unwrap_or((text, ""));
+        Some((argument.to_string(), rest))
+    }
+}
+
+fn quote(file: &str) -> String {
+    if file.contains(' ') {
+        format!("\"{file}\"")
+    } else {
+        file.to_string()
+    }
+}
+
+/// IPv4 addresses travel as one decimal number, IPv6 ones as they are written.
+fn parse_address(text: &str) -> Option<IpAddr> {
+    match text.parse::<u32>() {
+        Ok(number) => Some(IpAddr::V4(Ipv4Addr::from(number))),
+        Err(_) => text.parse().ok(),
+    }
+}
+
+fn format_address(address: IpAddr) -> String {
+    match address {
+        IpAddr::V4(address) => u32::from(address).to_string(),
+        IpAddr::V6(address) => address.to_string(),
+    }
+}
+
+/// The part of an offered file name that is safe to save under, None when nothing is left.
+pub fn safe_file_name(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next()?.trim();
+    let name: String = name.chars().filter(|ch| !ch.is_control()).collect();
+    if name.is_empty() || name.chars().all(|ch| ch == '.') {
+        return None;
+    }
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_offers() {
+        assert_eq!(
+            DccRequest::parse("CHAT chat 2130706433 5000"),
+            Some(DccRequest::Chat {
+                address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                port: 5000,
+                token: None,
+            })
+        );
+        assert_eq!(
+            DccRequest::parse("SEND \"my logs.txt\" 3232235777 0 1024 7"),
+            Some(DccRequest::Send {
+                file: "my logs.txt".into(),
+                address: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+                port: 0,
+                size: 1024,
+                token: Some("7".into()),
+            })
+        );
+        assert_eq!(
+            DccRequest::parse("SEND a.log ::1 4000 10"),
+            Some(DccRequest::Send {
+                file: "a.log".into(),
+                address: "::1".parse().unwrap(),
+                port: 4000,
+                size: 10,
+                token: None,
+            })
+        );
+        assert_eq!(DccRequest::parse("SEND a.log nowhere 4000 10"), None);
+        assert_eq!(DccRequest::parse("FOO bar"), None);
+    }
+
+    #[test]
+    fn round_trips() {
+        let requests = [
+            DccRequest::Chat {
+                address: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+                port: 1234,
+                token: None,
+            },
+            DccRequest::Send {
+                file: "with space.tar".into(),
+                address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                port: 0,
+                size: 99,
+                token: Some("3".into()),
+            },
+            DccRequest::Resume {
+                file: "a.log".into(),
+                port: 4000,
+                position: 512,
+                token: None,
+            },
+            DccRequest::Accept {
+                file: "a.log".into(),
+                port: 0,
+                position: 512,
+                token: Some("3".into()),
+            },
+        ];
+        for request in requests {
+            assert_eq!(DccRequest::parse(&request.encode()), Some(request));
+        }
+    }
+
+    #[test]
+    fn file_names_cannot_leave_the_download_directory() {
+        assert_eq!(safe_file_name("../../.bashrc"), Some(".bashrc".into()));
+        assert_eq!(safe_file_name("C:\\temp\\x.log"), Some("x.log".into()));
+        assert_eq!(safe_file_name(".."), None);
+        assert_eq!(safe_file_name("dir/"), None);
+    }
+}
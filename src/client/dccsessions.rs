@@ -0,0 +1,1125 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use mio::net::TcpStream;
+use mio::{Registry, Token};
+
+use crate::client::ctcp;
+use crate::client::dcc::{safe_file_name, DccRequest};
+use crate::client::event::ClientEvent;
+use crate::client::framer::LineFramer;
+use crate::client::reactor::Reactor;
+
+/// How long connecting to the other side of a DCC may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bytes read from a file or a socket at once.
+const CHUNK_LEN: usize = 16 * 1024;
+
+const MAX_CHAT_LINE_LEN: usize = 4096;
+
+/// Progress is reported at most this often, state changes right away.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DccPolicy {
+    /// Where received files are saved.
+    pub download_dir: PathBuf,
+    /// Address put in our offers instead of the one the server connection goes out from, for
+    /// when we are behind NAT.
+    pub address: Option<IpAddr>,
+    /// Offer files passively, asking the other side to listen, when others cannot reach us.
+    pub passive: bool,
+}
+
+impl Default for DccPolicy {
+    fn default() -> Self {
+        Self {
+            download_dir: std::env::var("HOME").map_or_else(|_| PathBuf::from("."), |home| Path::new(&home).join("Downloads")),
+            address: None,
+            passive: false,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransferState {
+    /// Waiting for the other side, or for us to accept an incoming offer.
+    Offered,
+    /// Asked to continue a partial download, waiting for the ACCEPT.
+    Resuming,
+    /// Listening for the other side to connect, or connecting to it.
+    Waiting,
+    Active,
+    Done,
+    Failed(String),
+}
+
+/// The socket of a session: listening for the other side, connecting to it, or connected.
+#[derive(Debug)]
+enum Link {
+    Closed,
+    Listening(TcpListener),
+    /// A connect in progress and when it has to be done by.
+    Connecting(TcpStream, Instant),
+    Connected(TcpStream),
+}
+
+impl Link {
+    /// Listens on a free port of every interface, for an address of the same family as `address`.
+    fn listen(address: IpAddr, reactor: &Reactor) -> io::Result<(Link, u16)> {
+        let any = match address {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let listener = TcpListener::bind((any, 0))?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+        reactor.register(&listener)?;
+        Ok((Link::Listening(listener), port))
+    }
+
+    /// Starts connecting without waiting for the other side, [`Link::ready`] tells when it answered.
+    fn connect(address: IpAddr, port: u16, reactor: &Reactor) -> io::Result<Link> {
+        let stream = TcpStream::connect(SocketAddr::new(address, port))?;
+        reactor.register(&stream)?;
+        Ok(Link::Connecting(stream, Instant::now() + CONNECT_TIMEOUT))
+    }
+
+    /// Takes the connection a listener is waiting for or finishes a connect, true once connected.
+    fn ready(&mut self, reactor: &Reactor) -> io::Result<bool> {
+        match self {
+            Link::Closed => Ok(false),
+            Link::Connected(_) => Ok(true),
+            Link::Listening(listener) => match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    let stream = TcpStream::from_std(stream);
+                    reactor.register(&stream)?;
+                    reactor.deregister(listener);
+                    *self = Link::Connected(stream);
+                    Ok(true)
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+                Err(e) => Err(e),
+            },
+            Link::Connecting(stream, deadline) => {
+                if let Some(e) = stream.take_error()? {
+                    return Err(e);
+                }
+                match stream.peer_addr() {
+                    Ok(_) => {
+                        let Link::Connecting(stream, _) = std::mem::replace(self, Link::Closed) else {
+                            unreachable!();
+                        };
+                        *self = Link::Connected(stream);
+                        Ok(true)
+                    }
+                    Err(e) if e.kind() == ErrorKind::NotConnected && Instant::now() < *deadline => Ok(false),
+                    Err(e) if e.kind() == ErrorKind::NotConnected => Err(ErrorKind::TimedOut.into()),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+
+    /// When a connect in progress times out.
+    fn deadline(&self) -> Option<Instant> {
+        match self {
+            Link::Connecting(_, deadline) => Some(*deadline),
+            _ => None,
+        }
+    }
+
+    fn stream(&mut self) -> Option<&mut TcpStream> {
+        match self {
+            Link::Connected(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    fn close(&mut self, reactor: &Reactor) {
+        match self {
+            Link::Listening(listener) => reactor.deregister(listener),
+            Link::Connecting(stream, _) | Link::Connected(stream) => reactor.deregister(stream),
+            Link::Closed => {}
+        }
+        *self = Link::Closed;
+    }
+}
+
+/// A file sent or received over DCC SEND.
+#[derive(Debug)]
+pub struct Transfer {
+    id: usize,
+    nick: String,
+    /// Name of the file in the offer.
+    file: String,
+    path: PathBuf,
+    direction: Direction,
+    /// Size from the offer, 0 when the sender did not know it.
+    size: u64,
+    /// Where the transfer started, past what a resumed download already had.
+    start: u64,
+    /// Bytes of the file sent or saved so far, the resumed part included.
+    position: u64,
+    /// Where the other side listens, for offers we connect to.
+    address: IpAddr,
+    /// The port of the offer, 0 when it is passive.
+    port: u16,
+    token: Option<String>,
+    state: TransferState,
+    link: Link,
+    disk: Option<File>,
+    /// Read from the file but not taken by the socket yet.
+    pending: Vec<u8>,
+    /// Acknowledgements of the receiver, 32-bit counts of the bytes it got.
+    acks: Vec<u8>,
+    acked: u32,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+}
+
+impl Transfer {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn state(&self) -> &TransferState {
+        &self.state
+    }
+
+    /// Average speed in bytes per second since the data started flowing.
+    pub fn rate(&self, now: Instant) -> Option<f64> {
+        let elapsed = self.finished.unwrap_or(now).saturating_duration_since(self.started?).as_secs_f64();
+        (elapsed > 0.0).then(|| (self.position - self.start) as f64 / elapsed)
+    }
+
+    fn is_open(&self) -> bool {
+        !matches!(self.state, TransferState::Done | TransferState::Failed(_))
+    }
+
+    /// Answers a RESUME or ACCEPT naming this transfer by its port, or by its token when passive.
+    fn matches(&self, port: u16, token: &Option<String>) -> bool {
+        if port != 0 {
+            self.port == port
+        } else {
+            token.is_some() && self.token == *token
+        }
+    }
+
+    /// The connection is up: open the file and start moving data.
+    fn begin(&mut self) -> io::Result<()> {
+        let disk = match self.direction {
+            Direction::Send => {
+                let mut file = File::open(&self.path)?;
+                file.seek(SeekFrom::Start(self.start))?;
+                file
+            }
+            Direction::Receive if self.start > 0 => OpenOptions::new().append(true).open(&self.path)?,
+            Direction::Receive => {
+                if let Some(dir) = self.path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                File::create(&self.path)?
+            }
+        };
+        self.disk = Some(disk);
+        self.position = self.start;
+        self.state = TransferState::Active;
+        self.started = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Moves what the sockets allow, returning true when anything moved.
+    fn poll(&mut self, reactor: &Reactor) -> Result<bool, String> {
+        if !matches!(self.state, TransferState::Waiting | TransferState::Active) {
+            return Ok(false);
+        }
+        if !self.link.ready(reactor).map_err(|e| e.to_string())? {
+            return Ok(false);
+        }
+        if self.state == TransferState::Waiting {
+            self.begin().map_err(|e| e.to_string())?;
+        }
+        let position = self.position;
+        match self.direction {
+            Direction::Send => self.send_data()?,
+            Direction::Receive => self.receive_data()?,
+        }
+        Ok(self.position != position || !self.is_open())
+    }
+
+    fn send_data(&mut self) -> Result<(), String> {
+        let (Some(stream), Some(disk)) = (self.link.stream(), self.disk.as_mut()) else {
+            return Ok(());
+        };
+        let mut closed = false;
+        loop {
+            if self.pending.is_empty() {
+                if self.position >= self.size {
+                    break;
+                }
+                let mut chunk = vec![0; CHUNK_LEN];
+                let len = disk.read(&mut chunk).map_err(|e| e.to_string())?;
+                if len == 0 {
+                    return Err("The file got shorter while sending it".into());
+                }
+                chunk.truncate(len);
+                self.pending = chunk;
+            }
+            match stream.write(&self.pending) {
+                Ok(0) => return Err("Connection closed".into()),
+                Ok(len) => {
+                    self.pending.drain(..len);
+                    self.position += len as u64;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+
+        let mut buffer = [0; 64];
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(len) => self.acks.extend_from_slice(&buffer[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        while self.acks.len() >= 4 {
+            let ack: Vec<u8> = self.acks.drain(..4).collect();
+            self.acked = u32::from_be_bytes([ack[0], ack[1], ack[2], ack[3]]);
+        }
+
+        let sent = self.position >= self.size && self.pending.is_empty();
+        // The count wraps for files past 4 GiB, only its low 32 bits can be compared
+        if sent && (closed || self.acked == self.size as u32) {
+            self.state = TransferState::Done;
+        } else if closed {
+            return Err(format!("Closed by {} after {}", self.nick, format_size(self.position)));
+        }
+        Ok(())
+    }
+
+    fn receive_data(&mut self) -> Result<(), String> {
+        let (Some(stream), Some(disk)) = (self.link.stream(), self.disk.as_mut()) else {
+            return Ok(());
+        };
+        let mut buffer = vec![0; CHUNK_LEN];
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) if self.size == 0 || self.position >= self.size => {
+                    self.state = TransferState::Done;
+                    break;
+                }
+                Ok(0) => return Err(format!("Closed by {} after {}", self.nick, format_size(self.position))),
+                Ok(len) => {
+                    disk.write_all(&buffer[..len]).map_err(|e| e.to_string())?;
+                    self.position += len as u64;
+                    // Acknowledgements are only a courtesy to senders waiting for them
+                    let _ = stream.write(&(self.position as u32).to_be_bytes());
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        if self.size > 0 && self.position >= self.size {
+            self.state = TransferState::Done;
+        }
+        Ok(())
+    }
+
+    /// Like `logs.txt to bob`, for messages about the transfer.
+    fn describe(&self) -> String {
+        match self.direction {
+            Direction::Send => format!("{} to {}", self.file, self.nick),
+            Direction::Receive => format!("{} from {}", self.file, self.nick),
+        }
+    }
+
+    fn finish(&mut self, state: TransferState, reactor: &Reactor) {
+        self.link.close(reactor);
+        self.disk = None;
+        self.pending.clear();
+        self.state = state;
+        self.finished = Some(Instant::now());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatState {
+    /// Someone offered us a chat, not accepted yet.
+    Offered,
+    /// We offered a chat and listen for the other side, or connect to the chat it offered.
+    Waiting,
+    Open,
+    Closed,
+}
+
+/// A DCC CHAT, lines of text going straight to the other client.
+#[derive(Debug)]
+pub struct Chat {
+    id: usize,
+    nick: String,
+    address: IpAddr,
+    port: u16,
+    state: ChatState,
+    link: Link,
+    framer: LineFramer,
+    /// Lines the socket did not take yet.
+    outgoing: Vec<u8>,
+}
+
+impl Chat {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    pub fn state(&self) -> &ChatState {
+        &self.state
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let Some(stream) = self.link.stream() else {
+            return Ok(());
+        };
+        while !self.outgoing.is_empty() {
+            match stream.write(&self.outgoing) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(len) => {
+                    self.outgoing.drain(..len);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads and sends what the socket allows, returning the lines that arrived or why the chat ended.
+    fn poll(&mut self, reactor: &Reactor, events: &mut Vec<ClientEvent>) -> Result<(), String> {
+        if !matches!(self.state, ChatState::Waiting | ChatState::Open) {
+            return Ok(());
+        }
+        if !self.link.ready(reactor).map_err(|e| e.to_string())? {
+            return Ok(());
+        }
+        if self.state == ChatState::Waiting {
+            self.state = ChatState::Open;
+            events.push(ClientEvent::DccChatOpened { nick: self.nick.clone() });
+        }
+        let Some(stream) = self.link.stream() else {
+            return Ok(());
+        };
+        let mut closed = false;
+        loop {
+            match self.framer.read_from(stream) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        while let Some(line) = self.framer.next_line() {
+            let Ok(line) = line else {
+                continue;
+            };
+            let text = String::from_utf8_lossy(&line).to_string();
+            let event = match ctcp::decode(&text) {
+                Some((command, text)) if command == "ACTION" => ClientEvent::DccChat {
+                    nick: self.nick.clone(),
+                    text,
+                    action: true,
+                },
+                Some(_) => continue,
+                None => ClientEvent::DccChat {
+                    nick: self.nick.clone(),
+                    text,
+                    action: false,
+                },
+            };
+            events.push(event);
+        }
+        if closed {
+            return Err(format!("Closed by {}", self.nick));
+        }
+        self.flush().map_err(|e| e.to_string())
+    }
+}
+
+/// Every DCC chat and file transfer, offered, running or finished.
+///
+/// Offers travel as CTCP messages through the server and are answered by the [`Client`], the
+/// data goes over direct connections registered with the same event loop as the server.
+///
+/// [`Client`]: crate::client::Client
+pub struct DccSessions {
+    policy: DccPolicy,
+    reactor: Reactor,
+    next_id: usize,
+    chats: Vec<Chat>,
+    transfers: Vec<Transfer>,
+    events: Vec<ClientEvent>,
+    /// A transfer moved data since the last [`ClientEvent::DccTransfers`].
+    progressed: bool,
+    reported: Instant,
+}
+
+impl DccSessions {
+    pub fn new(policy: DccPolicy) -> Self {
+        Self {
+            policy,
+            reactor: Reactor::default(),
+            next_id: 0,
+            chats: Vec::new(),
+            transfers: Vec::new(),
+            events: Vec::new(),
+            progressed: false,
+            reported: Instant::now(),
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: DccPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn set_reactor(&mut self, registry: Registry, token: Token) {
+        self.reactor = Reactor::new(registry, token);
+    }
+
+    pub fn passive(&self) -> bool {
+        self.policy.passive
+    }
+
+    pub fn chats(&self) -> &[Chat] {
+        &self.chats
+    }
+
+    pub fn transfers(&self) -> &[Transfer] {
+        &self.transfers
+    }
+
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// The address to put in an offer, `local` being where the server connection goes out from.
+    fn address(&self, local: IpAddr) -> IpAddr {
+        self.policy.address.unwrap_or(local)
+    }
+
+    fn changed(&mut self) {
+        self.events.push(ClientEvent::DccTransfers);
+        self.progressed = false;
+        self.reported = Instant::now();
+    }
+
+    /// Listens for `nick` to connect and returns the offer to send.
+    pub fn offer_chat(&mut self, nick: &str, local: IpAddr) -> Result<DccRequest, String> {
+        let address = self.address(local);
+        let (link, port) = Link::listen(address, &self.reactor).map_err(|e| e.to_string())?;
+        let id = self.next_id();
+        self.chats.push(Chat {
+            id,
+            nick: nick.to_string(),
+            address,
+            port,
+            state: ChatState::Waiting,
+            link,
+            framer: LineFramer::new(MAX_CHAT_LINE_LEN),
+            outgoing: Vec::new(),
+        });
+        self.changed();
+        Ok(DccRequest::Chat { address, port, token: None })
+    }
+
+    /// Offers the file at `path` to `nick` and returns the offer to send. When passive the other
+    /// side is asked to listen and we connect once it answers.
+    pub fn offer_file(&mut self, nick: &str, path: &Path, local: IpAddr, passive: bool) -> Result<DccRequest, String> {
+        let metadata = fs::metadata(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        if !metadata.is_file() {
+            return Err(format!("{} is not a file", path.display()));
+        }
+        let file = path.file_name().map(|name| name.to_string_lossy().to_string()).ok_or("Not a file name")?;
+        let address = self.address(local);
+        let id = self.next_id();
+        let (link, port, token, state) = if passive {
+            (Link::Closed, 0, Some(id.to_string()), TransferState::Offered)
+        } else {
+            let (link, port) = Link::listen(address, &self.reactor).map_err(|e| e.to_string())?;
+            (link, port, None, TransferState::Waiting)
+        };
+        self.transfers.push(Transfer {
+            id,
+            nick: nick.to_string(),
+            file: file.clone(),
+            path: path.to_path_buf(),
+            direction: Direction::Send,
+            size: metadata.len(),
+            start: 0,
+            position: 0,
+            address,
+            port,
+            token: token.clone(),
+            state,
+            link,
+            disk: None,
+            pending: Vec::new(),
+            acks: Vec::new(),
+            acked: 0,
+            started: None,
+            finished: None,
+        });
+        self.changed();
+        Ok(DccRequest::Send {
+            file,
+            address,
+            port,
+            size: metadata.len(),
+            token,
+        })
+    }
+
+    /// Handles a DCC request from `nick`, returning the answer to send if there is one.
+    pub fn request(&mut self, nick: &str, request: DccRequest, local: IpAddr) -> Option<DccRequest> {
+        match request {
+            DccRequest::Chat { address, port, .. } => {
+                let id = self.next_id();
+                self.chats.push(Chat {
+                    id,
+                    nick: nick.to_string(),
+                    address,
+                    port,
+                    state: ChatState::Offered,
+                    link: Link::Closed,
+                    framer: LineFramer::new(MAX_CHAT_LINE_LEN),
+                    outgoing: Vec::new(),
+                });
+                self.info(format!("DCC: {nick} offers a chat, /dcc chat {nick} to accept it"));
+                self.changed();
+                None
+            }
+            // The answer to our passive offer: the other side listens now
+            DccRequest::Send { address, port, token, .. } if port != 0 && self.find_offer(nick, Direction::Send, 0, &token).is_some() => {
+                let index = self.find_offer(nick, Direction::Send, 0, &token)?;
+                match Link::connect(address, port, &self.reactor) {
+                    Ok(link) => {
+                        let transfer = &mut self.transfers[index];
+                        transfer.link = link;
+                        transfer.state = TransferState::Waiting;
+                    }
+                    Err(e) => self.fail(index, e.to_string()),
+                }
+                self.changed();
+                None
+            }
+            DccRequest::Send {
+                file,
+                address,
+                port,
+                size,
+                token,
+            } => {
+                let Some(name) = safe_file_name(&file) else {
+                    self.info(format!("DCC: ignored a file from {nick} with the unusable name {file}"));
+                    return None;
+                };
+                let id = self.next_id();
+                self.transfers.push(Transfer {
+                    id,
+                    nick: nick.to_string(),
+                    file: name.clone(),
+                    path: self.policy.download_dir.join(&name),
+                    direction: Direction::Receive,
+                    size,
+                    start: 0,
+                    position: 0,
+                    address,
+                    port,
+                    token,
+                    state: TransferState::Offered,
+                    link: Link::Closed,
+                    disk: None,
+                    pending: Vec::new(),
+                    acks: Vec::new(),
+                    acked: 0,
+                    started: None,
+                    finished: None,
+                });
+                self.info(format!("DCC: {nick} offers {name} ({}), /dcc get {id} to download it", format_size(size)));
+                self.changed();
+                None
+            }
+            DccRequest::Resume { port, position, token, .. } => {
+                let index = self.find_offer(nick, Direction::Send, port, &token)?;
+                let transfer = &mut self.transfers[index];
+                transfer.start = position.min(transfer.size);
+                transfer.position = transfer.start;
+                let accept = DccRequest::Accept {
+                    file: transfer.file.clone(),
+                    port,
+                    position: transfer.start,
+                    token,
+                };
+                self.info(format!("DCC: {nick} resumes {} from {}", self.transfers[index].file, format_size(position)));
+                self.changed();
+                Some(accept)
+            }
+            DccRequest::Accept { port, token, .. } => {
+                let index = self.transfers.iter().position(|transfer| {
+                    transfer.state == TransferState::Resuming && transfer.nick.eq_ignore_ascii_case(nick) && transfer.matches(port, &token)
+                })?;
+                self.start_receive(index, local)
+            }
+        }
+    }
+
+    /// An offer of ours to `nick` still waiting for an answer, or still listening.
+    fn find_offer(&self, nick: &str, direction: Direction, port: u16, token: &Option<String>) -> Option<usize> {
+        self.transfers.iter().position(|transfer| {
+            transfer.direction == direction
+                && transfer.nick.eq_ignore_ascii_case(nick)
+                && matches!(transfer.state, TransferState::Offered | TransferState::Waiting)
+                && transfer.matches(port, token)
+        })
+    }
+
+    /// Accepts a file offered to us, picking up a partial download where it stopped. Returns the
+    /// request to send to the other side, if any.
+    pub fn get(&mut self, id: usize, local: IpAddr) -> Result<Option<DccRequest>, String> {
+        let index = self
+            .transfers
+            .iter()
+            .position(|transfer| transfer.id == id && transfer.direction == Direction::Receive)
+            .ok_or(format!("No file offer {id}"))?;
+        let transfer = &mut self.transfers[index];
+        if transfer.state != TransferState::Offered {
+            return Err(format!("Transfer {id} was already accepted"));
+        }
+        let existing = fs::metadata(&transfer.path).map(|metadata| metadata.len()).ok();
+        match existing {
+            Some(len) if len > 0 && len < transfer.size => {
+                transfer.start = len;
+                transfer.position = len;
+                transfer.state = TransferState::Resuming;
+                let resume = DccRequest::Resume {
+                    file: transfer.file.clone(),
+                    port: transfer.port,
+                    position: len,
+                    token: transfer.token.clone(),
+                };
+                self.changed();
+                Ok(Some(resume))
+            }
+            Some(_) => {
+                transfer.path = free_path(&transfer.path);
+                Ok(self.start_receive(index, local))
+            }
+            None => Ok(self.start_receive(index, local)),
+        }
+    }
+
+    /// Connects to the sender, or for a passive offer listens and returns the answer telling it where.
+    fn start_receive(&mut self, index: usize, local: IpAddr) -> Option<DccRequest> {
+        let address = self.address(local);
+        let transfer = &mut self.transfers[index];
+        let result = if transfer.port == 0 {
+            Link::listen(address, &self.reactor).map(|(link, port)| {
+                transfer.link = link;
+                transfer.state = TransferState::Waiting;
+                Some(DccRequest::Send {
+                    file: transfer.file.clone(),
+                    address,
+                    port,
+                    size: transfer.size,
+                    token: transfer.token.clone(),
+                })
+            })
+        } else {
+            Link::connect(transfer.address, transfer.port, &self.reactor).map(|link| {
+                transfer.link = link;
+                transfer.state = TransferState::Waiting;
+                None
+            })
+        };
+        self.changed();
+        result.unwrap_or_else(|e| {
+            self.fail(index, e.to_string());
+            None
+        })
+    }
+
+    /// Starts connecting to a chat `nick` offered, which opens once the other side answers.
+    /// Returns false when there is no such offer.
+    pub fn accept_chat(&mut self, nick: &str) -> Result<bool, String> {
+        let Some(chat) = self
+            .chats
+            .iter_mut()
+            .rev()
+            .find(|chat| chat.state == ChatState::Offered && chat.nick.eq_ignore_ascii_case(nick))
+        else {
+            return Ok(false);
+        };
+        match Link::connect(chat.address, chat.port, &self.reactor) {
+            Ok(link) => {
+                chat.link = link;
+                chat.state = ChatState::Waiting;
+                self.changed();
+                Ok(true)
+            }
+            Err(e) => {
+                chat.state = ChatState::Closed;
+                self.changed();
+                Err(format!("Could not connect to {nick}: {e}"))
+            }
+        }
+    }
+
+    /// Sends a line, or a CTCP ACTION, over the open chat with `nick`.
+    pub fn say(&mut self, nick: &str, text: &str, action: bool) -> Result<(), String> {
+        let chat = self
+            .chats
+            .iter_mut()
+            .rev()
+            .find(|chat| chat.state == ChatState::Open && chat.nick.eq_ignore_ascii_case(nick))
+            .ok_or(format!("No DCC chat with {nick} is open"))?;
+        for line in text.lines() {
+            let line = if action { ctcp::encode("ACTION", line) } else { line.to_string() };
+            chat.outgoing.extend_from_slice(line.as_bytes());
+            chat.outgoing.push(b'\n');
+        }
+        chat.flush().map_err(|e| e.to_string())
+    }
+
+    /// Closes the chat with `nick`.
+    pub fn close_chat(&mut self, nick: &str) -> bool {
+        match self
+            .chats
+            .iter()
+            .rposition(|chat| chat.state != ChatState::Closed && chat.nick.eq_ignore_ascii_case(nick))
+        {
+            Some(index) => self.close(self.chats[index].id),
+            None => false,
+        }
+    }
+
+    /// Closes a chat or stops a transfer, false when `id` names neither.
+    pub fn close(&mut self, id: usize) -> bool {
+        if let Some(chat) = self.chats.iter_mut().find(|chat| chat.id == id && chat.state != ChatState::Closed) {
+            let _ = chat.flush();
+            chat.link.close(&self.reactor);
+            chat.state = ChatState::Closed;
+            let nick = chat.nick.clone();
+            self.events.push(ClientEvent::DccChatClosed { nick, reason: "Closed".into() });
+        } else if let Some(transfer) = self.transfers.iter_mut().find(|transfer| transfer.id == id && transfer.is_open()) {
+            transfer.finish(TransferState::Failed("Cancelled".into()), &self.reactor);
+        } else {
+            return false;
+        }
+        self.changed();
+        true
+    }
+
+    fn fail(&mut self, index: usize, reason: String) {
+        let transfer = &mut self.transfers[index];
+        transfer.finish(TransferState::Failed(reason.clone()), &self.reactor);
+        let text = format!("DCC: {} failed: {reason}", transfer.describe());
+        self.info(text);
+    }
+
+    fn info(&mut self, text: String) {
+        self.events.push(ClientEvent::Info(text));
+    }
+
+    /// When a transfer has progress to report.
+    fn next_report(&self) -> Option<Instant> {
+        self.progressed.then_some(self.reported + PROGRESS_INTERVAL)
+    }
+
+    /// When the event loop has to wake up: to report progress or to give up on a connect.
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        let links = self
+            .chats
+            .iter()
+            .map(|chat| &chat.link)
+            .chain(self.transfers.iter().map(|transfer| &transfer.link));
+        links.filter_map(Link::deadline).chain(self.next_report()).min()
+    }
+
+    /// Moves data on every session and returns what happened.
+    pub fn poll(&mut self) -> Vec<ClientEvent> {
+        let mut events = std::mem::take(&mut self.events);
+        for chat in &mut self.chats {
+            if let Err(reason) = chat.poll(&self.reactor, &mut events) {
+                chat.link.close(&self.reactor);
+                // A chat that never opened has no buffer to tell
+                let event = if chat.state == ChatState::Open {
+                    ClientEvent::DccChatClosed {
+                        nick: chat.nick.clone(),
+                        reason,
+                    }
+                } else {
+                    ClientEvent::Info(format!("DCC: chat with {} failed: {reason}", chat.nick))
+                };
+                chat.state = ChatState::Closed;
+                events.push(event);
+            }
+        }
+        let mut finished = false;
+        for index in 0..self.transfers.len() {
+            match self.transfers[index].poll(&self.reactor) {
+                Ok(moved) => self.progressed |= moved,
+                Err(reason) => {
+                    self.fail(index, reason);
+                    finished = true;
+                }
+            }
+            let transfer = &mut self.transfers[index];
+            if transfer.state == TransferState::Done && transfer.finished.is_none() {
+                transfer.finish(TransferState::Done, &self.reactor);
+                let text = format!(
+                    "DCC: {} done, {} at {}/s",
+                    transfer.describe(),
+                    format_size(transfer.position),
+                    format_size(transfer.rate(Instant::now()).unwrap_or_default() as u64)
+                );
+                self.events.push(ClientEvent::Info(text));
+                finished = true;
+            }
+        }
+        if finished || self.next_report().is_some_and(|at| at <= Instant::now()) {
+            self.changed();
+        }
+        events.append(&mut self.events);
+        events
+    }
+}
+
+/// `path`, or `path.1`, `path.2`... when it is taken.
+fn free_path(path: &Path) -> PathBuf {
+    let mut candidate = path.to_path_buf();
+    let mut number = 0;
+    while candidate.exists() {
+        number += 1;
+        let mut name = path.as_os_str().to_os_string();
+        name.push(format!(".{number}"));
+        candidate = PathBuf::from(name);
+    }
+    candidate
+}
+
+/// A size in bytes for people, like `1.5 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    /// A fresh directory under the system temp dir, for one test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("crust-dcc-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn sessions(download_dir: PathBuf) -> DccSessions {
+        DccSessions::new(DccPolicy {
+            download_dir,
+            address: None,
+            passive: false,
+        })
+    }
+
+    /// Polls both sides until `done` holds.
+    fn run(a: &mut DccSessions, b: &mut DccSessions, done: impl Fn(&DccSessions, &DccSessions) -> bool) {
+        let start = Instant::now();
+        while !done(a, b) {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+            a.poll();
+            b.poll();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    fn finished(sessions: &DccSessions) -> bool {
+        sessions.transfers().iter().all(|transfer| !transfer.is_open())
+    }
+
+    #[test]
+    fn sends_a_file_over_loopback() {
+        let dir = temp_dir("active");
+        let source = dir.join("logs.txt");
+        fs::write(&source, data(200_000)).unwrap();
+        let mut alice = sessions(dir.join("alice"));
+        let mut bob = sessions(dir.join("bob"));
+
+        let offer = alice.offer_file("bob", &source, LOCALHOST, false).unwrap();
+        assert!(bob.request("alice", offer, LOCALHOST).is_none());
+        let id = bob.transfers()[0].id();
+        assert_eq!(bob.get(id, LOCALHOST), Ok(None));
+        run(&mut alice, &mut bob, |a, b| finished(a) && finished(b));
+
+        assert_eq!(alice.transfers()[0].state(), &TransferState::Done);
+        assert_eq!(bob.transfers()[0].state(), &TransferState::Done);
+        assert_eq!(fs::read(dir.join("bob").join("logs.txt")).unwrap(), data(200_000));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn resumes_a_passive_offer() {
+        let dir = temp_dir("passive");
+        let source = dir.join("big.log");
+        fs::write(&source, data(100_000)).unwrap();
+        fs::create_dir_all(dir.join("bob")).unwrap();
+        fs::write(dir.join("bob").join("big.log"), &data(100_000)[..30_000]).unwrap();
+        let mut alice = sessions(dir.join("alice"));
+        let mut bob = sessions(dir.join("bob"));
+
+        let offer = alice.offer_file("bob", &source, LOCALHOST, true).unwrap();
+        assert!(matches!(offer, DccRequest::Send { port: 0, token: Some(_), .. }));
+        bob.request("alice", offer, LOCALHOST);
+        let id = bob.transfers()[0].id();
+        let resume = bob.get(id, LOCALHOST).unwrap().unwrap();
+        assert!(matches!(resume, DccRequest::Resume { port: 0, position: 30_000, .. }));
+        let accept = alice.request("bob", resume, LOCALHOST).unwrap();
+        // Bob listens now and tells alice where
+        let listening = bob.request("alice", accept, LOCALHOST).unwrap();
+        assert!(matches!(listening, DccRequest::Send { port, .. } if port != 0));
+        assert!(alice.request("bob", listening, LOCALHOST).is_none());
+        run(&mut alice, &mut bob, |a, b| finished(a) && finished(b));
+
+        assert_eq!(bob.transfers()[0].state(), &TransferState::Done);
+        assert_eq!(alice.transfers()[0].position(), 100_000);
+        assert_eq!(fs::read(dir.join("bob").join("big.log")).unwrap(), data(100_000));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn chats_over_loopback() {
+        let dir = temp_dir("chat");
+        let mut alice = sessions(dir.clone());
+        let mut bob = sessions(dir.clone());
+
+        let offer = alice.offer_chat("bob", LOCALHOST).unwrap();
+        bob.request("alice", offer, LOCALHOST);
+        assert_eq!(bob.accept_chat("alice"), Ok(true));
+        // Connecting does not wait for alice, the chat opens on a later poll
+        assert_eq!(bob.chats()[0].state(), &ChatState::Waiting);
+        run(&mut alice, &mut bob, |a, b| {
+            a.chats()[0].state() == &ChatState::Open && b.chats()[0].state() == &ChatState::Open
+        });
+        bob.say("alice", "hello", false).unwrap();
+        alice.say("bob", "waves", true).unwrap();
+
+        let start = Instant::now();
+        let (mut to_alice, mut to_bob) = (Vec::new(), Vec::new());
+        while to_alice.is_empty() || to_bob.is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+            to_alice.extend(alice.poll().into_iter().filter(|event| matches!(event, ClientEvent::DccChat { .. })));
+            to_bob.extend(bob.poll().into_iter().filter(|event| matches!(event, ClientEvent::DccChat { .. })));
+        }
+        assert!(matches!(&to_alice[0], ClientEvent::DccChat { nick, text, action: false } if nick == "bob" && text == "hello"));
+        assert!(matches!(&to_bob[0], ClientEvent::DccChat { nick, text, action: true } if nick == "alice" && text == "waves"));
+
+        assert!(bob.close_chat("alice"));
+        run(&mut alice, &mut bob, |a, _| a.chats()[0].state() == &ChatState::Closed);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn reports_a_failed_connect_on_poll() {
+        let dir = temp_dir("refused");
+        let mut bob = sessions(dir.clone());
+        // A port nothing listens on any more
+        let port = TcpListener::bind((LOCALHOST, 0)).unwrap().local_addr().unwrap().port();
+        bob.request(
+            "alice",
+            DccRequest::Chat {
+                address: LOCALHOST,
+                port,
+                token: None,
+            },
+            LOCALHOST,
+        );
+        bob.poll();
+        assert_eq!(bob.accept_chat("alice"), Ok(true));
+
+        let start = Instant::now();
+        let mut events = Vec::new();
+        while bob.chats()[0].state() != &ChatState::Closed {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+            events.extend(bob.poll());
+        }
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, ClientEvent::Info(text) if text.starts_with("DCC: chat with alice failed"))));
+        assert!(!events.iter().any(|event| matches!(event, ClientEvent::DccChatClosed { .. })));
+        let _ = fs::remove_dir_all(dir);
+    }
+}
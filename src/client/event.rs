@@ -0,0 +1,107 @@
+use crate::client::UserInfo;
+
+/// What the client understood from the server, ready to be shown by the TUI.
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    Info(String),
+    Message {
+        from: UserInfo,
+        target: String,
+        text: String,
+    },
+    Notice {
+        from: UserInfo,
+        target: String,
+        text: String,
+    },
+    /// A CTCP ACTION, what `/me` sends.
+    Action {
+        from: UserInfo,
+        target: String,
+        text: String,
+    },
+    /// Someone sent us a CTCP request, answered if it is enabled.
+    Ctcp {
+        from: UserInfo,
+        command: String,
+        params: String,
+    },
+    /// The answer to a CTCP request we sent, with the round trip for a PING.
+    CtcpReply {
+        from: UserInfo,
+        command: String,
+        params: String,
+    },
+    /// A line of a DCC chat, a CTCP ACTION when `action`.
+    DccChat {
+        nick: String,
+        text: String,
+        action: bool,
+    },
+    DccChatOpened {
+        nick: String,
+    },
+    DccChatClosed {
+        nick: String,
+        reason: String,
+    },
+    /// A DCC transfer was offered, moved data or ended.
+    DccTransfers,
+    Join {
+        user: UserInfo,
+        channel: String,
+    },
+    Part {
+        user: UserInfo,
+        channel: String,
+        reason: String,
+    },
+    Quit {
+        user: UserInfo,
+        reason: String,
+        channels: Vec<String>,
+    },
+    Kick {
+        user: UserInfo,
+        channel: String,
+        kicked_by: UserInfo,
+        reason: String,
+    },
+    Nick {
+        user: UserInfo,
+        new_nick: String,
+        channels: Vec<String>,
+    },
+    Mode {
+        target: String,
+        mode: String,
+        changed_by: UserInfo,
+    },
+    Topic {
+        channel: String,
+        topic: String,
+        set_by: Option<UserInfo>,
+    },
+    ChannelInfo {
+        channel: String,
+        text: String,
+    },
+    /// The server accepted us with this nick.
+    Registered {
+        nick: String,
+    },
+    /// The server told us the name of its network.
+    Network {
+        name: String,
+    },
+    /// Connecting to the server failed, nothing is retried.
+    ConnectFailed {
+        reason: String,
+    },
+    /// The connection dropped, a reconnect may follow.
+    Disconnected {
+        reason: String,
+    },
+    /// Registered again after a dropped connection.
+    Reconnected,
+}
@@ -0,0 +1,264 @@
+use std::fmt;
+use std::io::{self, Read};
+
+/// Room made for every read from the socket.
+const READ_CHUNK: usize = 4096;
+
+#[derive(Debug, PartialEq)]
+pub enum FrameError {
+    /// A line went past the maximum length, its bytes are dropped up to the next line break.
+    LineTooLong { len: usize, max: usize },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::LineTooLong { len, max } => write!(f, "Dropped a line of at least {len} bytes, longer than the {max} allowed"),
+        }
+    }
+}
+
+/// Cuts the bytes read from a server into lines.
+///
+/// Lines end with `\r\n` or a bare `\n`, and empty lines are skipped. The buffer grows with what
+/// is read and shrinks as lines are taken out, but never holds more than one line of `max_len`
+/// bytes: anything longer is reported once with [`FrameError::LineTooLong`] and dropped.
+#[derive(Debug)]
+pub struct LineFramer {
+    buffer: Vec<u8>,
+    /// Start of the bytes not taken out as lines yet.
+    start: usize,
+    /// Bytes after `start` already searched for a line break.
+    scanned: usize,
+    /// True while dropping the rest of a line that was too long.
+    discarding: bool,
+    max_len: usize,
+}
+
+impl LineFramer {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            start: 0,
+            scanned: 0,
+            discarding: false,
+            max_len,
+        }
+    }
+
+    /// Drops any partial line, for a new connection.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.start = 0;
+        self.scanned = 0;
+        self.discarding = false;
+    }
+
+    /// Reads once from `reader` into the buffer and returns what `read` returned.
+    pub fn read_from(&mut self, reader: &mut impl Read) -> io::Result<usize> {
+        self.compact();
+        let len = self.buffer.len();
+        self.buffer.resize(len + READ_CHUNK, 0);
+        let result = reader.read(&mut self.buffer[len..]);
+        self.buffer.truncate(len + *result.as_ref().unwrap_or(&0));
+        result
+    }
+
+    /// The next complete line without its terminator, an error for a line that was too long, or
+    /// None until more bytes arrive.
+    pub fn next_line(&mut self) -> Option<Result<Vec<u8>, FrameError>> {
+        loop {
+            let pending = &self.buffer[self.start..];
+            let Some(position) = pending[self.scanned..].iter().position(|byte| *byte == b'\n') else {
+                return self.wait_for_more();
+            };
+            let end = self.scanned + position;
+            let line = pending[..end].strip_suffix(b"\r").unwrap_or(&pending[..end]);
+            let result = if self.discarding || line.is_empty() {
+                None
+            } else if line.len() > self.max_len {
+                Some(Err(FrameError::LineTooLong {
+                    len: line.len(),
+                    max: self.max_len,
+                }))
+            } else {
+                Some(Ok(line.to_vec()))
+            };
+            self.start += end + 1;
+            self.scanned = 0;
+            self.discarding = false;
+            if result.is_some() {
+                return result;
+            }
+        }
+    }
+
+    /// No line break in the buffer: remember how far it was searched and give up on the line if it is too long already.
+    fn wait_for_more(&mut self) -> Option<Result<Vec<u8>, FrameError>> {
+        let len = self.buffer.len() - self.start;
+        // One byte more than the maximum may be the `\r` of a line that just fits
+        if self.discarding || len > self.max_len + 1 {
+            self.start = self.buffer.len();
+            self.scanned = 0;
+            if !self.discarding {
+                self.discarding = true;
+                return Some(Err(FrameError::LineTooLong { len, max: self.max_len }));
+            }
+        } else {
+            self.scanned = len;
+        }
+        None
+    }
+
+    /// Moves the unread bytes to the front so the buffer does not grow with every line.
+    fn compact(&mut self) {
+        if self.start > 0 {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::io::ErrorKind;
+
+    const MAX: usize = 64;
+
+    /// Hands out its data like a non-blocking socket: in reads of the given sizes, each one
+    /// possibly failing first with the given error.
+    struct Socket<'a> {
+        data: &'a [u8],
+        reads: &'a [(usize, Option<ErrorKind>)],
+        next: usize,
+        failed: bool,
+    }
+
+    impl Read for Socket<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let (size, error) = self.reads[self.next % self.reads.len()];
+            if let (Some(kind), false) = (error, self.failed) {
+                self.failed = true;
+                return Err(kind.into());
+            }
+            self.failed = false;
+            self.next += 1;
+            let len = size.clamp(1, buf.len()).min(self.data.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    /// Reads `data` the way the client does and collects everything that comes out.
+    fn frame_reads(data: &[u8], reads: &[(usize, Option<ErrorKind>)]) -> Vec<Result<Vec<u8>, FrameError>> {
+        let mut framer = LineFramer::new(MAX);
+        let mut socket = Socket {
+            data,
+            reads,
+            next: 0,
+            failed: false,
+        };
+        let mut results = Vec::new();
+        loop {
+            match framer.read_from(&mut socket) {
+                Ok(0) => break,
+                Ok(_) => results.extend(std::iter::from_fn(|| framer.next_line())),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {}
+                Err(e) => panic!("{e}"),
+            }
+        }
+        results
+    }
+
+    /// Reads `data` in pieces of the given sizes.
+    fn frame(data: &[u8], chunks: &[usize]) -> Vec<Result<Vec<u8>, FrameError>> {
+        let reads: Vec<_> = chunks.iter().map(|size| (*size, None)).collect();
+        frame_reads(data, &reads)
+    }
+
+    fn reads(max_size: usize) -> impl Strategy<Value = Vec<(usize, Option<ErrorKind>)>> {
+        let error = proptest::option::of(prop_oneof![Just(ErrorKind::WouldBlock), Just(ErrorKind::Interrupted)]);
+        proptest::collection::vec((1..max_size, error), 1..10)
+    }
+
+    #[test]
+    fn splits_on_both_terminators() {
+        let lines = frame(b"PING :a\r\nPING :b\nPING :c\r\n\r\npartial", &[1000]);
+        assert_eq!(lines, vec![Ok(b"PING :a".to_vec()), Ok(b"PING :b".to_vec()), Ok(b"PING :c".to_vec())]);
+    }
+
+    #[test]
+    fn reads_lines_longer_than_a_read() {
+        let line = "x".repeat(READ_CHUNK * 3);
+        let mut framer = LineFramer::new(READ_CHUNK * 4);
+        let data = format!("{line}\r\nnext\r\n");
+        let mut reader = data.as_bytes();
+        while framer.read_from(&mut reader).unwrap() > 0 {}
+        assert_eq!(framer.next_line(), Some(Ok(line.into_bytes())));
+        assert_eq!(framer.next_line(), Some(Ok(b"next".to_vec())));
+        assert_eq!(framer.next_line(), None);
+    }
+
+    #[test]
+    fn reports_long_lines_once_and_recovers() {
+        let long = "y".repeat(MAX * 3);
+        let data = format!("before\r\n{long}\r\nafter\r\n");
+        let lines = frame(data.as_bytes(), &[7]);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], Ok(b"before".to_vec()));
+        assert!(matches!(lines[1], Err(FrameError::LineTooLong { max: MAX, .. })));
+        assert_eq!(lines[2], Ok(b"after".to_vec()));
+    }
+
+    #[test]
+    fn a_line_of_exactly_the_maximum_fits() {
+        let line = "z".repeat(MAX);
+        let lines = frame(format!("{line}\r\n").as_bytes(), &[MAX + 1, 1]);
+        assert_eq!(lines, vec![Ok(line.into_bytes())]);
+    }
+
+    fn line() -> impl Strategy<Value = Vec<u8>> {
+        proptest::collection::vec(any::<u8>().prop_filter("no line breaks", |byte| *byte != b'\r' && *byte != b'\n'), 1..=MAX)
+    }
+
+    proptest! {
+        #[test]
+        fn any_chunking_gives_the_same_lines(
+            lines in proptest::collection::vec((line(), any::<bool>()), 0..20),
+            reads in reads(40),
+        ) {
+            let mut data = Vec::new();
+            for (line, crlf) in &lines {
+                data.extend_from_slice(line);
+                data.extend_from_slice(if *crlf { b"\r\n" } else { b"\n" });
+            }
+            let expected: Vec<_> = lines.into_iter().map(|(line, _)| Ok(line)).collect();
+            prop_assert_eq!(frame_reads(&data, &reads), expected);
+        }
+
+        #[test]
+        fn long_lines_never_hide_the_others(
+            lines in proptest::collection::vec((line(), any::<bool>()), 0..20),
+            reads in reads(200),
+        ) {
+            // Every line marked true is made too long
+            let mut data = Vec::new();
+            for (line, long) in &lines {
+                let line = if *long { line.repeat(MAX * 2 / line.len() + 1) } else { line.clone() };
+                data.extend_from_slice(&line);
+                data.extend_from_slice(b"\r\n");
+            }
+            let results = frame_reads(&data, &reads);
+            prop_assert_eq!(results.len(), lines.len());
+            for ((line, long), result) in lines.into_iter().zip(results) {
+                match result {
+                    Ok(framed) => prop_assert!(!long && framed == line),
+                    Err(FrameError::LineTooLong { .. }) => prop_assert!(long),
+                }
+            }
+        }
+    }
+}
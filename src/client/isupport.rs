@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+
+use crate::client::casemapping::CaseMapping;
+use crate::client::channel::{ChannelModeKinds, PrefixModes};
+
+/// Server features announced with RPL_ISUPPORT (005).
+///
+/// Until the server sends them the values are the ones RFC 1459 servers use.
+#[derive(Debug)]
+pub struct ISupport {
+    tokens: BTreeMap<String, String>,
+    chantypes: String,
+    statusmsg: String,
+    prefix: PrefixModes,
+    chanmodes: ChannelModeKinds,
+    casemapping: CaseMapping,
+    nicklen: Option<usize>,
+    network: Option<String>,
+    /// Most targets a command takes at once, None meaning no limit.
+    targmax: BTreeMap<String, Option<usize>>,
+    monitor: bool,
+}
+
+impl Default for ISupport {
+    fn default() -> Self {
+        Self {
+            tokens: BTreeMap::new(),
+            chantypes: "#&".into(),
+            statusmsg: String::new(),
+            prefix: PrefixModes::default(),
+            chanmodes: ChannelModeKinds::default(),
+            casemapping: CaseMapping::default(),
+            nicklen: None,
+            network: None,
+            targmax: BTreeMap::new(),
+            monitor: false,
+        }
+    }
+}
+
+impl ISupport {
+    /// Applies the tokens of a 005 reply, the parameters between our nick and the trailing text.
+    pub fn apply<S: AsRef<str>>(&mut self, tokens: &[S]) {
+        for token in tokens {
+            let token = token.as_ref();
+            match token.strip_prefix('-') {
+                Some(name) => {
+                    self.tokens.remove(name);
+                    self.set(name, None);
+                }
+                None => {
+                    let (name, value) = token.split_once('=').unwrap_or((token, ""));
+                    let value = unescape(value);
+                    self.set(name, Some(&value));
+                    self.tokens.insert(name.to_string(), value);
+                }
+            }
+        }
+    }
+
+    /// Updates one feature, `None` meaning the server removed the token and its default applies again.
+    fn set(&mut self, name: &str, value: Option<&str>) {
+        let default = ISupport::default();
+        let number = |value: Option<&str>| value.and_then(|value| value.parse::<usize>().ok());
+        match name {
+            "CHANTYPES" => self.chantypes = value.map_or(default.chantypes, String::from),
+            "STATUSMSG" => self.statusmsg = value.unwrap_or_default().to_string(),
+            "PREFIX" => self.prefix = value.and_then(PrefixModes::parse).unwrap_or(default.prefix),
+            "CHANMODES" => self.chanmodes = value.and_then(ChannelModeKinds::parse).unwrap_or(default.chanmodes),
+            "CASEMAPPING" => self.casemapping = value.and_then(CaseMapping::parse).unwrap_or_default(),
+            "NICKLEN" => self.nicklen = number(value),
+            "NETWORK" => self.network = value.filter(|value| !value.is_empty()).map(String::from),
+            "TARGMAX" => {
+                self.targmax = value
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter_map(|pair| pair.split_once(':'))
+                    .map(|(command, max)| (command.to_ascii_uppercase(), max.parse().ok()))
+                    .collect()
+            }
+            "MONITOR" => self.monitor = value.is_some(),
+            _ => {}
+        }
+    }
+
+    pub fn is_channel(&self, name: &str) -> bool {
+        name.starts_with(|ch| self.chantypes.contains(ch))
+    }
+
+    /// Channel type used for names typed without one, like `/join crust`.
+    pub fn default_chantype(&self) -> char {
+        self.chantypes.chars().next().unwrap_or('#')
+    }
+
+    /// Removes the STATUSMSG prefix of targets like `@#channel`, messages sent only to the channel operators.
+    pub fn strip_statusmsg<'a>(&self, target: &'a str) -> &'a str {
+        let channel = target.trim_start_matches(|ch| self.statusmsg.contains(ch));
+        if channel.len() != target.len() && self.is_channel(channel) {
+            channel
+        } else {
+            target
+        }
+    }
+
+    pub fn prefix_modes(&self) -> &PrefixModes {
+        &self.prefix
+    }
+
+    pub fn channel_modes(&self) -> &ChannelModeKinds {
+        &self.chanmodes
+    }
+
+    pub fn casemapping(&self) -> CaseMapping {
+        self.casemapping
+    }
+
+    pub fn nicklen(&self) -> Option<usize> {
+        self.nicklen
+    }
+
+    pub fn network(&self) -> Option<&str> {
+        self.network.as_deref()
+    }
+
+    /// Most targets `command` takes at once, None meaning no limit.
+    pub fn max_targets(&self, command: &str) -> Option<usize> {
+        self.targmax.get(command).copied().flatten()
+    }
+
+    pub fn monitor(&self) -> bool {
+        self.monitor
+    }
+
+    /// Raw value of any token, including the ones without a typed accessor.
+    pub fn token(&self, name: &str) -> Option<&str> {
+        self.tokens.get(name).map(|value| value.as_str())
+    }
+}
+
+/// Decodes the `\xHH` escapes ISUPPORT values use for spaces, `=` and backslashes.
+fn unescape(value: &str) -> String {
+    let mut result = Vec::with_capacity(value.len());
+    let bytes = value.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && bytes.get(i + 1) == Some(&b'x') {
+            if let Some(byte) = value.get(i + 2..i + 4).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                result.push(byte);
+                i += 4;
+                continue;
+            }
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&result).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn isupport(tokens: &str) -> ISupport {
+        let mut isupport = ISupport::default();
+        isupport.apply(&tokens.split(' ').collect::<Vec<_>>());
+        isupport
+    }
+
+    #[test]
+    fn defaults_before_any_token() {
+        let isupport = ISupport::default();
+        assert!(isupport.is_channel("#crust"));
+        assert!(isupport.is_channel("&local"));
+        assert!(!isupport.is_channel("+modeless"));
+        assert_eq!(isupport.casemapping(), CaseMapping::Rfc1459);
+        assert_eq!(isupport.prefix_modes().symbol('o'), Some('@'));
+    }
+
+    #[test]
+    fn parses_typical_tokens() {
+        let isupport = isupport(
+            "CHANTYPES=#! PREFIX=(qaohv)~&@%+ CHANMODES=beI,k,l,imnpst CASEMAPPING=ascii NICKLEN=16 TOPICLEN=390 MODES=4 NETWORK=Libera.Chat TARGMAX=PRIVMSG:4,NOTICE:4,JOIN: STATUSMSG=@+ MONITOR=100",
+        );
+        assert!(isupport.is_channel("!crust"));
+        assert!(!isupport.is_channel("&crust"));
+        assert_eq!(isupport.default_chantype(), '#');
+        assert_eq!(isupport.prefix_modes().symbol('h'), Some('%'));
+        assert_eq!(isupport.casemapping(), CaseMapping::Ascii);
+        assert_eq!(isupport.nicklen(), Some(16));
+        assert_eq!(isupport.token("TOPICLEN"), Some("390"));
+        assert_eq!(isupport.token("MODES"), Some("4"));
+        assert_eq!(isupport.network(), Some("Libera.Chat"));
+        assert_eq!(isupport.max_targets("PRIVMSG"), Some(4));
+        assert_eq!(isupport.max_targets("JOIN"), None);
+        assert_eq!(isupport.strip_statusmsg("@#crust"), "#crust");
+        assert_eq!(isupport.strip_statusmsg("@nick"), "@nick");
+        assert!(isupport.monitor());
+        assert_eq!(isupport.token("MONITOR"), Some("100"));
+    }
+
+    #[test]
+    fn prefix_and_chanmodes_drive_mode_arguments() {
+        let isupport = isupport("PREFIX=(Yov)!@+ CHANMODES=beg,kf,lj,mnt");
+        assert_eq!(isupport.prefix_modes().symbol('Y'), Some('!'));
+        assert_eq!(isupport.prefix_modes().rank('!'), 0);
+        assert!(isupport.channel_modes().takes_argument('f', false, isupport.prefix_modes()));
+        assert!(isupport.channel_modes().takes_argument('j', true, isupport.prefix_modes()));
+        assert!(!isupport.channel_modes().takes_argument('j', false, isupport.prefix_modes()));
+        assert!(isupport.channel_modes().is_list('g'));
+    }
+
+    #[test]
+    fn removing_a_token_restores_the_default() {
+        let mut isupport = isupport("CHANTYPES=! MODES NETWORK=Test");
+        assert_eq!(isupport.token("MODES"), Some(""));
+        isupport.apply(&["-CHANTYPES", "-MODES", "-NETWORK"]);
+        assert!(isupport.is_channel("#crust"));
+        assert_eq!(isupport.token("MODES"), None);
+        assert_eq!(isupport.network(), None);
+        assert_eq!(isupport.token("NETWORK"), None);
+    }
+
+    #[test]
+    fn unescapes_values() {
+        let isupport = isupport(r"NETWORK=Some\x20Net\x3Dwork\x5C");
+        assert_eq!(isupport.network(), Some(r"Some Net=work\"));
+    }
+}
@@ -0,0 +1,158 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LagPolicy {
+    /// Quiet time after which the client checks the connection with a PING.
+    pub interval: Duration,
+    /// Wait for the PONG before the connection is considered dead.
+    pub timeout: Duration,
+}
+
+impl Default for LagPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LagCheck {
+    Fine,
+    /// Time to send `PING <token>`.
+    Ping(String),
+    /// The last PING went unanswered for longer than the timeout.
+    Dead,
+}
+
+/// Measures the round trip to the server with our own PINGs and notices when it stops answering.
+///
+/// A PING is only sent after `interval` without hearing anything from the server, so busy
+/// connections cost nothing extra.
+#[derive(Debug)]
+pub struct Lag {
+    policy: LagPolicy,
+    last_received: Instant,
+    /// Token and send time of the PING waiting for its PONG.
+    pending: Option<(String, Instant)>,
+    lag: Option<Duration>,
+    sent: u32,
+}
+
+impl Lag {
+    pub fn new(policy: LagPolicy) -> Self {
+        Self {
+            policy,
+            last_received: Instant::now(),
+            pending: None,
+            lag: None,
+            sent: 0,
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: LagPolicy) {
+        self.policy = policy;
+    }
+
+    /// Starts over for a new connection.
+    pub fn reset(&mut self, now: Instant) {
+        self.last_received = now;
+        self.pending = None;
+        self.lag = None;
+    }
+
+    /// Anything arrived from the server.
+    pub fn received(&mut self, now: Instant) {
+        self.last_received = now;
+    }
+
+    pub fn check(&mut self, now: Instant) -> LagCheck {
+        match &self.pending {
+            Some((_, sent)) if now.saturating_duration_since(*sent) >= self.policy.timeout => LagCheck::Dead,
+            Some(_) => LagCheck::Fine,
+            None if now.saturating_duration_since(self.last_received) >= self.policy.interval => {
+                self.sent = self.sent.wrapping_add(1);
+                let token = format!("crust-lag-{}", self.sent);
+                self.pending = Some((token.clone(), now));
+                LagCheck::Ping(token)
+            }
+            None => LagCheck::Fine,
+        }
+    }
+
+    /// Handles a PONG, returning false when it does not answer our PING.
+    pub fn pong(&mut self, token: &str, now: Instant) -> bool {
+        match &self.pending {
+            Some((pending, sent)) if pending == token => {
+                self.lag = Some(now.saturating_duration_since(*sent));
+                self.pending = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The last measured lag, or the time the current PING has been waiting when that is longer.
+    pub fn lag(&self, now: Instant) -> Option<Duration> {
+        let waiting = self.pending.as_ref().map(|(_, sent)| now.saturating_duration_since(*sent));
+        match (self.lag, waiting) {
+            (Some(lag), Some(waiting)) => Some(lag.max(waiting)),
+            (lag, waiting) => lag.or(waiting.filter(|waiting| *waiting >= Duration::from_secs(1))),
+        }
+    }
+
+    /// When [`Lag::check`] has something to do. While a PONG is late this is every second, so the
+    /// growing lag can be shown.
+    pub fn next_check(&self, now: Instant) -> Instant {
+        match &self.pending {
+            Some((_, sent)) => {
+                let waited = now.saturating_duration_since(*sent).as_secs();
+                (*sent + self.policy.timeout).min(*sent + Duration::from_secs(waited + 1))
+            }
+            None => self.last_received + self.policy.interval,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn pings_after_a_quiet_interval_and_measures_the_pong() {
+        let start = Instant::now();
+        let mut lag = Lag::new(LagPolicy::default());
+        lag.reset(start);
+        assert_eq!(lag.check(start + secs(10)), LagCheck::Fine);
+        lag.received(start + secs(20));
+        assert_eq!(lag.check(start + secs(40)), LagCheck::Fine);
+        assert_eq!(lag.next_check(start + secs(40)), start + secs(50));
+
+        let LagCheck::Ping(token) = lag.check(start + secs(50)) else {
+            panic!("expected a PING");
+        };
+        assert_eq!(lag.check(start + secs(51)), LagCheck::Fine);
+        assert!(!lag.pong("someone-else", start + secs(52)));
+        assert!(lag.pong(&token, start + secs(50) + Duration::from_millis(250)));
+        assert_eq!(lag.lag(start + secs(51)), Some(Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn unanswered_ping_means_dead() {
+        let start = Instant::now();
+        let mut lag = Lag::new(LagPolicy::default());
+        lag.reset(start);
+        assert!(matches!(lag.check(start + secs(30)), LagCheck::Ping(_)));
+        // Other traffic does not answer the PING
+        lag.received(start + secs(60));
+        assert_eq!(lag.lag(start + secs(75)), Some(secs(45)));
+        assert_eq!(lag.next_check(start + secs(75)), start + secs(76));
+        assert_eq!(lag.check(start + secs(89)), LagCheck::Fine);
+        assert_eq!(lag.check(start + secs(90)), LagCheck::Dead);
+    }
+}
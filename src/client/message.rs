@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    Empty,
+    MissingCommand,
+    InvalidCommand(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "Empty message"),
+            ParseError::MissingCommand => write!(f, "Message has no command"),
+            ParseError::InvalidCommand(command) => write!(f, "Invalid command \"{command}\""),
+        }
+    }
+}
+
+/// Source of a message, either a server name or a `nick!user@host` mask.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prefix {
+    pub nick: String,
+    pub user: Option<String>,
+    pub host: Option<String>,
+}
+
+impl Prefix {
+    pub fn new(nick: String, user: Option<String>, host: Option<String>) -> Self {
+        Self { nick, user, host }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        let (rest, host) = match value.split_once('@') {
+            Some((rest, host)) => (rest, Some(host.to_string())),
+            None => (value, None),
+        };
+        let (nick, user) = match rest.split_once('!') {
+            Some((nick, user)) => (nick, Some(user.to_string())),
+            None => (rest, None),
+        };
+        Self::new(nick.to_string(), user, host)
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.nick)?;
+        if let Some(user) = &self.user {
+            write!(f, "!{user}")?;
+        }
+        if let Some(host) = &self.host {
+            write!(f, "@{host}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A single IRC line split into its IRCv3 tags, prefix, command and parameters.
+///
+/// The last parameter is the trailing one when it was sent after a `:`, but once parsed it is
+/// stored like any other parameter. Serializing adds the `:` back whenever it is needed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrcMessage {
+    pub tags: BTreeMap<String, String>,
+    pub prefix: Option<Prefix>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl IrcMessage {
+    pub fn new(command: &str, params: Vec<String>) -> Self {
+        Self {
+            tags: BTreeMap::new(),
+            prefix: None,
+            command: command.to_string(),
+            params,
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let mut rest = line.trim_end_matches(['\r', '\n']).trim_start_matches(' ');
+        if rest.is_empty() {
+            return Err(ParseError::Empty);
+        }
+
+        let mut tags = BTreeMap::new();
+        if let Some(tail) = rest.strip_prefix('@') {
+            let (raw_tags, tail) = tail.split_once(' ').unwrap_or((tail, ""));
+            for tag in raw_tags.split(';').filter(|tag| !tag.is_empty()) {
+                let (key, value) = tag.split_once('=').unwrap_or((tag, ""));
+                tags.insert(key.to_string(), unescape_tag_value(value));
+            }
+            rest = tail.trim_start_matches(' ');
+        }
+
+        let mut prefix = None;
+        if let Some(tail) = rest.strip_prefix(':') {
+            let (raw_prefix, tail) = tail.split_once(' ').unwrap_or((tail, ""));
+            prefix = Some(Prefix::parse(raw_prefix));
+            rest = tail.trim_start_matches(' ');
+        }
+
+        let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        if command.is_empty() {
+            return Err(ParseError::MissingCommand);
+        }
+        if !command.chars().all(|ch| ch.is_ascii_alphanumeric()) {
+            return Err(ParseError::InvalidCommand(command.to_string()));
+        }
+
+        let mut params = Vec::new();
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(trailing) = rest.strip_prefix(':') {
+                params.push(trailing.to_string());
+                break;
+            }
+            let (param, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+            params.push(param.to_string());
+            rest = tail;
+        }
+
+        Ok(Self {
+            tags,
+            prefix,
+            command: command.to_ascii_uppercase(),
+            params,
+        })
+    }
+
+    pub fn with_tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_prefix(mut self, prefix: Prefix) -> Self {
+        self.prefix = Some(prefix);
+        self
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(|value| value.as_str())
+    }
+
+    pub fn param(&self, index: usize) -> Option<&str> {
+        self.params.get(index).map(|param| param.as_str())
+    }
+
+    /// The last parameter, which is where most commands carry their free text.
+    pub fn trailing(&self) -> Option<&str> {
+        self.params.last().map(|param| param.as_str())
+    }
+
+    /// Nick of the sender, or the server name for server originated messages.
+    #[cfg(test)]
+    pub fn source_nick(&self) -> Option<&str> {
+        self.prefix.as_ref().map(|prefix| prefix.nick.as_str())
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        self.command.len() == 3 && self.command.chars().all(|ch| ch.is_ascii_digit())
+    }
+}
+
+impl FromStr for IrcMessage {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for IrcMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.tags.is_empty() {
+            write!(f, "@")?;
+            for (i, (key, value)) in self.tags.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ";")?;
+                }
+                write!(f, "{key}")?;
+                if !value.is_empty() {
+                    write!(f, "={}", escape_tag_value(value))?;
+                }
+            }
+            write!(f, " ")?;
+        }
+        if let Some(prefix) = &self.prefix {
+            write!(f, ":{prefix} ")?;
+        }
+        write!(f, "{}", self.command)?;
+        let last = self.params.len().saturating_sub(1);
+        for (i, param) in self.params.iter().enumerate() {
+            if i == last && (param.is_empty() || param.contains(' ') || param.starts_with(':')) {
+                write!(f, " :{param}")?;
+            } else {
+                write!(f, " {param}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn unescape_tag_value(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => result.push(';'),
+            Some('s') => result.push(' '),
+            Some('\\') => result.push('\\'),
+            Some('r') => result.push('\r'),
+            Some('n') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => {}
+        }
+    }
+    result
+}
+
+fn escape_tag_value(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            ';' => result.push_str("\\:"),
+            ' ' => result.push_str("\\s"),
+            '\\' => result.push_str("\\\\"),
+            '\r' => result.push_str("\\r"),
+            '\n' => result.push_str("\\n"),
+            other => result.push(other),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(line: &str) -> IrcMessage {
+        let message = IrcMessage::parse(line).unwrap();
+        let serialized = message.to_string();
+        assert_eq!(IrcMessage::parse(&serialized).unwrap(), message, "{line} -> {serialized}");
+        message
+    }
+
+    #[test]
+    fn parses_ping_without_prefix() {
+        let message = round_trip("PING :irc.quakenet.org");
+        assert_eq!(message.prefix, None);
+        assert_eq!(message.command, "PING");
+        assert_eq!(message.params, vec!["irc.quakenet.org"]);
+    }
+
+    #[test]
+    fn parses_full_user_prefix() {
+        let message = round_trip(":Fulgore!~fulgore@host.example.com PRIVMSG #WarPigs :hello there");
+        let prefix = message.prefix.unwrap();
+        assert_eq!(prefix.nick, "Fulgore");
+        assert_eq!(prefix.user.as_deref(), Some("~fulgore"));
+        assert_eq!(prefix.host.as_deref(), Some("host.example.com"));
+        assert_eq!(message.command, "PRIVMSG");
+        assert_eq!(message.params, vec!["#WarPigs", "hello there"]);
+    }
+
+    #[test]
+    fn parses_server_prefix_and_numeric() {
+        let message = round_trip(":underworld1.no.quakenet.org 001 Fulgore :Welcome to the QuakeNet IRC Network, Fulgore");
+        assert_eq!(message.source_nick(), Some("underworld1.no.quakenet.org"));
+        assert!(message.is_numeric());
+        assert_eq!(message.param(0), Some("Fulgore"));
+        assert_eq!(message.trailing(), Some("Welcome to the QuakeNet IRC Network, Fulgore"));
+    }
+
+    #[test]
+    fn parses_middle_params_without_trailing() {
+        let message = round_trip(":irc.libera.chat 005 Fulgore CHANTYPES=# EXCEPTS INVEX CHANMODES=eIbq,k,flj,CFLMPQScgimnprstuz");
+        assert_eq!(message.params.len(), 5);
+        assert_eq!(message.param(4), Some("CHANMODES=eIbq,k,flj,CFLMPQScgimnprstuz"));
+    }
+
+    #[test]
+    fn parses_ircv3_tags() {
+        let message = round_trip("@badge-info=;badges=broadcaster/1;msgid=abc\\s123;time=2024-01-06T12:00:00.000Z :nick!user@host PRIVMSG #chan :hi");
+        assert_eq!(message.tag("badge-info"), Some(""));
+        assert_eq!(message.tag("badges"), Some("broadcaster/1"));
+        assert_eq!(message.tag("msgid"), Some("abc 123"));
+        assert_eq!(message.tag("time"), Some("2024-01-06T12:00:00.000Z"));
+        assert_eq!(message.params, vec!["#chan", "hi"]);
+    }
+
+    #[test]
+    fn tag_escaping_round_trips() {
+        let message = IrcMessage::new("TAGMSG", vec!["#chan".into()]).with_tag("+draft/text", "a;b c\\d\r\n");
+        let serialized = message.to_string();
+        assert_eq!(serialized, "@+draft/text=a\\:b\\sc\\\\d\\r\\n TAGMSG #chan");
+        assert_eq!(IrcMessage::parse(&serialized).unwrap(), message);
+    }
+
+    #[test]
+    fn keeps_empty_and_colon_trailing() {
+        let message = round_trip(":nick!user@host TOPIC #chan :");
+        assert_eq!(message.params, vec!["#chan", ""]);
+
+        let message = round_trip(":nick!user@host PRIVMSG #chan ::-)");
+        assert_eq!(message.trailing(), Some(":-)"));
+    }
+
+    #[test]
+    fn ignores_extra_spaces_and_line_breaks() {
+        let message = round_trip(":nick!user@host   JOIN    #chan\r\n");
+        assert_eq!(message.command, "JOIN");
+        assert_eq!(message.params, vec!["#chan"]);
+    }
+
+    #[test]
+    fn parses_cap_ls_multiline() {
+        let message = round_trip(":irc.example.com CAP * LS * :multi-prefix extended-join sasl=PLAIN,EXTERNAL");
+        assert_eq!(message.params, vec!["*", "LS", "*", "multi-prefix extended-join sasl=PLAIN,EXTERNAL"]);
+    }
+
+    #[test]
+    fn serializes_outgoing_messages() {
+        assert_eq!(IrcMessage::new("NICK", vec!["Fulgore".into()]).to_string(), "NICK Fulgore");
+        assert_eq!(
+            IrcMessage::new("USER", vec!["fulgore".into(), "0".into(), "*".into(), "Real Name".into()]).to_string(),
+            "USER fulgore 0 * :Real Name"
+        );
+        assert_eq!(
+            IrcMessage::new("PRIVMSG", vec!["#WarPigs".into(), "hello".into()])
+                .with_prefix(Prefix::parse("a!b@c"))
+                .to_string(),
+            ":a!b@c PRIVMSG #WarPigs hello"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert_eq!(IrcMessage::parse(""), Err(ParseError::Empty));
+        assert_eq!(IrcMessage::parse("\r\n"), Err(ParseError::Empty));
+        assert_eq!(IrcMessage::parse(":only.a.prefix"), Err(ParseError::MissingCommand));
+        assert_eq!(IrcMessage::parse("@a=b"), Err(ParseError::MissingCommand));
+        assert!(matches!(IrcMessage::parse("PRIV-MSG x"), Err(ParseError::InvalidCommand(_))));
+    }
+}
@@ -0,0 +1,108 @@
+/// Gives up on registering after this many rejected nicks.
+const MAX_ATTEMPTS: usize = 30;
+
+/// Picks the nick to register with and the ones to fall back to when the server rejects it.
+///
+/// The configured nicks are tried in order, then variants of the primary one: `nick_`, `nick__`
+/// and then `nick1`, `nick2` and so on, shortened to fit the server's nick length. Registering
+/// gives up once [`MAX_ATTEMPTS`] nicks were rejected.
+#[derive(Debug)]
+pub struct Nicknames {
+    configured: Vec<String>,
+    attempts: usize,
+}
+
+impl Nicknames {
+    pub fn new(configured: Vec<String>) -> Self {
+        Self { configured, attempts: 0 }
+    }
+
+    pub fn set_configured(&mut self, configured: Vec<String>) {
+        if !configured.is_empty() {
+            self.configured = configured;
+        }
+    }
+
+    /// The nick we want, which the client tries to get back when registered with another one.
+    pub fn primary(&self) -> &str {
+        self.configured.first().map_or("", |nick| nick.as_str())
+    }
+
+    /// Starts over for a new connection and returns the nick to register with.
+    pub fn first(&mut self) -> String {
+        self.attempts = 0;
+        self.primary().to_string()
+    }
+
+    /// The nick to try after the last one was rejected, or None when out of ideas.
+    pub fn next(&mut self, max_len: Option<usize>) -> Option<String> {
+        loop {
+            self.attempts += 1;
+            if self.attempts >= MAX_ATTEMPTS {
+                return None;
+            }
+            if let Some(nick) = self.configured.get(self.attempts) {
+                return Some(nick.clone());
+            }
+            let variant = self.variant(self.attempts - self.configured.len().max(1), max_len);
+            // Skip variants already tried as configured alternatives
+            if !self.configured.contains(&variant) {
+                return Some(variant);
+            }
+        }
+    }
+
+    fn variant(&self, index: usize, max_len: Option<usize>) -> String {
+        let suffix = match index {
+            0 => "_".to_string(),
+            1 => "__".to_string(),
+            n => (n - 1).to_string(),
+        };
+        let base = self.primary();
+        let keep = max_len.map_or(base.len(), |max| max.saturating_sub(suffix.len()).min(base.len()));
+        let keep = (0..=keep).rev().find(|i| base.is_char_boundary(*i)).unwrap_or(0);
+        format!("{}{suffix}", &base[..keep])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tries_configured_nicks_then_variants() {
+        let mut nicks = Nicknames::new(vec!["crust".into(), "crusty".into()]);
+        assert_eq!(nicks.first(), "crust");
+        assert_eq!(nicks.next(None).as_deref(), Some("crusty"));
+        assert_eq!(nicks.next(None).as_deref(), Some("crust_"));
+        assert_eq!(nicks.next(None).as_deref(), Some("crust__"));
+        assert_eq!(nicks.next(None).as_deref(), Some("crust1"));
+        assert_eq!(nicks.next(None).as_deref(), Some("crust2"));
+        assert_eq!(nicks.first(), "crust");
+        assert_eq!(nicks.next(None).as_deref(), Some("crusty"));
+    }
+
+    #[test]
+    fn skips_variants_that_were_configured() {
+        let mut nicks = Nicknames::new(vec!["crust".into(), "crust_".into()]);
+        nicks.first();
+        assert_eq!(nicks.next(None).as_deref(), Some("crust_"));
+        assert_eq!(nicks.next(None).as_deref(), Some("crust__"));
+    }
+
+    #[test]
+    fn variants_fit_the_nick_length() {
+        let mut nicks = Nicknames::new(vec!["longnickname".into()]);
+        nicks.first();
+        assert_eq!(nicks.next(Some(9)).as_deref(), Some("longnick_"));
+        assert_eq!(nicks.next(Some(9)).as_deref(), Some("longnic__"));
+        assert_eq!(nicks.next(Some(9)).as_deref(), Some("longnick1"));
+    }
+
+    #[test]
+    fn gives_up_eventually() {
+        let mut nicks = Nicknames::new(vec!["crust".into()]);
+        nicks.first();
+        assert!((0..MAX_ATTEMPTS).any(|_| nicks.next(None).is_none()));
+    }
+}
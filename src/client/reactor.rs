@@ -0,0 +1,28 @@
+use std::io;
+use std::os::fd::AsRawFd;
+
+use mio::unix::SourceFd;
+use mio::{Interest, Registry, Token};
+
+/// Where the event loop is, so every socket of a client can wake it up.
+#[derive(Default)]
+pub struct Reactor(Option<(Registry, Token)>);
+
+impl Reactor {
+    pub fn new(registry: Registry, token: Token) -> Self {
+        Self(Some((registry, token)))
+    }
+
+    pub fn register(&self, source: &impl AsRawFd) -> io::Result<()> {
+        match &self.0 {
+            Some((registry, token)) => registry.register(&mut SourceFd(&source.as_raw_fd()), *token, Interest::READABLE | Interest::WRITABLE),
+            None => Ok(()),
+        }
+    }
+
+    pub fn deregister(&self, source: &impl AsRawFd) {
+        if let Some((registry, _)) = &self.0 {
+            let _ = registry.deregister(&mut SourceFd(&source.as_raw_fd()));
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Attempts before giving up, 0 disables reconnecting.
+    pub max_attempts: u32,
+    /// Delay before the first attempt, doubled after every failure.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Schedules reconnect attempts with jittered exponential backoff.
+#[derive(Debug)]
+pub struct Reconnect {
+    policy: ReconnectPolicy,
+    attempt: u32,
+    next_at: Option<Instant>,
+    seed: u64,
+}
+
+impl Reconnect {
+    pub fn new(policy: ReconnectPolicy) -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+        Self {
+            policy,
+            attempt: 0,
+            next_at: None,
+            seed: seed | 1,
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: ReconnectPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.policy.max_attempts
+    }
+
+    /// Plans the next attempt and returns how long until it, or None when out of attempts.
+    pub fn schedule(&mut self) -> Option<Duration> {
+        if self.attempt >= self.policy.max_attempts {
+            self.next_at = None;
+            return None;
+        }
+        let delay = self.delay(self.attempt);
+        self.attempt += 1;
+        self.next_at = Some(Instant::now() + delay);
+        Some(delay)
+    }
+
+    /// When the planned attempt is due, if one is planned.
+    pub fn due_at(&self) -> Option<Instant> {
+        self.next_at
+    }
+
+    /// True once, when the scheduled attempt is due.
+    pub fn take_due(&mut self) -> bool {
+        match self.next_at {
+            Some(at) if at <= Instant::now() => {
+                self.next_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forgets the failed attempts after a successful registration.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+        self.next_at = None;
+    }
+
+    /// Stops reconnecting, like after a disconnect asked for by the user.
+    pub fn cancel(&mut self) {
+        self.reset();
+    }
+
+    /// Picks a delay between half and all of `base * 2^attempt`, capped at `max_delay`, so
+    /// clients dropped together by a netsplit don't all come back at the same moment.
+    fn delay(&mut self, attempt: u32) -> Duration {
+        let full = self.policy.base_delay.saturating_mul(1 << attempt.min(16)).min(self.policy.max_delay);
+        let half = full / 2;
+        let jitter = self.next_random() % (half.as_millis() as u64 + 1);
+        half + Duration::from_millis(jitter)
+    }
+
+    /// xorshift64, good enough to spread reconnects around.
+    fn next_random(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_within_jitter_and_caps() {
+        let mut reconnect = Reconnect::new(ReconnectPolicy {
+            max_attempts: 20,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(60),
+        });
+        for attempt in 0..20u32 {
+            let full = Duration::from_secs(2 * (1u64 << attempt.min(16))).min(Duration::from_secs(60));
+            let delay = reconnect.schedule().unwrap();
+            assert!(delay >= full / 2 && delay <= full, "attempt {attempt}: {delay:?} not in {full:?}");
+        }
+    }
+
+    #[test]
+    fn stops_after_max_attempts_until_reset() {
+        let mut reconnect = Reconnect::new(ReconnectPolicy {
+            max_attempts: 2,
+            ..ReconnectPolicy::default()
+        });
+        assert!(reconnect.schedule().is_some());
+        assert!(reconnect.schedule().is_some());
+        assert_eq!(reconnect.schedule(), None);
+        assert!(!reconnect.take_due());
+        reconnect.reset();
+        assert_eq!(reconnect.attempt(), 0);
+        assert!(reconnect.schedule().is_some());
+    }
+}
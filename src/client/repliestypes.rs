@@ -1,4 +1,5 @@
 #[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum Replies {
@@ -6,6 +7,7 @@ pub enum Replies {
     RPL_YOURHOST,
     RPL_CREATED,
     RPL_MYINFO,
+    RPL_ISUPPORT,
     RPL_BOUNCE,
     RPL_USERHOST,
     RPL_ISON,
@@ -163,6 +165,20 @@ pub enum Replies {
     RPL_STATSBLINE,
     RPL_STATSDLINE,
     ERR_NOSERVICEHOST,
+    RPL_LOGGEDIN,
+    RPL_LOGGEDOUT,
+    ERR_NICKLOCKED,
+    RPL_SASLSUCCESS,
+    ERR_SASLFAIL,
+    ERR_SASLTOOLONG,
+    ERR_SASLABORTED,
+    ERR_SASLALREADY,
+    RPL_SASLMECHS,
+    RPL_MONONLINE,
+    RPL_MONOFFLINE,
+    RPL_MONLIST,
+    RPL_ENDOFMONLIST,
+    ERR_MONLISTFULL,
     UNKNOWN(String),
 }
 
@@ -173,7 +189,8 @@ impl Replies {
             "002" => Replies::RPL_YOURHOST,
             "003" => Replies::RPL_CREATED,
             "004" => Replies::RPL_MYINFO,
-            "005" => Replies::RPL_BOUNCE,
+            "005" => Replies::RPL_ISUPPORT,
+            "010" => Replies::RPL_BOUNCE,
             "302" => Replies::RPL_USERHOST,
             "303" => Replies::RPL_ISON,
             "301" => Replies::RPL_AWAY,
@@ -330,6 +347,20 @@ impl Replies {
             "247" => Replies::RPL_STATSBLINE,
             "250" => Replies::RPL_STATSDLINE,
             "492" => Replies::ERR_NOSERVICEHOST,
+            "900" => Replies::RPL_LOGGEDIN,
+            "901" => Replies::RPL_LOGGEDOUT,
+            "902" => Replies::ERR_NICKLOCKED,
+            "903" => Replies::RPL_SASLSUCCESS,
+            "904" => Replies::ERR_SASLFAIL,
+            "905" => Replies::ERR_SASLTOOLONG,
+            "906" => Replies::ERR_SASLABORTED,
+            "907" => Replies::ERR_SASLALREADY,
+            "908" => Replies::RPL_SASLMECHS,
+            "730" => Replies::RPL_MONONLINE,
+            "731" => Replies::RPL_MONOFFLINE,
+            "732" => Replies::RPL_MONLIST,
+            "733" => Replies::RPL_ENDOFMONLIST,
+            "734" => Replies::ERR_MONLISTFULL,
             _ => Replies::UNKNOWN(numbers.to_string()),
         }
     }
@@ -0,0 +1,240 @@
+use crate::client::message::IrcMessage;
+
+/// AUTHENTICATE payloads are sent in chunks of this many bytes.
+const CHUNK_SIZE: usize = 400;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SaslMechanism {
+    Plain,
+    External,
+}
+
+impl SaslMechanism {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_uppercase().as_str() {
+            "PLAIN" => Some(SaslMechanism::Plain),
+            "EXTERNAL" => Some(SaslMechanism::External),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::External => "EXTERNAL",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SaslCredentials {
+    pub mechanism: SaslMechanism,
+    pub username: String,
+    pub password: String,
+    /// Disconnect instead of registering unauthenticated when SASL fails.
+    pub abort_on_failure: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum SaslState {
+    Idle,
+    Started,
+    Done,
+}
+
+/// SASL authentication run between CAP ACK and CAP END.
+#[derive(Debug)]
+pub struct Sasl {
+    credentials: Option<SaslCredentials>,
+    state: SaslState,
+}
+
+impl Sasl {
+    pub fn new() -> Self {
+        Self {
+            credentials: None,
+            state: SaslState::Idle,
+        }
+    }
+
+    pub fn set_credentials(&mut self, credentials: Option<SaslCredentials>) {
+        self.credentials = credentials;
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.credentials.is_some()
+    }
+
+    pub fn abort_on_failure(&self) -> bool {
+        self.credentials.as_ref().is_some_and(|credentials| credentials.abort_on_failure)
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.state == SaslState::Started
+    }
+
+    pub fn reset(&mut self) {
+        self.state = SaslState::Idle;
+    }
+
+    /// Starts authenticating if we have credentials and the server offers our mechanism.
+    ///
+    /// `mechanisms` is the value of the `sasl` capability, which may be empty on servers
+    /// that only send the list with RPL_SASLMECHS.
+    pub fn start(&mut self, mechanisms: &str) -> Result<IrcMessage, String> {
+        let Some(credentials) = &self.credentials else {
+            return Err("No SASL credentials configured".into());
+        };
+        let name = credentials.mechanism.name();
+        if !mechanisms.is_empty() && !mechanisms.split(',').any(|mechanism| mechanism.eq_ignore_ascii_case(name)) {
+            return Err(format!("Server does not support SASL {name} (only {mechanisms})"));
+        }
+        self.state = SaslState::Started;
+        Ok(IrcMessage::new("AUTHENTICATE", vec![name.into()]))
+    }
+
+    /// Answers an `AUTHENTICATE +` from the server with our credentials.
+    pub fn respond(&mut self, challenge: &str) -> Vec<IrcMessage> {
+        let Some(credentials) = &self.credentials else {
+            return vec![abort()];
+        };
+        if self.state != SaslState::Started || challenge != "+" {
+            return vec![abort()];
+        }
+        match credentials.mechanism {
+            SaslMechanism::Plain => {
+                let payload = format!("{0}\0{0}\0{1}", credentials.username, credentials.password);
+                authenticate_chunks(payload.as_bytes())
+            }
+            SaslMechanism::External => authenticate_chunks(&[]),
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.state = SaslState::Done;
+    }
+}
+
+fn abort() -> IrcMessage {
+    IrcMessage::new("AUTHENTICATE", vec!["*".into()])
+}
+
+/// Base64 encodes `payload` into AUTHENTICATE lines of at most 400 bytes.
+///
+/// A payload that is empty or an exact multiple of 400 bytes ends with `AUTHENTICATE +`
+/// so the server knows nothing else follows.
+pub fn authenticate_chunks(payload: &[u8]) -> Vec<IrcMessage> {
+    let encoded = base64(payload);
+    let mut messages: Vec<IrcMessage> = encoded
+        .as_bytes()
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| IrcMessage::new("AUTHENTICATE", vec![String::from_utf8_lossy(chunk).to_string()]))
+        .collect();
+    if encoded.len().is_multiple_of(CHUNK_SIZE) {
+        messages.push(IrcMessage::new("AUTHENTICATE", vec!["+".into()]));
+    }
+    messages
+}
+
+fn base64(data: &[u8]) -> String {
+    let mut result = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(BASE64[(value >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sasl(mechanism: SaslMechanism) -> Sasl {
+        let mut sasl = Sasl::new();
+        sasl.set_credentials(Some(SaslCredentials {
+            mechanism,
+            username: "user".into(),
+            password: "secret".into(),
+            abort_on_failure: false,
+        }));
+        sasl
+    }
+
+    fn payloads(messages: &[IrcMessage]) -> Vec<&str> {
+        messages.iter().map(|message| message.param(0).unwrap()).collect()
+    }
+
+    #[test]
+    fn encodes_base64_with_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(b"user\0user\0secret"), "dXNlcgB1c2VyAHNlY3JldA==");
+        assert_eq!(base64(&[0xff, 0xfe, 0x00]), "//4A");
+    }
+
+    #[test]
+    fn cuts_payloads_into_400_byte_chunks() {
+        assert_eq!(payloads(&authenticate_chunks(b"")), ["+"]);
+        assert_eq!(payloads(&authenticate_chunks(b"foo")), ["Zm9v"]);
+
+        // 300 bytes encode to exactly 400, so a `+` says nothing follows
+        let chunks = authenticate_chunks(&[b'a'; 300]);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].param(0).unwrap().len(), 400);
+        assert_eq!(chunks[1].param(0), Some("+"));
+
+        let chunks = authenticate_chunks(&[b'a'; 301]);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].param(0).unwrap().len(), 400);
+        assert_eq!(chunks[1].param(0), Some("YQ=="));
+
+        let chunks = authenticate_chunks(&[b'a'; 600]);
+        assert_eq!(payloads(&chunks)[2], "+");
+        assert!(chunks[..2].iter().all(|chunk| chunk.param(0).unwrap().len() == 400));
+    }
+
+    #[test]
+    fn authenticates_with_plain() {
+        let mut sasl = sasl(SaslMechanism::Plain);
+        assert!(!sasl.in_progress());
+        // Answering before starting aborts
+        assert_eq!(payloads(&sasl.respond("+")), ["*"]);
+
+        assert!(sasl.start("EXTERNAL").is_err());
+        assert_eq!(sasl.start("external,plain").unwrap().param(0), Some("PLAIN"));
+        assert!(sasl.in_progress());
+        assert_eq!(payloads(&sasl.respond("+")), ["dXNlcgB1c2VyAHNlY3JldA=="]);
+        sasl.finish();
+        assert!(!sasl.in_progress());
+        assert_eq!(payloads(&sasl.respond("+")), ["*"]);
+
+        // Servers that only list mechanisms in RPL_SASLMECHS leave the value empty
+        sasl.reset();
+        assert!(sasl.start("").is_ok());
+        assert_eq!(payloads(&sasl.respond("unexpected")), ["*"]);
+    }
+
+    #[test]
+    fn authenticates_with_external() {
+        let mut sasl = sasl(SaslMechanism::External);
+        assert!(sasl.start("PLAIN").is_err());
+        assert!(!sasl.in_progress());
+        assert_eq!(sasl.start("PLAIN,EXTERNAL").unwrap().param(0), Some("EXTERNAL"));
+        assert_eq!(payloads(&sasl.respond("+")), ["+"]);
+
+        assert!(Sasl::new().start("PLAIN").is_err());
+        assert_eq!(payloads(&Sasl::new().respond("+")), ["*"]);
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Write};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloodPolicy {
+    /// Lines that can go out at once after a quiet moment.
+    pub burst: u32,
+    /// Time it takes to earn one more line, zero disables throttling.
+    pub interval: Duration,
+}
+
+impl Default for FloodPolicy {
+    fn default() -> Self {
+        Self {
+            burst: 5,
+            interval: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Priority {
+    Normal,
+    /// Skips the line of waiting messages and the rate limit, for PONG and QUIT.
+    Urgent,
+}
+
+/// Outgoing lines waiting for the socket and for the flood limit.
+///
+/// A token bucket holding up to `burst` tokens pays for every normal line and earns one token
+/// per `interval`. Lines are written one at a time; when the non-blocking socket only takes part
+/// of one, the rest is kept and finished before anything else, urgent lines included.
+#[derive(Debug)]
+pub struct SendQueue {
+    policy: FloodPolicy,
+    tokens: f64,
+    refilled: Instant,
+    urgent: VecDeque<Vec<u8>>,
+    normal: VecDeque<Vec<u8>>,
+    /// What is left of a line the socket only took part of.
+    partial: Vec<u8>,
+}
+
+impl SendQueue {
+    pub fn new(policy: FloodPolicy) -> Self {
+        Self {
+            tokens: f64::from(policy.burst),
+            policy,
+            refilled: Instant::now(),
+            urgent: VecDeque::new(),
+            normal: VecDeque::new(),
+            partial: Vec::new(),
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: FloodPolicy) {
+        self.tokens = self.tokens.min(f64::from(policy.burst));
+        self.policy = policy;
+    }
+
+    /// Queues a line, without its CRLF.
+    pub fn push(&mut self, line: &[u8], priority: Priority) {
+        let mut line = line.to_vec();
+        line.extend_from_slice(b"\r\n");
+        match priority {
+            Priority::Normal => self.normal.push_back(line),
+            Priority::Urgent => self.urgent.push_back(line),
+        }
+    }
+
+    /// Lines not completely written yet.
+    pub fn len(&self) -> usize {
+        self.urgent.len() + self.normal.len() + usize::from(!self.partial.is_empty())
+    }
+
+    /// When the rate limit lets the next waiting line go, None when nothing waits for it.
+    ///
+    /// Lines held back by a full socket are sent once it becomes writable instead.
+    pub fn next_send_at(&self) -> Option<Instant> {
+        if self.normal.is_empty() || !self.partial.is_empty() || !self.urgent.is_empty() || self.tokens >= 1.0 {
+            return None;
+        }
+        Some(self.refilled + self.policy.interval.mul_f64(1.0 - self.tokens))
+    }
+
+    /// Drops everything for a new connection, starting with a full bucket.
+    pub fn clear(&mut self) {
+        self.urgent.clear();
+        self.normal.clear();
+        self.partial.clear();
+        self.tokens = f64::from(self.policy.burst);
+        self.refilled = Instant::now();
+    }
+
+    /// Writes as much as the socket and the rate limit allow.
+    ///
+    /// Returns an error only when the connection is unusable, a full socket buffer is not one.
+    pub fn flush(&mut self, out: &mut impl Write, now: Instant) -> io::Result<()> {
+        self.refill(now);
+        loop {
+            if self.partial.is_empty() {
+                self.partial = match self.urgent.pop_front() {
+                    Some(line) => line,
+                    None if self.tokens >= 1.0 => match self.normal.pop_front() {
+                        Some(line) => {
+                            self.tokens -= 1.0;
+                            line
+                        }
+                        None => return Ok(()),
+                    },
+                    None => return Ok(()),
+                };
+            }
+            match out.write(&self.partial) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.partial.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let burst = f64::from(self.policy.burst.max(1));
+        if self.policy.interval.is_zero() {
+            self.tokens = burst;
+        } else {
+            let earned = now.saturating_duration_since(self.refilled).as_secs_f64() / self.policy.interval.as_secs_f64();
+            self.tokens = (self.tokens + earned).min(burst);
+        }
+        self.refilled = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A socket that takes at most `chunk` bytes per write and, when `stalls`, is full every other call.
+    struct Socket {
+        written: Vec<u8>,
+        chunk: usize,
+        stalls: bool,
+        full: bool,
+    }
+
+    impl Socket {
+        fn new(chunk: usize, stalls: bool) -> Self {
+            Self {
+                written: Vec::new(),
+                chunk,
+                stalls,
+                full: false,
+            }
+        }
+
+        fn lines(&self) -> Vec<&str> {
+            std::str::from_utf8(&self.written).unwrap().split_terminator("\r\n").collect()
+        }
+    }
+
+    impl Write for Socket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.full = self.stalls && !self.full;
+            if self.full {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(self.chunk);
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn policy(burst: u32) -> FloodPolicy {
+        FloodPolicy {
+            burst,
+            interval: Duration::from_secs(2),
+        }
+    }
+
+    #[test]
+    fn burst_then_one_line_per_interval() {
+        let mut queue = SendQueue::new(policy(2));
+        let mut socket = Socket::new(usize::MAX, false);
+        let start = Instant::now();
+        for line in ["a", "b", "c", "d"] {
+            queue.push(line.as_bytes(), Priority::Normal);
+        }
+        queue.flush(&mut socket, start).unwrap();
+        assert_eq!(socket.lines(), vec!["a", "b"]);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.next_send_at(), Some(start + Duration::from_secs(2)));
+
+        queue.flush(&mut socket, start + Duration::from_secs(3)).unwrap();
+        assert_eq!(socket.lines(), vec!["a", "b", "c"]);
+        queue.flush(&mut socket, start + Duration::from_secs(4)).unwrap();
+        assert_eq!(socket.lines(), vec!["a", "b", "c", "d"]);
+        assert_eq!(queue.len(), 0);
+        assert_eq!(queue.next_send_at(), None);
+    }
+
+    #[test]
+    fn urgent_lines_skip_the_queue_and_the_limit() {
+        let mut queue = SendQueue::new(policy(1));
+        let mut socket = Socket::new(usize::MAX, false);
+        let now = Instant::now();
+        queue.push(b"PRIVMSG #a :1", Priority::Normal);
+        queue.push(b"PRIVMSG #a :2", Priority::Normal);
+        queue.flush(&mut socket, now).unwrap();
+        queue.push(b"PONG :x", Priority::Urgent);
+        queue.push(b"QUIT", Priority::Urgent);
+        queue.flush(&mut socket, now).unwrap();
+        assert_eq!(socket.lines(), vec!["PRIVMSG #a :1", "PONG :x", "QUIT"]);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn partial_writes_finish_before_the_next_line() {
+        let mut queue = SendQueue::new(policy(5));
+        let mut socket = Socket::new(3, true);
+        let now = Instant::now();
+        queue.push(b"PRIVMSG #a :hello", Priority::Normal);
+        queue.flush(&mut socket, now).unwrap();
+        queue.push(b"PONG :x", Priority::Urgent);
+        for _ in 0..20 {
+            queue.flush(&mut socket, now).unwrap();
+        }
+        assert_eq!(socket.lines(), vec!["PRIVMSG #a :hello", "PONG :x"]);
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn broken_sockets_are_reported() {
+        struct Broken;
+        impl Write for Broken {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut queue = SendQueue::new(FloodPolicy::default());
+        queue.push(b"QUIT", Priority::Urgent);
+        assert!(queue.flush(&mut Broken, Instant::now()).is_err());
+    }
+}
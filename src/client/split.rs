@@ -0,0 +1,204 @@
+/// Longest line a server accepts, CRLF included, unless ISUPPORT LINELEN says otherwise.
+pub const MAX_LINE_LEN: usize = 512;
+
+/// Host name length assumed for our own prefix until the server shows the real one.
+pub const MAX_HOST_LEN: usize = 63;
+
+/// Never cut text into pieces smaller than this, even with an absurdly long target.
+const MIN_TEXT_LEN: usize = 16;
+
+/// One piece of outgoing text.
+#[derive(Debug, PartialEq)]
+pub struct Line {
+    pub text: String,
+    /// True when the piece continues the previous one, which only happens when a line was too long.
+    pub concat: bool,
+}
+
+/// Limits a server advertises with `draft/multiline=max-bytes=4096,max-lines=24`.
+#[derive(Debug, PartialEq)]
+pub struct MultilineLimits {
+    pub max_bytes: usize,
+    pub max_lines: Option<usize>,
+}
+
+impl MultilineLimits {
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut max_bytes = None;
+        let mut max_lines = None;
+        for pair in value.split(',') {
+            match pair.split_once('=') {
+                Some(("max-bytes", bytes)) => max_bytes = bytes.parse().ok(),
+                Some(("max-lines", lines)) => max_lines = lines.parse().ok(),
+                _ => {}
+            }
+        }
+        Some(Self {
+            max_bytes: max_bytes?,
+            max_lines,
+        })
+    }
+}
+
+/// Bytes left for the text of `@tags :source COMMAND target :text` in a line of `line_len` bytes.
+///
+/// `source_len` is the length of the `nick!user@host` the server puts in front when relaying the
+/// line. Tags are counted against the line too: the IRCv3 spec gives them their own budget, but
+/// some servers still cut at 512 bytes with them included.
+pub fn text_budget(line_len: usize, source_len: usize, command: &str, target: &str, tags_len: usize) -> usize {
+    let overhead = tags_len + 1 + source_len + 1 + command.len() + 1 + target.len() + 2 + 2;
+    line_len.saturating_sub(overhead).max(MIN_TEXT_LEN)
+}
+
+/// Splits text on its line breaks, then cuts lines longer than `max_len` bytes.
+///
+/// Cuts happen after the last space that fits, or between two characters for words that are
+/// longer than a whole line. The space stays at the end of the piece, so joining the pieces of a
+/// line gives it back unchanged.
+pub fn split_text(text: &str, max_len: usize) -> Vec<Line> {
+    let max_len = max_len.max(1);
+    let mut lines = Vec::new();
+    for line in text.split('\n') {
+        let mut rest = line.strip_suffix('\r').unwrap_or(line);
+        let mut concat = false;
+        while rest.len() > max_len {
+            let end = (0..=max_len).rev().find(|i| rest.is_char_boundary(*i)).unwrap_or(0);
+            let cut = match rest[..end].rfind(' ') {
+                Some(space) if space > 0 => space + 1,
+                // A single character wider than the budget still has to go somewhere
+                _ if end == 0 => rest.chars().next().map_or(rest.len(), char::len_utf8),
+                _ => end,
+            };
+            lines.push(Line {
+                text: rest[..cut].to_string(),
+                concat,
+            });
+            rest = &rest[cut..];
+            concat = true;
+        }
+        lines.push(Line {
+            text: rest.to_string(),
+            concat,
+        });
+    }
+    lines
+}
+
+/// Groups lines into multiline batches that stay within the server's limits.
+///
+/// Line breaks between lines count as one byte. A batch cannot start with a continuation, so a
+/// line cut across two batches shows up as two lines.
+pub fn batches(lines: Vec<Line>, limits: &MultilineLimits) -> Vec<Vec<Line>> {
+    let mut batches: Vec<Vec<Line>> = Vec::new();
+    let mut current: Vec<Line> = Vec::new();
+    let mut bytes = 0;
+    for mut line in lines {
+        let size = line.text.len() + usize::from(!current.is_empty() && !line.concat);
+        let full = limits.max_lines.is_some_and(|max| current.len() >= max) || bytes + size > limits.max_bytes;
+        if full && !current.is_empty() {
+            batches.push(std::mem::take(&mut current));
+            bytes = 0;
+        }
+        if current.is_empty() {
+            line.concat = false;
+            bytes += line.text.len();
+        } else {
+            bytes += size;
+        }
+        current.push(line);
+    }
+    if !current.is_empty() {
+        batches.push(current);
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(lines: &[Line]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn short_text_is_one_line() {
+        assert_eq!(
+            split_text("hello world", 100),
+            vec![Line {
+                text: "hello world".into(),
+                concat: false
+            }]
+        );
+    }
+
+    #[test]
+    fn cuts_after_the_last_space_that_fits() {
+        let lines = split_text("the quick brown fox", 10);
+        assert_eq!(texts(&lines), vec!["the quick ", "brown fox"]);
+        assert!(!lines[0].concat);
+        assert!(lines[1].concat);
+    }
+
+    #[test]
+    fn long_words_are_cut_on_character_boundaries() {
+        // Every é is two bytes, a cut at 5 bytes would land inside one
+        let lines = split_text("éééééé", 5);
+        assert_eq!(texts(&lines), vec!["éé", "éé", "éé"]);
+        assert!(lines.iter().all(|line| line.text.len() <= 5));
+        let lines = split_text("aaaaaaaaaaaa b", 5);
+        assert_eq!(texts(&lines), vec!["aaaaa", "aaaaa", "aa b"]);
+    }
+
+    #[test]
+    fn line_breaks_start_new_lines() {
+        let lines = split_text("one\r\ntwo\n\nthree", 100);
+        assert_eq!(texts(&lines), vec!["one", "two", "", "three"]);
+        assert!(lines.iter().all(|line| !line.concat));
+    }
+
+    #[test]
+    fn pieces_join_back_into_the_text() {
+        let text = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, ünïcödé everywhere 日本語のテキスト";
+        for max in 1..text.len() {
+            let lines = split_text(text, max);
+            assert!(lines.iter().all(|line| line.text.len() <= max || line.text.chars().count() == 1));
+            assert_eq!(lines.iter().map(|line| line.text.as_str()).collect::<String>(), text);
+        }
+    }
+
+    #[test]
+    fn budget_leaves_room_for_prefix_and_tags() {
+        let source = "crust!~crust@example.org";
+        let budget = text_budget(MAX_LINE_LEN, source.len(), "PRIVMSG", "#rust", 0);
+        let line = format!(":{source} PRIVMSG #rust :{}\r\n", "x".repeat(budget));
+        assert_eq!(line.len(), MAX_LINE_LEN);
+        assert_eq!(text_budget(MAX_LINE_LEN, source.len(), "PRIVMSG", "#rust", 20), budget - 20);
+    }
+
+    #[test]
+    fn parses_multiline_limits() {
+        assert_eq!(
+            MultilineLimits::parse("max-bytes=4096,max-lines=24"),
+            Some(MultilineLimits {
+                max_bytes: 4096,
+                max_lines: Some(24)
+            })
+        );
+        assert_eq!(MultilineLimits::parse("max-lines=24"), None);
+    }
+
+    #[test]
+    fn batches_respect_the_limits() {
+        let limits = MultilineLimits {
+            max_bytes: 10,
+            max_lines: Some(2),
+        };
+        let batched = batches(split_text("aaaa\nbbbb\ncccc dddd", 5), &limits);
+        let batched: Vec<Vec<(&str, bool)>> = batched
+            .iter()
+            .map(|batch| batch.iter().map(|line| (line.text.as_str(), line.concat)).collect())
+            .collect();
+        assert_eq!(batched, vec![vec![("aaaa", false), ("bbbb", false)], vec![("cccc ", false), ("dddd", true)]]);
+    }
+}
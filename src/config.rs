@@ -1,9 +1,13 @@
 use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
 use std::{fs, io};
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::app;
+use crate::client::{parse_fingerprint, CtcpPolicy, DccPolicy, FloodPolicy, LagPolicy, ReconnectPolicy, SaslCredentials, SaslMechanism, TlsConfig, TlsVerify};
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct User {
@@ -31,12 +35,106 @@ impl User {
     }
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SaslFailure {
+    #[default]
+    Continue,
+    Abort,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Sasl {
+    pub mechanism: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    #[serde(default)]
+    pub on_failure: SaslFailure,
+}
+
+impl Sasl {
+    pub fn credentials(&self) -> Result<SaslCredentials, String> {
+        let mechanism = SaslMechanism::parse(&self.mechanism).ok_or(format!("Unknown SASL mechanism {}", self.mechanism))?;
+        if mechanism == SaslMechanism::Plain && (self.username.is_empty() || self.password.is_empty()) {
+            return Err("SASL PLAIN needs a username and a password".into());
+        }
+        Ok(SaslCredentials {
+            mechanism,
+            username: self.username.clone(),
+            password: self.password.clone(),
+            abort_on_failure: self.on_failure == SaslFailure::Abort,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Tls {
+    /// Accept any certificate, including self-signed and expired ones.
+    #[serde(default)]
+    pub accept_invalid: bool,
+    /// SHA-256 fingerprint of the only certificate to accept, in hex.
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    /// PEM file with the certificate used for CertFP.
+    #[serde(default)]
+    pub client_cert: Option<String>,
+    /// PEM file with the key of `client_cert`, when it is not in the same file.
+    #[serde(default)]
+    pub client_key: Option<String>,
+}
+
+impl Tls {
+    pub fn config(&self) -> Result<TlsConfig, String> {
+        let verify = match &self.fingerprint {
+            Some(fingerprint) => TlsVerify::Pinned(parse_fingerprint(fingerprint)?),
+            None if self.accept_invalid => TlsVerify::AcceptInvalid,
+            None => TlsVerify::System,
+        };
+        let client_cert = self.client_cert.as_ref().map(|cert| {
+            let key = self.client_key.as_ref().unwrap_or(cert);
+            (cert.into(), key.into())
+        });
+        Ok(TlsConfig { verify, client_cert })
+    }
+}
+
+/// A channel joined on connect, with its key if it has one.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct AutoJoin {
+    pub channel: String,
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Server {
     last_nickname: Option<String>,
     name: String,
     address: String,
     port: u16,
+    #[serde(default)]
+    tls: bool,
+    #[serde(default)]
+    tls_options: Option<Tls>,
+    #[serde(default)]
+    sasl: Option<Sasl>,
+    /// Connect as soon as the client starts.
+    #[serde(default)]
+    autoconnect: bool,
+    #[serde(default)]
+    autojoin: Vec<AutoJoin>,
+    /// Raw IRC lines sent once registered, like `PRIVMSG NickServ :IDENTIFY secret`.
+    #[serde(default)]
+    commands: Vec<String>,
+    /// Used instead of the ones of [`User`] on this server.
+    #[serde(default)]
+    nicknames: Option<Vec<String>>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    realname: Option<String>,
 }
 
 impl Server {
@@ -46,32 +144,497 @@ impl Server {
             name,
             address,
             port,
+            tls: false,
+            tls_options: None,
+            sasl: None,
+            autoconnect: false,
+            autojoin: Vec::new(),
+            commands: Vec::new(),
+            nicknames: None,
+            username: None,
+            realname: None,
+        }
+    }
+
+    pub fn set_tls(&mut self, tls: bool) {
+        self.tls = tls;
+    }
+
+    pub fn set_autoconnect(&mut self, autoconnect: bool) {
+        self.autoconnect = autoconnect;
+    }
+
+    pub fn autoconnect(&self) -> bool {
+        self.autoconnect
+    }
+
+    pub fn add_autojoin(&mut self, channel: &str, key: Option<&str>) {
+        self.autojoin.push(AutoJoin {
+            channel: channel.to_string(),
+            key: key.map(|key| key.to_string()),
+        });
+    }
+
+    /// Channels to join and their keys.
+    pub fn autojoin(&self) -> Vec<(String, Option<String>)> {
+        self.autojoin.iter().map(|join| (join.channel.clone(), join.key.clone())).collect()
+    }
+
+    pub fn commands(&self) -> &[String] {
+        &self.commands
+    }
+
+    /// `user` with the nicks, username and real name this server overrides.
+    pub fn user(&self, user: &User) -> User {
+        User {
+            nicknames: self
+                .nicknames
+                .clone()
+                .filter(|nicks| !nicks.is_empty())
+                .unwrap_or_else(|| user.nicknames.clone()),
+            username: self.username.clone().unwrap_or_else(|| user.username.clone()),
+            realname: self.realname.clone().unwrap_or_else(|| user.realname.clone()),
+        }
+        .clean()
+    }
+
+    pub fn set_last_nickname(&mut self, nick: &str) -> bool {
+        if self.last_nickname.as_deref() == Some(nick) {
+            return false;
+        }
+        self.last_nickname = Some(nick.to_string());
+        true
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// One line for `/server list`.
+    pub fn describe(&self) -> String {
+        let mut text = format!("{} {}:{}{}", self.name, self.address, if self.tls { "+" } else { "" }, self.port);
+        if self.autoconnect {
+            text += " autoconnect";
+        }
+        if !self.autojoin.is_empty() {
+            let channels: Vec<&str> = self.autojoin.iter().map(|join| join.channel.as_str()).collect();
+            text += &format!(" joins {}", channels.join(","));
+        }
+        if let Some(nicknames) = &self.nicknames {
+            text += &format!(" as {}", nicknames.join(","));
+        }
+        if self.sasl.is_some() {
+            text += " sasl";
+        }
+        text
+    }
+
+    /// TLS settings to connect with, or None for a plain text connection.
+    pub fn tls(&self) -> Result<Option<TlsConfig>, String> {
+        if !self.tls {
+            return Ok(None);
+        }
+        self.tls_options.clone().unwrap_or_default().config().map(Some)
+    }
+
+    pub fn sasl(&self) -> Option<&Sasl> {
+        self.sasl.as_ref()
+    }
+
+    /// Changes one of [`SERVER_FIELDS`] from `/server set`, `value` being the rest of the line.
+    /// The identity overrides and on-connect commands are cleared without a value.
+    pub fn set(&mut self, field: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let words: Vec<&str> = value.split_whitespace().collect();
+        match (field, &words[..]) {
+            ("autoconnect", ["on"]) => self.autoconnect = true,
+            ("autoconnect", ["off"]) => self.autoconnect = false,
+            ("join", [channel, key @ ..]) if key.len() <= 1 => {
+                self.autojoin.retain(|join| !join.channel.eq_ignore_ascii_case(channel));
+                self.add_autojoin(channel, key.first().copied());
+            }
+            ("part", [channel]) => {
+                let len = self.autojoin.len();
+                self.autojoin.retain(|join| !join.channel.eq_ignore_ascii_case(channel));
+                if self.autojoin.len() == len {
+                    return Err(format!("{channel} is not joined on connect"));
+                }
+            }
+            ("command", []) => self.commands.clear(),
+            ("command", _) => self.commands.push(value.to_string()),
+            ("nicks", []) => self.nicknames = None,
+            ("nicks", nicks) => self.nicknames = Some(nicks.iter().map(|nick| nick.to_string()).collect()),
+            ("username", []) => self.username = None,
+            ("username", [username]) => self.username = Some(username.to_string()),
+            ("realname", []) => self.realname = None,
+            ("realname", _) => self.realname = Some(value.to_string()),
+            ("sasl", ["off"]) => self.sasl = None,
+            ("sasl", [mechanism, rest @ ..]) => {
+                let abort = rest.last() == Some(&"-abort");
+                let credentials = if abort { &rest[..rest.len() - 1] } else { rest };
+                let (username, password) = match credentials {
+                    [] => ("", ""),
+                    [username, password] => (*username, *password),
+                    _ => return Err("SASL takes a username and a password, or none for EXTERNAL".into()),
+                };
+                let sasl = Sasl {
+                    mechanism: mechanism.to_string(),
+                    username: username.to_string(),
+                    password: password.to_string(),
+                    on_failure: if abort { SaslFailure::Abort } else { SaslFailure::Continue },
+                };
+                sasl.credentials()?;
+                self.sasl = Some(sasl);
+            }
+            _ if !SERVER_FIELDS.contains(&field) => return Err(format!("Unknown server setting {field}, one of: {}", SERVER_FIELDS.join(" "))),
+            _ => return Err(format!("Invalid value for {field}")),
+        }
+        Ok(())
+    }
+}
+
+/// What `/server set` changes: `autoconnect on|off`, `join <#channel> [key]`, `part <#channel>`,
+/// `command [line]`, `nicks [nick...]`, `username [name]`, `realname [name]` and
+/// `sasl <mechanism> [username password] [-abort] | off`.
+pub const SERVER_FIELDS: &[&str] = &["autoconnect", "join", "part", "command", "nicks", "username", "realname", "sasl"];
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Reconnect {
+    /// Attempts before giving up, 0 disables reconnecting.
+    pub max_attempts: u32,
+    /// Seconds before the first attempt, doubled after every failure.
+    pub base_delay: u64,
+    /// Longest wait between attempts, in seconds.
+    pub max_delay: u64,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        let policy = ReconnectPolicy::default();
+        Self {
+            max_attempts: policy.max_attempts,
+            base_delay: policy.base_delay.as_secs(),
+            max_delay: policy.max_delay.as_secs(),
+        }
+    }
+}
+
+impl Reconnect {
+    pub fn policy(&self) -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_attempts: self.max_attempts,
+            base_delay: Duration::from_secs(self.base_delay.max(1)),
+            max_delay: Duration::from_secs(self.max_delay.max(self.base_delay)),
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Flood {
+    /// Lines sent at once before the rate limit applies.
+    pub burst: u32,
+    /// Milliseconds between lines once the burst is spent, 0 disables the limit.
+    pub interval: u64,
+}
+
+impl Default for Flood {
+    fn default() -> Self {
+        let policy = FloodPolicy::default();
+        Self {
+            burst: policy.burst,
+            interval: policy.interval.as_millis() as u64,
+        }
+    }
+}
+
+impl Flood {
+    pub fn policy(&self) -> FloodPolicy {
+        FloodPolicy {
+            burst: self.burst.max(1),
+            interval: Duration::from_millis(self.interval),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Ping {
+    /// Seconds without hearing from the server before checking the connection with a PING.
+    pub interval: u64,
+    /// Seconds to wait for the answer before reconnecting.
+    pub timeout: u64,
+}
+
+impl Default for Ping {
+    fn default() -> Self {
+        let policy = LagPolicy::default();
+        Self {
+            interval: policy.interval.as_secs(),
+            timeout: policy.timeout.as_secs(),
+        }
+    }
+}
+
+impl Ping {
+    pub fn policy(&self) -> LagPolicy {
+        LagPolicy {
+            interval: Duration::from_secs(self.interval.max(1)),
+            timeout: Duration::from_secs(self.timeout.max(1)),
+        }
+    }
+}
+
+/// Which CTCP requests get an automatic reply.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Ctcp {
+    pub version: bool,
+    pub ping: bool,
+    pub time: bool,
+    pub source: bool,
+    pub clientinfo: bool,
+    /// Replies sent at most every `period` seconds, other requests go unanswered.
+    pub max_replies: usize,
+    pub period: u64,
+}
+
+impl Default for Ctcp {
+    fn default() -> Self {
+        let policy = CtcpPolicy::default();
+        Self {
+            version: policy.version,
+            ping: policy.ping,
+            time: policy.time,
+            source: policy.source,
+            clientinfo: policy.clientinfo,
+            max_replies: policy.max_replies,
+            period: policy.period.as_secs(),
+        }
+    }
+}
+
+impl Ctcp {
+    pub fn policy(&self) -> CtcpPolicy {
+        CtcpPolicy {
+            version: self.version,
+            ping: self.ping,
+            time: self.time,
+            source: self.source,
+            clientinfo: self.clientinfo,
+            max_replies: self.max_replies,
+            period: Duration::from_secs(self.period),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Dcc {
+    /// Where received files go, `~/` meaning the home directory.
+    pub download_dir: String,
+    /// Address to give others in offers, for when we are behind NAT.
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Offer files passively, the receiver listening instead of us.
+    #[serde(default)]
+    pub passive: bool,
+}
+
+impl Default for Dcc {
+    fn default() -> Self {
+        let policy = DccPolicy::default();
+        Self {
+            download_dir: policy.download_dir.to_string_lossy().to_string(),
+            address: policy.address.map(|address| address.to_string()),
+            passive: policy.passive,
+        }
+    }
+}
+
+impl Dcc {
+    /// The policy to use, ignoring an address that does not parse.
+    pub fn policy(&self) -> DccPolicy {
+        let download_dir = match (self.download_dir.strip_prefix("~/"), std::env::var("HOME")) {
+            (Some(path), Ok(home)) => Path::new(&home).join(path),
+            _ => self.download_dir.clone().into(),
+        };
+        DccPolicy {
+            download_dir,
+            address: self.address.as_ref().and_then(|address| address.parse().ok()),
+            passive: self.passive,
+        }
+    }
+}
+
+/// What is remembered of the lines typed in the prompt.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct History {
+    /// Lines kept for every buffer and for all of them together, 0 disables the history.
+    pub size: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self { size: 1000 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Scrollback {
+    /// Messages kept in every buffer, the oldest going first.
+    pub messages: usize,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self { messages: 5000 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Completion {
+    /// Added after a nick completed at the start of the line, to address its owner.
+    pub nick_suffix: String,
+}
+
+impl Default for Completion {
+    fn default() -> Self {
+        Self { nick_suffix: ": ".into() }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Config {
     pub user: Option<User>,
     pub servers: Option<Vec<Server>>,
+    #[serde(default)]
+    pub capabilities: Option<Vec<String>>,
+    #[serde(default)]
+    pub reconnect: Reconnect,
+    #[serde(default)]
+    pub flood: Flood,
+    #[serde(default)]
+    pub ping: Ping,
+    #[serde(default)]
+    pub ctcp: Ctcp,
+    #[serde(default)]
+    pub dcc: Dcc,
+    #[serde(default)]
+    pub history: History,
+    #[serde(default)]
+    pub completion: Completion,
+    #[serde(default)]
+    pub scrollback: Scrollback,
 }
 
 impl Config {
     pub fn new(user: User, servers: Option<Vec<Server>>) -> Option<Self> {
-        if user.nicknames.len() > 0 {
-            Some(Self { user: Some(user), servers })
+        if !user.nicknames.is_empty() {
+            Some(Self {
+                user: Some(user),
+                servers,
+                capabilities: None,
+                reconnect: Reconnect::default(),
+                flood: Flood::default(),
+                ping: Ping::default(),
+                ctcp: Ctcp::default(),
+                dcc: Dcc::default(),
+                history: History::default(),
+                completion: Completion::default(),
+                scrollback: Scrollback::default(),
+            })
         } else {
             None
         }
     }
 
+    /// Finds a configured server by its name or by its address and port.
+    pub fn find_server(&self, name_or_address: &str, port: Option<u16>) -> Option<&Server> {
+        self.servers.as_ref()?.iter().find(|server| match port {
+            Some(port) => server.address.eq_ignore_ascii_case(name_or_address) && server.port == port,
+            None => server.name.eq_ignore_ascii_case(name_or_address),
+        })
+    }
+
+    /// Adds a server, unless one with the same name exists.
+    pub fn add_server(&mut self, server: Server) -> bool {
+        if self.find_server(&server.name, None).is_some() {
+            return false;
+        }
+        self.servers.get_or_insert_with(Vec::new).push(server);
+        true
+    }
+
+    pub fn remove_server(&mut self, name: &str) -> bool {
+        let Some(servers) = self.servers.as_mut() else {
+            return false;
+        };
+        let len = servers.len();
+        servers.retain(|server| !server.name.eq_ignore_ascii_case(name));
+        servers.len() != len
+    }
+
+    pub fn server_mut(&mut self, name: &str) -> Option<&mut Server> {
+        self.servers.as_mut()?.iter_mut().find(|server| server.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn find_server_mut(&mut self, address: &str, port: u16) -> Option<&mut Server> {
+        self.servers
+            .as_mut()?
+            .iter_mut()
+            .find(|server| server.address.eq_ignore_ascii_case(address) && server.port == port)
+    }
+
+    /// Every setting as a dotted key like `flood.burst`, lists and servers counting as one setting.
+    pub fn keys(&self) -> Vec<String> {
+        fn collect(value: &Value, prefix: &str, keys: &mut Vec<String>) {
+            match value {
+                Value::Object(map) => {
+                    for (key, value) in map {
+                        let key = if prefix.is_empty() { key.clone() } else { format!("{prefix}.{key}") };
+                        collect(value, &key, keys);
+                    }
+                }
+                _ => keys.push(prefix.to_string()),
+            }
+        }
+        let mut keys = Vec::new();
+        if let Ok(value) = serde_json::to_value(self) {
+            collect(&value, "", &mut keys);
+        }
+        keys
+    }
+
+    /// The value of a dotted key, as JSON with passwords, channel keys and on-connect commands hidden.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let mut value = serde_json::to_value(self).ok()?;
+        redact(&mut value);
+        key.split('.').try_fold(&value, |value, key| value.get(key)).map(|value| value.to_string())
+    }
+
+    /// Changes a dotted key to `value`, read as JSON or else as a string. Clients opened from now
+    /// on use the new value.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let mut config = serde_json::to_value(&*self).map_err(|e| e.to_string())?;
+        let slot = key
+            .split('.')
+            .try_fold(&mut config, |value, key| value.get_mut(key))
+            .ok_or(format!("Unknown setting {key}"))?;
+        *slot = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+        *self = serde_json::from_value(config).map_err(|e| format!("Invalid value for {key}: {e}"))?;
+        Ok(())
+    }
+
     pub fn save(&self) {
         let file_path = Self::config_file_path();
         match serde_json::to_string_pretty(self) {
-            Ok(json) => match fs::write(file_path, json.into_bytes()) {
-                Ok(_) => {
-                    return;
-                }
+            Ok(json) => match app::write_private(Path::new(&file_path), json.as_bytes()) {
+                Ok(_) => {}
                 Err(e) => {
                     eprintln!("Failed to save the configuration. Error: {e}.");
                 }
@@ -96,7 +659,7 @@ impl Config {
                         *user = clean;
                     }
 
-                    if !user.realname.is_empty() && !user.username.is_empty() && user.nicknames.len() > 0 {
+                    if !user.realname.is_empty() && !user.username.is_empty() && !user.nicknames.is_empty() {
                         if cleaned {
                             config.save();
                         }
@@ -109,6 +672,11 @@ impl Config {
         None
     }
 
+    /// Where the prompt history is kept between runs, next to the config.
+    pub fn history_file_path() -> String {
+        format!("{}_history", Self::config_file_path())
+    }
+
     fn config_file_path() -> String {
         format!(
             "{home}/.config/{app_name}",
@@ -123,6 +691,37 @@ impl Config {
     }
 }
 
+/// Fields shown as `****` by [`Config::get`], wherever they are nested.
+const SECRET_FIELDS: &[&str] = &["password", "key", "commands"];
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (name, value) in map.iter_mut() {
+                let empty = value.is_null() || value.as_str() == Some("") || value.as_array().is_some_and(|values| values.is_empty());
+                if SECRET_FIELDS.contains(&name.as_str()) && !empty {
+                    *value = Value::String("****".into());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// Whether `/set key value` would store a password, a channel key or an on-connect command.
+pub fn is_secret_setting(key: &str, value: &str) -> bool {
+    if key.split('.').any(|key| SECRET_FIELDS.contains(&key)) {
+        return true;
+    }
+    let plain: Value = serde_json::from_str(value).unwrap_or(Value::Null);
+    let mut redacted = plain.clone();
+    redact(&mut redacted);
+    redacted != plain
+}
+
 fn request_input(what: &'static str) -> Option<String> {
     let mut value = String::new();
     loop {
@@ -152,24 +751,11 @@ fn request_input(what: &'static str) -> Option<String> {
 pub fn request_config() -> Option<Config> {
     println!("To start using {} we need to know your username, real name and nickname.", app::Name());
     println!("Use CTRL+C to cancel.");
-    let username: String;
-    let realname: String;
     let mut nickname: String;
     let mut nicks: Vec<String> = Vec::<String>::new();
 
-    match request_input("username") {
-        None => {
-            return None;
-        }
-        Some(value) => username = value,
-    }
-
-    match request_input("real name") {
-        None => {
-            return None;
-        }
-        Some(value) => realname = value,
-    }
+    let username = request_input("username")?;
+    let realname = request_input("real name")?;
 
     loop {
         match request_input("nickname") {
@@ -210,3 +796,81 @@ pub fn request_config() -> Option<Config> {
 
     Config::new(user, None)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config::new(User::new(vec!["me".into(), "me_".into()], "user".into(), "Real Name".into()), None).unwrap()
+    }
+
+    #[test]
+    fn adds_and_removes_servers_by_name() {
+        let mut config = config();
+        assert!(config.add_server(Server::new("libera".into(), "irc.libera.chat".into(), 6697)));
+        assert!(!config.add_server(Server::new("Libera".into(), "other.example".into(), 6667)));
+        assert_eq!(config.servers.as_ref().map(Vec::len), Some(1));
+        assert!(config.find_server("LIBERA", None).is_some());
+        assert!(config.find_server("irc.libera.chat", Some(6697)).is_some());
+        assert!(config.find_server("irc.libera.chat", Some(6667)).is_none());
+
+        assert!(config.remove_server("LiBeRa"));
+        assert!(!config.remove_server("libera"));
+        assert!(config.find_server("libera", None).is_none());
+    }
+
+    #[test]
+    fn server_identity_falls_back_to_the_user() {
+        let user = config().user.unwrap();
+        let mut server = Server::new("libera".into(), "irc.libera.chat".into(), 6697);
+        assert_eq!(server.user(&user), user);
+
+        server.set("nicks", "other other_").unwrap();
+        server.set("realname", "  Someone Else ").unwrap();
+        let overridden = server.user(&user);
+        assert_eq!(overridden.nicknames, ["other", "other_"]);
+        assert_eq!(overridden.username, "user");
+        assert_eq!(overridden.realname, "Someone Else");
+
+        // An empty list in the file counts as no override
+        server.nicknames = Some(Vec::new());
+        server.set("realname", "").unwrap();
+        assert_eq!(server.user(&user), user);
+    }
+
+    #[test]
+    fn set_changes_server_settings() {
+        let mut server = Server::new("libera".into(), "irc.libera.chat".into(), 6697);
+        server.set("join", "#rust secret").unwrap();
+        server.set("join", "#crust").unwrap();
+        server.set("join", "#Rust newkey").unwrap();
+        assert_eq!(
+            server.autojoin(),
+            [("#crust".to_string(), None), ("#Rust".to_string(), Some("newkey".to_string()))]
+        );
+        server.set("part", "#crust").unwrap();
+        assert!(server.set("part", "#crust").is_err());
+
+        server.set("command", "PRIVMSG NickServ :IDENTIFY  two spaces").unwrap();
+        server.set("command", "MODE me +x").unwrap();
+        assert_eq!(server.commands(), ["PRIVMSG NickServ :IDENTIFY  two spaces", "MODE me +x"]);
+        server.set("command", "").unwrap();
+        assert!(server.commands().is_empty());
+
+        assert!(server.set("sasl", "plain account").is_err());
+        server.set("sasl", "plain account password -abort").unwrap();
+        let credentials = server.sasl().unwrap().credentials().unwrap();
+        assert_eq!((credentials.username.as_str(), credentials.password.as_str()), ("account", "password"));
+        assert!(credentials.abort_on_failure);
+        server.set("sasl", "external").unwrap();
+        assert_eq!(server.sasl().unwrap().credentials().unwrap().mechanism, SaslMechanism::External);
+        server.set("sasl", "off").unwrap();
+        assert!(server.sasl().is_none());
+
+        server.set("autoconnect", "on").unwrap();
+        assert!(server.autoconnect());
+        assert!(server.set("autoconnect", "maybe").is_err());
+        assert!(server.set("colour", "blue").unwrap_err().starts_with("Unknown server setting"));
+    }
+}
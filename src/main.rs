@@ -1,7 +1,7 @@
-use crate::client::{Client, UserInfo};
 use crate::config::Config;
 use crossterm::cursor::MoveTo;
-use crossterm::{terminal, QueueableCommand};
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use crossterm::{execute, terminal, QueueableCommand};
 use std::cell::RefCell;
 use std::io::Write;
 use std::rc::Rc;
@@ -15,10 +15,7 @@ mod tui;
 
 pub fn main() -> Result<(), usize> {
     let config = match Config::load() {
-        Some(config) => {
-            println!("{config:?}");
-            config
-        }
+        Some(config) => config,
         None => match config::request_config() {
             Some(config) => {
                 config.save();
@@ -31,20 +28,25 @@ pub fn main() -> Result<(), usize> {
         },
     };
 
-    let ref user = config.user.unwrap();
-    let client = Client::new(UserInfo::new(user.nicknames.iter().next().unwrap().clone(), user.username.clone(), user.realname.clone()).unwrap());
-
     thread::sleep(Duration::from_millis(1000));
     let (w, h) = terminal::size().unwrap();
     for _ in 0..h {
         println!();
     }
 
-    let client = Rc::new(RefCell::new(client));
+    let config = Rc::new(RefCell::new(config));
 
     let _ = terminal::enable_raw_mode();
+    // Mouse wheel scrolling of the chat
+    let _ = execute!(std::io::stdout(), EnableMouseCapture);
+    // A panic must not leave the terminal reporting mouse events
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        finalize(h);
+        hook(info);
+    }));
 
-    let mut frame = tui::Window::new(w, h, client);
+    let mut frame = tui::Window::new(w, h, config);
     if let Err(e) = frame.run() {
         finalize(h);
         println!("{e}");
@@ -58,6 +60,7 @@ pub fn main() -> Result<(), usize> {
 fn finalize(h: u16) {
     let mut out = std::io::stdout();
     let _ = out.queue(MoveTo(0, h + 1));
+    let _ = out.queue(DisableMouseCapture);
     let _ = out.flush();
     let _ = terminal::disable_raw_mode();
 }
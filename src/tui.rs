@@ -1,46 +1,58 @@
-use crate::client::Client;
+use crate::client::{Client, ClientEvent};
+use crate::config::Config;
+use crate::tui::buffers::{BufferKind, Buffers};
 use crate::tui::commands::CmdErr;
 use crate::tui::commands::CmdOk;
 use crate::tui::commands::CommandParser;
 use crate::tui::constants::MIN_CHAT_WIDTH;
+use crate::tui::history::InputHistory;
+use crate::tui::networks::{NetworkId, Networks};
+use crate::tui::search::Search;
 use crate::tui::traits::{Dirty, Draw, Resize};
 use crate::tui::widgets::bufferlist::BufferList;
-use crate::tui::widgets::chat::Chat;
+use crate::tui::widgets::chat::message::Message;
+use crate::tui::widgets::chat::{Chat, Scroll};
 use crate::tui::widgets::nicklist::NickList;
 use crate::tui::widgets::prompt::Prompt;
 use crate::tui::widgets::status::Status;
 use crate::tui::widgets::topic::Topic;
 use crate::tui::widgets::vertbar::{VertBar, VertBarType};
 use crossterm::cursor::MoveTo;
-use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEventKind};
 use crossterm::style::{Color, Print, Stylize};
 use crossterm::terminal::{Clear, ClearType};
 use crossterm::{event, QueueableCommand};
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token};
+use signal_hook::consts::{SIGHUP, SIGTERM, SIGWINCH};
+use signal_hook_mio::v0_8::Signals;
 use std::cell::RefCell;
-use std::io::Write;
+use std::io;
+use std::io::{ErrorKind, Write};
+use std::os::fd::AsRawFd;
+use std::path::Path;
 use std::rc::Rc;
-use std::time::Duration;
-use std::{io, thread};
+use std::time::{Duration, Instant};
 
+mod buffers;
 mod commands;
+mod completion;
 mod constants;
+mod history;
+mod networks;
 mod position;
+mod search;
 mod traits;
 mod widgets;
 
-macro_rules! todo_ui {
-    () => {
-        let _ = crossterm::terminal::disable_raw_mode();
-        println!();
-        todo!();
-    };
-    ($value:expr) => {
-        let _ = crossterm::terminal::disable_raw_mode();
-        println!();
-        println!("{:#?}", $value);
-        todo!();
-    };
-}
+const INPUT: Token = Token(0);
+const SIGNALS: Token = Token(1);
+/// Network `n` registers its sockets under `SERVER + n`.
+const SERVER: Token = Token(2);
+
+/// Messages scrolled by a turn of the mouse wheel.
+const MOUSE_SCROLL: usize = 3;
+
 pub struct Window {
     buffer_list: BufferList,
     topic: Topic,
@@ -53,8 +65,20 @@ pub struct Window {
     width: u16,
     height: u16,
     out: io::Stdout,
-    client: Rc<RefCell<Client>>,
+    networks: Rc<RefCell<Networks>>,
+    buffers: Rc<RefCell<Buffers>>,
+    config: Rc<RefCell<Config>>,
     parser: CommandParser,
+    finding: Option<Finding>,
+}
+
+/// Ctrl+F in progress.
+struct Finding {
+    /// The prompt before the search, back when it ends.
+    prompt: String,
+    /// The scroll of the buffer before the search, where every change of the query searches from
+    /// and where Esc goes back to.
+    scroll: usize,
 }
 
 macro_rules! set_all_dirty {
@@ -71,22 +95,29 @@ macro_rules! set_all_dirty {
 }
 
 impl Window {
-    pub fn new(width: u16, height: u16, client: Rc<RefCell<Client>>) -> Self {
-        let client_clone = client.clone();
+    pub fn new(width: u16, height: u16, config: Rc<RefCell<Config>>) -> Self {
+        let networks = Networks::new(config.clone());
+        let first = networks.iter().next().map(|(id, _)| id).unwrap_or_default();
+        let networks = Rc::new(RefCell::new(networks));
+        let buffers = Rc::new(RefCell::new(Buffers::new(first, config.borrow().scrollback.messages)));
+        let history = InputHistory::load(Path::new(&Config::history_file_path()), config.borrow().history.size);
         let mut result = Self {
-            buffer_list: BufferList::new(width, height),
-            topic: Topic::new(width, height),
-            chat: Chat::new(width, height),
-            nicks: NickList::new(width, height),
-            status: Status::new(width, height),
-            prompt: Prompt::new(width, height),
+            buffer_list: BufferList::new(width, height, buffers.clone()),
+            topic: Topic::new(width, height, networks.clone(), buffers.clone()),
+            chat: Chat::new(width, height, buffers.clone()),
+            nicks: NickList::new(width, height, networks.clone(), buffers.clone()),
+            status: Status::new(width, height, networks.clone(), buffers.clone()),
+            prompt: Prompt::new(width, height, history),
             left_bar: VertBar::new(width, height, VertBarType::Left),
             right_bar: VertBar::new(width, height, VertBarType::Right),
             width,
             height,
             out: std::io::stdout(),
-            client,
-            parser: CommandParser::new(client_clone),
+            parser: CommandParser::new(networks.clone(), buffers.clone(), config.clone()),
+            config,
+            networks,
+            buffers,
+            finding: None,
         };
         let _ = result.resize(width, height);
         result
@@ -170,95 +201,324 @@ impl Window {
         Ok(())
     }
 
+    /// Sleeps until the terminal, a server, a signal or a client timer needs attention.
     pub fn run(&mut self) -> io::Result<()> {
-        while self.poll() {
-            //Poll Client
-            self.client.borrow_mut().poll().iter().for_each(|s| self.chat.append(s.clone()));
+        let mut poll = Poll::new()?;
+        let mut events = Events::with_capacity(16);
+        poll.registry().register(&mut SourceFd(&io::stdin().as_raw_fd()), INPUT, Interest::READABLE)?;
+        let mut signals = Signals::new([SIGWINCH, SIGTERM, SIGHUP])?;
+        poll.registry().register(&mut signals, SIGNALS, Interest::READABLE)?;
+        self.networks.borrow_mut().set_reactor(poll.registry().try_clone()?, SERVER);
+        self.parser.autoconnect();
 
+        loop {
+            // Input first, so whatever a command sent shows up in the same pass
+            if !self.poll() {
+                break;
+            }
+            self.poll_networks();
+            self.status.update();
             self.draw()?;
 
-            thread::sleep(Duration::from_millis(16));
+            let timeout = self.networks.borrow().next_wakeup().map(|at| at.saturating_duration_since(Instant::now()));
+            if let Err(e) = poll.poll(&mut events, timeout) {
+                if e.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            // Resizes come through crossterm's own handler, SIGWINCH only needs to wake us up
+            if events.iter().any(|event| event.token() == SIGNALS) && signals.pending().any(|signal| signal != SIGWINCH) {
+                break;
+            }
         }
 
+        if let Err(e) = self.prompt.history().save() {
+            eprintln!("Failed to save the prompt history. Error: {e}.");
+        }
         Ok(())
     }
 
+    fn poll_networks(&mut self) {
+        let clients: Vec<(NetworkId, Rc<RefCell<Client>>)> = self.networks.borrow().iter().map(|(id, client)| (id, client.clone())).collect();
+        let mut changed = false;
+        let mut failed = Vec::new();
+        for (network, client) in clients {
+            let events = client.borrow_mut().poll();
+            if events.is_empty() {
+                continue;
+            }
+            changed = true;
+            let client = client.borrow();
+            let mut buffers = self.buffers.borrow_mut();
+            for event in events {
+                match &event {
+                    ClientEvent::Registered { nick } => {
+                        self.parser.connect_succeeded(network);
+                        self.remember_nick(&client, nick);
+                    }
+                    ClientEvent::Nick { new_nick, .. } if client.same_nick(new_nick, client.nick()) => self.remember_nick(&client, new_nick),
+                    ClientEvent::ConnectFailed { reason } => failed.push((network, reason.clone())),
+                    _ => {}
+                }
+                buffers.route(network, &client, event);
+            }
+        }
+        for (network, reason) in failed {
+            self.parser.connect_failed(network, &reason);
+        }
+        if changed {
+            self.buffers_changed();
+        }
+    }
+
     fn poll(&mut self) -> bool {
         while event::poll(Duration::ZERO).unwrap() {
             match event::read() {
-                Ok(Event::Resize(w, h)) => {
-                    if let Err(_) = self.resize(w, h) {
+                Ok(Event::Resize(w, h)) if self.resize(w, h).is_err() => {
+                    return false;
+                }
+                Ok(Event::Key(event)) if event.kind == KeyEventKind::Press => {
+                    if event.code == KeyCode::Char('c') && event.modifiers.contains(KeyModifiers::CONTROL) {
                         return false;
                     }
-                }
-                Ok(Event::Key(event)) => {
-                    if event.kind == KeyEventKind::Press {
-                        if let KeyCode::Char(ch) = event.code {
-                            if event.modifiers.contains(KeyModifiers::CONTROL) {
-                                if 'c' == ch {
-                                    return false;
-                                }
-                            }
-                        }
-                        if let Some(text) = self.prompt.key_press(event) {
-                            if self.parse(text) == CmdOk::Quit {
-                                return false;
-                            }
+                    if event.modifiers.contains(KeyModifiers::ALT) && self.switch_buffer(event.code) {
+                        continue;
+                    }
+                    if self.find(event) {
+                        continue;
+                    }
+                    if self.scroll(event) {
+                        continue;
+                    }
+                    // Tab completes in the context of the active buffer, which the parser knows
+                    if matches!(event.code, KeyCode::Tab | KeyCode::BackTab) {
+                        self.prompt
+                            .complete(event.code == KeyCode::Tab, |before, word| self.parser.complete(before, word));
+                        continue;
+                    }
+                    let buffer = self.buffers.borrow().active().name.to_string();
+                    if let Some(text) = self.prompt.key_press(event, &buffer) {
+                        if self.parse(text) == CmdOk::Quit {
+                            return false;
                         }
                     }
                 }
+                Ok(Event::Mouse(event)) => match event.kind {
+                    MouseEventKind::ScrollUp => self.chat.scroll(Scroll::Up(MOUSE_SCROLL)),
+                    MouseEventKind::ScrollDown => self.chat.scroll(Scroll::Down(MOUSE_SCROLL)),
+                    _ => {}
+                },
                 _ => {}
             }
         }
         true
     }
 
+    /// PageUp/PageDown move through the scrollback, Ctrl+Home/End jump to its ends, and so do
+    /// Home/End while the prompt is empty.
+    fn scroll(&mut self, event: KeyEvent) -> bool {
+        let jump = event.modifiers.contains(KeyModifiers::CONTROL) || (self.prompt.is_empty() && !self.prompt.is_searching());
+        match event.code {
+            KeyCode::PageUp => self.chat.scroll(Scroll::PageUp),
+            KeyCode::PageDown => self.chat.scroll(Scroll::PageDown),
+            KeyCode::Home if jump => self.chat.scroll(Scroll::Top),
+            KeyCode::End if jump => self.chat.scroll(Scroll::Bottom),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Ctrl+F searches the active buffer as the prompt is typed, taking the arguments of /search.
+    /// Ctrl+F again goes to an older match, Enter keeps the matches highlighted and Esc stops.
+    /// Right after, n and N move to older and newer matches until another key is pressed.
+    fn find(&mut self, event: KeyEvent) -> bool {
+        let control = event.modifiers.contains(KeyModifiers::CONTROL);
+        let Some(finding) = &self.finding else {
+            if self.chat.browse(event) {
+                return true;
+            }
+            let idle = self.prompt.is_empty() && !self.prompt.is_searching() && self.chat.has_search();
+            match event.code {
+                KeyCode::Char('f') if control => {
+                    self.finding = Some(Finding {
+                        prompt: self.prompt.line(),
+                        scroll: self.buffers.borrow().active().scroll,
+                    });
+                    self.prompt.set_line("");
+                    self.prompt.set_label(Some("(search)".into()));
+                }
+                KeyCode::Esc if idle => self.chat.clear_search(),
+                _ => return false,
+            }
+            return true;
+        };
+        let scroll = finding.scroll;
+        let found = match event.code {
+            KeyCode::Char('f') if control => self.chat.next_match(true),
+            KeyCode::Char('g') if control => {
+                self.cancel_finding(scroll);
+                return true;
+            }
+            KeyCode::Esc => {
+                self.cancel_finding(scroll);
+                return true;
+            }
+            KeyCode::Enter => {
+                self.stop_finding();
+                self.chat.browse_matches();
+                return true;
+            }
+            KeyCode::Char(_) if !control => self.prompt_search(event, scroll),
+            KeyCode::Backspace | KeyCode::Delete | KeyCode::Left | KeyCode::Right | KeyCode::Home | KeyCode::End => self.prompt_search(event, scroll),
+            _ => return true,
+        };
+        let label = if found { "(search)" } else { "(failed search)" };
+        self.prompt.set_label(Some(label.into()));
+        true
+    }
+
+    /// Edits the query of Ctrl+F and searches with it from the view at `scroll`.
+    fn prompt_search(&mut self, event: KeyEvent, scroll: usize) -> bool {
+        let buffer = self.buffers.borrow().active().name.to_string();
+        self.prompt.key_press(event, &buffer);
+        // Flags typed before the text would otherwise send the view to whatever they match
+        self.buffers.borrow_mut().set_scroll(scroll);
+        Search::parse(&self.prompt.line()).is_ok_and(|search| self.chat.search(search))
+    }
+
+    fn stop_finding(&mut self) {
+        if let Some(finding) = self.finding.take() {
+            self.prompt.set_line(&finding.prompt);
+            self.prompt.set_label(None);
+        }
+    }
+
+    /// Stops searching, scrolling back to `scroll`.
+    fn cancel_finding(&mut self, scroll: usize) {
+        self.chat.clear_search();
+        self.buffers.borrow_mut().set_scroll(scroll);
+        self.stop_finding();
+    }
+
+    /// Runs /search, stopping the search without arguments.
+    fn search(&mut self, args: &str) {
+        if args.trim().is_empty() {
+            self.chat.clear_search();
+            return;
+        }
+        match Search::parse(args).map(|search| self.chat.search(search)) {
+            Ok(true) => self.chat.browse_matches(),
+            Ok(false) => self.buffers.borrow_mut().push_active("No match".into()),
+            Err(e) => self.buffers.borrow_mut().push_active(e.into()),
+        }
+    }
+
+    /// Alt+1..9 jumps to a buffer, Alt+Left/Right moves to the previous/next one.
+    fn switch_buffer(&mut self, code: KeyCode) -> bool {
+        let mut buffers = self.buffers.borrow_mut();
+        let index = match code {
+            KeyCode::Char(ch @ '1'..='9') => ch as usize - '1' as usize,
+            KeyCode::Char('0') => 9,
+            KeyCode::Left => (buffers.active_index() + buffers.list().len() - 1) % buffers.list().len(),
+            KeyCode::Right => (buffers.active_index() + 1) % buffers.list().len(),
+            _ => return false,
+        };
+        buffers.set_active(index);
+        drop(buffers);
+        self.chat.stop_browsing();
+        self.buffers_changed();
+        true
+    }
+
+    /// Records the nick the server accepted in the config of the server we are on.
+    fn remember_nick(&self, client: &Client, nick: &str) {
+        let Some((address, port)) = client.server_address() else {
+            return;
+        };
+        let mut config = self.config.borrow_mut();
+        if config.find_server_mut(address, port).is_some_and(|server| server.set_last_nickname(nick)) {
+            config.save();
+        }
+    }
+
+    fn buffers_changed(&mut self) {
+        self.buffer_list.dirty();
+        self.topic.dirty();
+        self.chat.dirty();
+        self.nicks.dirty();
+    }
+
     fn parse(&mut self, command: String) -> CmdOk {
         let result = self.parser.try_run(&command);
         match result {
             Ok(success) => match success {
                 CmdOk::Ran => {}
-                CmdOk::Print(_text) => {
-                    todo_ui!();
+                CmdOk::Print(text) => {
+                    self.buffers.borrow_mut().push_active(text.into());
                 }
-                CmdOk::Help(_first, _second) => {
-                    todo_ui!();
+                CmdOk::Search(args) => self.search(&args),
+                CmdOk::Help(description, signature) => {
+                    let mut buffers = self.buffers.borrow_mut();
+                    buffers.push_active(signature.into());
+                    buffers.push_active(description.into());
                 }
                 CmdOk::Quit => {
                     return CmdOk::Quit;
                 }
             },
-            Err(error) => {
-                match error {
-                    CmdErr::NotConnected => {
-                        todo_ui!();
-                    }
-                    CmdErr::AlreadyConnected => {
-                        todo_ui!();
-                    }
-                    CmdErr::InvalidParameters => {
-                        todo_ui!();
-                    }
-                    CmdErr::InvalidCommand(cmd) => {
-                        todo_ui!(CmdErr::InvalidCommand(cmd));
-                    }
-                    CmdErr::HelpNotFound => {
-                        todo_ui!();
-                    }
-                    CmdErr::NotACommand => {
-                        if self.client.borrow().is_connected() {
-                            // This is a message
-                            // Send it to WarPigs (for now)
-                            let msg = self.client.borrow_mut().send_message("WarPigs".to_string(), command);
-                            self.chat.append(msg);
-                        } else {
-                            self.chat.append("Not connected".into());
+            Err(error) => match error {
+                CmdErr::NotConnected => {
+                    self.buffers.borrow_mut().push_active("Not connected".into());
+                }
+                CmdErr::InvalidParameters => {
+                    let name = command.trim_start_matches('/').split(' ').next().unwrap_or_default();
+                    let text = match self.parser.signature(name) {
+                        Some(signature) => format!("Invalid parameters, usage: {signature}"),
+                        None => "Invalid parameters".to_string(),
+                    };
+                    self.buffers.borrow_mut().push_active(text.into());
+                }
+                CmdErr::InvalidCommand(cmd) => {
+                    self.buffers.borrow_mut().push_active(format!("Unknown command {cmd}").into());
+                }
+                CmdErr::HelpNotFound => {
+                    self.buffers.borrow_mut().push_active("No help found, try /help <command>".into());
+                }
+                CmdErr::Failed(reason) => {
+                    self.buffers.borrow_mut().push_active(reason.into());
+                }
+                CmdErr::NotACommand => {
+                    let client = self.networks.borrow().get(self.buffers.borrow().active_network());
+                    let mut buffers = self.buffers.borrow_mut();
+                    let buffer = buffers.active();
+                    if buffer.kind == BufferKind::Server || buffer.kind == BufferKind::Transfers {
+                        buffers.push_active("Cannot send messages to this buffer".into());
+                    } else if buffer.kind == BufferKind::DccChat {
+                        let nick = buffer.name.to_string();
+                        let mut client = client.borrow_mut();
+                        match client.dcc_say(nick.trim_start_matches('='), &command, false) {
+                            Ok(()) => buffers.push_active(Message::FromUser {
+                                user: client.user_info().clone(),
+                                text: command,
+                            }),
+                            Err(e) => buffers.push_active(e.into()),
                         }
+                    } else if client.borrow().is_connected() {
+                        let target = buffer.name.to_string();
+                        let mut client = client.borrow_mut();
+                        client.send_message(&target, &command);
+                        buffers.push_active(Message::FromUser {
+                            user: client.user_info().clone(),
+                            text: command,
+                        });
+                    } else {
+                        buffers.push_active("Not connected".into());
                     }
                 }
-            }
+            },
         }
-        //self.chat.append(command);
+        self.buffers_changed();
         CmdOk::Ran
     }
 }
@@ -0,0 +1,441 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::app;
+use crate::client::{format_size, CaseMapping, Chat, ChatState, Client, ClientEvent, Direction, Identifier, Transfer, TransferState};
+use crate::tui::networks::NetworkId;
+use crate::tui::search::Search;
+use crate::tui::widgets::chat::message::Message;
+
+/// Name of the buffer listing DCC chats and transfers.
+const TRANSFERS: &str = "transfers";
+
+/// Width of the progress bar of a transfer, in cells.
+const PROGRESS_BAR_WIDTH: u64 = 20;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BufferKind {
+    Server,
+    Channel,
+    Query,
+    /// A DCC chat, named `=nick`.
+    DccChat,
+    /// The list of DCC chats and transfers, rewritten as they change.
+    Transfers,
+}
+
+/// Scrollback and state of a single server, channel or private query window.
+pub struct Buffer {
+    pub name: Identifier,
+    pub kind: BufferKind,
+    /// The network the buffer belongs to.
+    pub network: NetworkId,
+    pub messages: Vec<Message>,
+    pub unread: usize,
+    /// Messages below the view, 0 while following the newest one.
+    pub scroll: usize,
+    /// Search whose matches are highlighted, n and N moving between them right after it.
+    pub search: Option<Search>,
+    /// Shows messages without their colours and other formatting.
+    pub strip_formatting: bool,
+}
+
+impl Buffer {
+    fn new(name: Identifier, kind: BufferKind, network: NetworkId) -> Self {
+        Self {
+            name,
+            kind,
+            network,
+            messages: Vec::new(),
+            unread: 0,
+            scroll: 0,
+            search: None,
+            strip_formatting: false,
+        }
+    }
+}
+
+/// Every open buffer, grouped by network: the server buffer of a network comes first and its
+/// channels, queries and DCC buffers follow it.
+pub struct Buffers {
+    list: Vec<Buffer>,
+    active: usize,
+    /// CASEMAPPING of every network.
+    mappings: HashMap<NetworkId, CaseMapping>,
+    /// Messages kept in every buffer, the oldest going first.
+    scrollback: usize,
+}
+
+impl Buffers {
+    pub fn new(network: NetworkId, scrollback: usize) -> Self {
+        let mut buffers = Self {
+            list: Vec::new(),
+            active: 0,
+            mappings: HashMap::new(),
+            scrollback: scrollback.max(1),
+        };
+        buffers.add_network(network);
+        buffers
+    }
+
+    /// Adds the server buffer of a new network, at the end of the list.
+    pub fn add_network(&mut self, network: NetworkId) -> usize {
+        let mapping = CaseMapping::default();
+        self.mappings.insert(network, mapping);
+        self.list.push(Buffer::new(Identifier::new(app::Name(), mapping), BufferKind::Server, network));
+        self.list.len() - 1
+    }
+
+    /// Closes every buffer of a network, which must not be the last one.
+    pub fn remove_network(&mut self, network: NetworkId) {
+        let active = self.list[self.active].network;
+        self.list.retain(|buffer| buffer.network != network);
+        self.mappings.remove(&network);
+        self.active = if active == network {
+            0
+        } else {
+            self.list.iter().position(|buffer| buffer.network == active).unwrap_or(0)
+        };
+    }
+
+    pub fn mapping(&self, network: NetworkId) -> CaseMapping {
+        self.mappings.get(&network).copied().unwrap_or_default()
+    }
+
+    /// Follows the CASEMAPPING of the server so `#Rust` and `#rust` share a buffer.
+    fn set_casemapping(&mut self, network: NetworkId, mapping: CaseMapping) {
+        if mapping != self.mapping(network) {
+            self.mappings.insert(network, mapping);
+            self.list
+                .iter_mut()
+                .filter(|buffer| buffer.network == network)
+                .for_each(|buffer| buffer.name.remap(mapping));
+        }
+    }
+
+    pub fn list(&self) -> &[Buffer] {
+        &self.list
+    }
+
+    pub fn active(&self) -> &Buffer {
+        &self.list[self.active]
+    }
+
+    pub fn active_mut(&mut self) -> &mut Buffer {
+        &mut self.list[self.active]
+    }
+
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    /// The network of the active buffer, the one commands act on.
+    pub fn active_network(&self) -> NetworkId {
+        self.active().network
+    }
+
+    pub fn set_active(&mut self, index: usize) -> bool {
+        if index < self.list.len() {
+            self.active = index;
+            self.list[index].unread = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Index of the server buffer of `network`.
+    pub fn server(&self, network: NetworkId) -> usize {
+        self.list
+            .iter()
+            .position(|buffer| buffer.network == network && buffer.kind == BufferKind::Server)
+            .unwrap_or(0)
+    }
+
+    /// Shows which server a network is on until the server tells the name of its network.
+    pub fn rename_server(&mut self, network: NetworkId, name: &str) {
+        let (index, mapping) = (self.server(network), self.mapping(network));
+        self.list[index].name.rename(name, mapping);
+    }
+
+    pub fn find(&self, network: NetworkId, name: &str) -> Option<usize> {
+        let name = Identifier::new(name, self.mapping(network));
+        self.list.iter().position(|buffer| buffer.network == network && buffer.name == name)
+    }
+
+    fn find_query(&self, network: NetworkId, nick: &str) -> Option<usize> {
+        self.find(network, nick).filter(|index| self.list[*index].kind == BufferKind::Query)
+    }
+
+    /// Returns the index of the buffer of `network` called `name`, opening it after the other
+    /// buffers of the network if needed.
+    pub fn open(&mut self, network: NetworkId, name: &str, kind: BufferKind) -> usize {
+        if let Some(index) = self.find(network, name) {
+            return index;
+        }
+        let index = self
+            .list
+            .iter()
+            .rposition(|buffer| buffer.network == network)
+            .map_or(self.list.len(), |last| last + 1);
+        self.list
+            .insert(index, Buffer::new(Identifier::new(name, self.mapping(network)), kind, network));
+        if self.active >= index && self.list.len() > 1 {
+            self.active += 1;
+        }
+        index
+    }
+
+    /// Closes a buffer, server buffers only go with [`Buffers::remove_network`].
+    pub fn close(&mut self, index: usize) -> bool {
+        if index >= self.list.len() || self.list[index].kind == BufferKind::Server {
+            return false;
+        }
+        self.list.remove(index);
+        if self.active >= index {
+            self.active -= 1;
+        }
+        true
+    }
+
+    pub fn push(&mut self, index: usize, message: Message) {
+        let buffer = &mut self.list[index];
+        if index != self.active {
+            buffer.unread += 1;
+        }
+        // A scrolled view stays on the messages it shows
+        if buffer.scroll > 0 {
+            buffer.scroll += 1;
+        }
+        buffer.messages.push(message);
+        if buffer.messages.len() > self.scrollback {
+            let excess = buffer.messages.len() - self.scrollback;
+            buffer.messages.drain(..excess);
+            buffer.scroll = buffer.scroll.min(buffer.messages.len() - 1);
+            if let Some(search) = &mut buffer.search {
+                search.current = search.current.and_then(|current| current.checked_sub(excess));
+            }
+        }
+    }
+
+    /// Scrolls the active buffer to `scroll` messages above the newest one.
+    pub fn set_scroll(&mut self, scroll: usize) {
+        let buffer = &mut self.list[self.active];
+        buffer.scroll = scroll.min(buffer.messages.len().saturating_sub(1));
+    }
+
+    pub fn push_active(&mut self, message: Message) {
+        self.push(self.active, message);
+    }
+
+    /// Opens the transfer list of a network, filled with the current state of its DCC sessions.
+    pub fn open_transfers(&mut self, network: NetworkId, client: &Client) -> usize {
+        let index = self.open(network, TRANSFERS, BufferKind::Transfers);
+        self.show_transfers(network, client);
+        index
+    }
+
+    /// Rewrites the transfer list of a network if it is open.
+    fn show_transfers(&mut self, network: NetworkId, client: &Client) {
+        let Some(buffer) = self
+            .list
+            .iter_mut()
+            .find(|buffer| buffer.network == network && buffer.kind == BufferKind::Transfers)
+        else {
+            return;
+        };
+        let now = Instant::now();
+        let dcc = client.dcc();
+        buffer.messages = dcc.chats().iter().map(|chat| chat_line(chat).into()).collect();
+        buffer
+            .messages
+            .extend(dcc.transfers().iter().map(|transfer| transfer_line(transfer, now).into()));
+        if buffer.messages.is_empty() {
+            buffer.messages.push("No DCC chats or transfers".into());
+        }
+    }
+
+    /// Sends an event from the client of `network` to the buffers it belongs to.
+    pub fn route(&mut self, network: NetworkId, client: &Client, event: ClientEvent) {
+        self.set_casemapping(network, client.casemapping());
+        let mapping = self.mapping(network);
+        let server = self.server(network);
+        let own_nick = client.nick();
+        match &event {
+            ClientEvent::Info(_) | ClientEvent::Registered { .. } | ClientEvent::ConnectFailed { .. } | ClientEvent::Ctcp { .. } => {
+                self.push(server, event.into())
+            }
+            ClientEvent::CtcpReply { .. } if self.active().network == network => self.push_active(event.into()),
+            ClientEvent::CtcpReply { .. } => self.push(server, event.into()),
+            ClientEvent::DccChat { nick, .. } | ClientEvent::DccChatOpened { nick } => {
+                let index = self.open(network, &format!("={nick}"), BufferKind::DccChat);
+                self.push(index, event.into());
+            }
+            ClientEvent::DccChatClosed { nick, .. } => {
+                // Gone when /close ended the chat
+                if let Some(index) = self.find(network, &format!("={nick}")) {
+                    self.push(index, event.into());
+                }
+            }
+            ClientEvent::DccTransfers => self.show_transfers(network, client),
+            ClientEvent::Network { name } => {
+                self.list[server].name.rename(name, mapping);
+                self.push(server, event.into());
+            }
+            ClientEvent::Message { from, target, .. } | ClientEvent::Notice { from, target, .. } | ClientEvent::Action { from, target, .. } => {
+                let index = if client.is_channel(target) {
+                    self.open(network, target, BufferKind::Channel)
+                } else if matches!(event, ClientEvent::Notice { .. }) {
+                    self.find(network, from.nick()).unwrap_or(server)
+                } else {
+                    self.open(network, from.nick(), BufferKind::Query)
+                };
+                self.push(index, event.into());
+            }
+            ClientEvent::Join { user, channel } => {
+                let index = self.open(network, channel, BufferKind::Channel);
+                if client.same_nick(user.nick(), own_nick) {
+                    self.set_active(index);
+                }
+                self.push(index, event.into());
+            }
+            ClientEvent::Part { channel, .. } | ClientEvent::Kick { channel, .. } => {
+                // Gone when /close left the channel
+                if let Some(index) = self.find(network, channel) {
+                    self.push(index, event.into());
+                }
+            }
+            ClientEvent::Topic { channel, .. } | ClientEvent::ChannelInfo { channel, .. } => {
+                let index = self.open(network, channel, BufferKind::Channel);
+                self.push(index, event.into());
+            }
+            ClientEvent::Quit { user, channels, .. } => {
+                let mut indexes: Vec<usize> = channels.iter().filter_map(|channel| self.find(network, channel)).collect();
+                indexes.extend(self.find_query(network, user.nick()));
+                for index in indexes {
+                    self.push(index, event.clone().into());
+                }
+            }
+            ClientEvent::Nick { user, new_nick, channels } => {
+                let mut indexes: Vec<usize> = channels.iter().filter_map(|channel| self.find(network, channel)).collect();
+                if let Some(index) = self.find_query(network, user.nick()) {
+                    self.list[index].name.rename(new_nick, mapping);
+                    indexes.push(index);
+                }
+                if client.same_nick(new_nick, own_nick) {
+                    indexes.push(server);
+                }
+                for index in indexes {
+                    self.push(index, event.clone().into());
+                }
+            }
+            ClientEvent::Disconnected { .. } | ClientEvent::Reconnected => {
+                // Channel and query buffers stay open so the conversation picks up after a reconnect
+                for index in 0..self.list.len() {
+                    if self.list[index].network == network {
+                        self.push(index, event.clone().into());
+                    }
+                }
+            }
+            ClientEvent::Mode { target, .. } => {
+                let index = if client.is_channel(target) {
+                    self.open(network, target, BufferKind::Channel)
+                } else {
+                    server
+                };
+                self.push(index, event.into());
+            }
+        }
+    }
+}
+
+fn chat_line(chat: &Chat) -> String {
+    let state = match chat.state() {
+        ChatState::Offered => "offered, /dcc chat to accept",
+        ChatState::Waiting => "waiting",
+        ChatState::Open => "open",
+        ChatState::Closed => "closed",
+    };
+    format!("#{} chat with {}  {state}", chat.id(), chat.nick())
+}
+
+/// A transfer as a line of the list, like `#2 logs.txt -> bob [#####     ] 52% 1.2 MiB/2.3 MiB 340.0 KiB/s`.
+fn transfer_line(transfer: &Transfer, now: Instant) -> String {
+    let who = match transfer.direction() {
+        Direction::Send => format!("{} -> {}", transfer.file(), transfer.nick()),
+        Direction::Receive => format!("{} <- {}", transfer.file(), transfer.nick()),
+    };
+    let (position, size) = (transfer.position(), transfer.size());
+    let progress = match position.min(size).checked_mul(PROGRESS_BAR_WIDTH).and_then(|done| done.checked_div(size)) {
+        Some(filled) => format!(
+            "[{}{}] {}% {}/{}",
+            "#".repeat(filled as usize),
+            " ".repeat((PROGRESS_BAR_WIDTH - filled) as usize),
+            (position.min(size) as f64 * 100.0 / size as f64) as u64,
+            format_size(position),
+            format_size(size)
+        ),
+        // The sender did not tell the size
+        None => format_size(position),
+    };
+    let rate = || format!("{}/s", format_size(transfer.rate(now).unwrap_or_default() as u64));
+    let state = match transfer.state() {
+        TransferState::Offered if transfer.direction() == Direction::Receive => format!("offered, /dcc get {}", transfer.id()),
+        TransferState::Offered => "offered".to_string(),
+        TransferState::Resuming => "resuming".to_string(),
+        TransferState::Waiting => "waiting for the connection".to_string(),
+        TransferState::Active => rate(),
+        TransferState::Done => format!("done, {}", rate()),
+        TransferState::Failed(reason) => format!("failed: {reason}"),
+    };
+    format!("#{} {who} {progress} {state}", transfer.id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(buffer: &Buffer) -> Vec<String> {
+        buffer.messages.iter().map(Message::text).collect()
+    }
+
+    #[test]
+    fn push_drains_beyond_scrollback() {
+        let mut buffers = Buffers::new(0, 3);
+        for i in 0..5 {
+            buffers.push_active(format!("m{i}").into());
+        }
+        assert_eq!(texts(buffers.active()), ["m2", "m3", "m4"]);
+    }
+
+    #[test]
+    fn push_keeps_scrolled_view() {
+        let mut buffers = Buffers::new(0, 5);
+        for i in 0..3 {
+            buffers.push_active(format!("m{i}").into());
+        }
+        buffers.push_active("m3".into());
+        assert_eq!(buffers.active().scroll, 0);
+        buffers.set_scroll(1);
+        buffers.push_active("m4".into());
+        assert_eq!(buffers.active().scroll, 2);
+        // Once the oldest message goes, the view can't reach above it
+        buffers.push_active("m5".into());
+        buffers.push_active("m6".into());
+        buffers.push_active("m7".into());
+        assert_eq!(buffers.active().messages.len(), 5);
+        assert_eq!(buffers.active().scroll, 4);
+    }
+
+    #[test]
+    fn set_scroll_clamps() {
+        let mut buffers = Buffers::new(0, 10);
+        buffers.set_scroll(5);
+        assert_eq!(buffers.active().scroll, 0);
+        for i in 0..3 {
+            buffers.push_active(format!("m{i}").into());
+        }
+        buffers.set_scroll(100);
+        assert_eq!(buffers.active().scroll, 2);
+    }
+}
@@ -8,17 +8,26 @@
 // Twitch: https://www.twitch.tv/tsoding
 
 use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::Path;
 use std::rc::Rc;
 
-use crate::client::Client;
+use crate::client::{Client, TlsConfig, TlsVerify, UserInfo};
+use crate::config::{Config, Server, SERVER_FIELDS};
+use crate::tui::buffers::{BufferKind, Buffers};
 use crate::tui::commands::CmdErr::*;
 use crate::tui::commands::CmdOk::*;
+use crate::tui::completion::matching;
+use crate::tui::networks::{NetworkId, Networks};
+use crate::tui::widgets::chat::message::{Message, KINDS};
 
 #[derive(PartialEq)]
 pub enum CmdOk {
     Ran,
     Print(String),
     Help(String, String),
+    /// Search the active buffer with these arguments.
+    Search(String),
     Quit,
 }
 
@@ -26,36 +35,129 @@ pub enum CmdOk {
 pub enum CmdErr {
     NotACommand,
     NotConnected,
-    AlreadyConnected,
     InvalidParameters,
     InvalidCommand(String),
     HelpNotFound,
+    Failed(String),
 }
 
 pub type CommandResult = Result<CmdOk, CmdErr>;
 type CommandFunc = fn(&mut CommandParser, &str) -> CommandResult;
+/// Candidates for the next argument of a command, given the ones before it.
+type CompleteFunc = fn(&CommandParser, &[&str]) -> Vec<String>;
 
 struct Command {
     name: &'static str,
     description: &'static str,
     signature: &'static str,
     run: CommandFunc,
+    complete: Option<CompleteFunc>,
 }
 
 pub struct CommandParser {
-    client: Rc<RefCell<Client>>,
+    networks: Rc<RefCell<Networks>>,
+    buffers: Rc<RefCell<Buffers>>,
+    config: Rc<RefCell<Config>>,
     cmd_list: Vec<Command>,
+    /// Networks opened for a connect that has not registered yet, dropped again when it fails.
+    opened: HashSet<NetworkId>,
 }
 
 impl CommandParser {
-    pub fn new(client: Rc<RefCell<Client>>) -> Self {
-        let mut result = CommandParser { cmd_list: Vec::new(), client };
+    pub fn new(networks: Rc<RefCell<Networks>>, buffers: Rc<RefCell<Buffers>>, config: Rc<RefCell<Config>>) -> Self {
+        let mut result = CommandParser {
+            cmd_list: Vec::new(),
+            networks,
+            buffers,
+            config,
+            opened: HashSet::new(),
+        };
 
-        result.register("join", "Join a channel", "/join <channel>", Self::join);
-        result.register("j", "Join a channel", "/j <channel>", Self::join);
+        result.register("join", "Join a channel, with its <key> if it has one", "/join <channel> [key]", Self::join);
+        result.register("j", "Join a channel, with its <key> if it has one", "/j <channel> [key]", Self::join);
 
-        result.register("connect", "Connect to a server at <ip> and <port>", "/connect <ip> <port>", Self::connect);
-        result.register("c", "connects to quakenet", "/c", Self::connect_quakenet);
+        result.register(
+            "nick",
+            "Change your nick, also while the server refuses every configured one",
+            "/nick <nick>",
+            Self::nick,
+        );
+
+        result.register(
+            "connect",
+            "Connect to a server at <ip> and <port>, using TLS when the port starts with +, or to a configured server by <name>. \
+             Opens a new network when the active one is already connected",
+            "/connect <ip> [+]<port> | <name>",
+            Self::connect,
+        );
+        result.register(
+            "c",
+            "Connect to a server at <ip> and <port>, using TLS when the port starts with +, or to a configured server by <name>",
+            "/c <ip> [+]<port> | <name>",
+            Self::connect,
+        );
+
+        result.register(
+            "server",
+            "List, add, change, remove or connect to configured servers, -auto connecting on startup and #channels joined on connect. \
+             set changes the autoconnect, join, part, command, nicks, username, realname or sasl setting of a server, \
+             an empty value clearing the identity overrides and commands",
+            "/server list | add <name> <address> [+]<port> [-auto] [#channel...] | set <name> <setting> [value] | remove <name> | connect <name>",
+            Self::server,
+        );
+
+        result.register("buffer", "Switch to the buffer with <number> or <name>", "/buffer <number|name>", Self::buffer);
+        result.register("b", "Switch to the buffer with <number> or <name>", "/b <number|name>", Self::buffer);
+
+        result.register("query", "Open a private conversation with <nick>", "/query <nick>", Self::query);
+        result.register(
+            "close",
+            "Close the active buffer, closing a server buffer disconnects and closes its network",
+            "/close",
+            Self::close,
+        );
+
+        result.register("ignore", "Hide messages from <nick>, or list the ignored nicks", "/ignore [nick]", Self::ignore);
+        result.register("unignore", "Show messages from <nick> again", "/unignore <nick>", Self::unignore);
+
+        result.register("me", "Send <text> as an action, like * nick waves", "/me <text>", Self::me);
+        result.register(
+            "ctcp",
+            "Send a CTCP <command> like VERSION or PING to <nick>",
+            "/ctcp <nick> <command> [params]",
+            Self::ctcp,
+        );
+
+        result.register(
+            "dcc",
+            "Offer or accept a DCC chat, send a file, download offer <id>, cancel <id> or show the transfers",
+            "/dcc chat <nick> | send [-passive] <nick> <file> | get <id> | close <id> | list",
+            Self::dcc,
+        );
+
+        result.register(
+            "search",
+            "Search the active buffer for <text>, -i ignoring case, -r taking it as a regex, -nick and -kind keeping only \
+             messages from <nick> or of <kind>. Right after it, n and N move between the matches until another key, /search alone stops",
+            "/search [-i] [-r] [-nick <nick>] [-kind <kind>] [text]",
+            Self::search,
+        );
+
+        result.register(
+            "formatting",
+            "Show or strip the colours, bold and other formatting of messages in the active buffer",
+            "/formatting [show|strip]",
+            Self::formatting,
+        );
+
+        result.register("cap", "List the capabilities enabled on the server", "/cap", Self::cap);
+
+        result.register(
+            "set",
+            "List the settings, show the value of <key> or change it, networks opened afterwards using the new value",
+            "/set [key [value]]",
+            Self::set,
+        );
 
         result.register("quit", "Close the chat", "/quit", Self::quit);
         result.register("q", "Close the chat", "/q", Self::quit);
@@ -63,63 +165,593 @@ impl CommandParser {
         result.register("help", "Print help", "/help [command]", Self::help);
         result.register("h", "Print help", "/h [command]", Self::help);
 
+        for name in ["join", "j"] {
+            result.completer(name, |parser, args| if args.is_empty() { parser.channels() } else { Vec::new() });
+        }
+        for name in ["connect", "c"] {
+            result.completer(name, |parser, args| if args.is_empty() { parser.servers() } else { Vec::new() });
+        }
+        for name in ["buffer", "b"] {
+            result.completer(name, |parser, args| if args.is_empty() { parser.buffer_names() } else { Vec::new() });
+        }
+        for name in ["help", "h"] {
+            result.completer(name, |parser, args| {
+                if args.is_empty() {
+                    parser.cmd_list.iter().map(|command| command.name.to_string()).collect()
+                } else {
+                    Vec::new()
+                }
+            });
+        }
+        for name in ["query", "ignore"] {
+            result.completer(name, |parser, args| if args.is_empty() { parser.nicks() } else { Vec::new() });
+        }
+        result.completer("unignore", |parser, args| {
+            if args.is_empty() {
+                parser.client().borrow().ignored().map(|nick| nick.to_string()).collect()
+            } else {
+                Vec::new()
+            }
+        });
+        result.completer("ctcp", |parser, args| match args {
+            [] => parser.nicks(),
+            [_] => ["VERSION", "PING", "TIME", "SOURCE", "CLIENTINFO"].map(String::from).to_vec(),
+            _ => Vec::new(),
+        });
+        result.completer("dcc", |parser, args| match args {
+            [] => ["chat", "send", "get", "close", "list"].map(String::from).to_vec(),
+            ["chat"] => parser.nicks(),
+            ["send"] => [parser.nicks(), vec!["-passive".to_string()]].concat(),
+            ["send", "-passive"] => parser.nicks(),
+            _ => Vec::new(),
+        });
+        result.completer("server", |parser, args| match args {
+            [] => ["list", "add", "set", "remove", "connect"].map(String::from).to_vec(),
+            ["remove" | "connect" | "set"] => parser.servers(),
+            ["set", _] => SERVER_FIELDS.iter().map(|field| field.to_string()).collect(),
+            ["set", _, "autoconnect"] => ["on", "off"].map(String::from).to_vec(),
+            ["set", _, "sasl"] => ["plain", "external", "off"].map(String::from).to_vec(),
+            _ => Vec::new(),
+        });
+        result.completer("search", |parser, args| match args.last() {
+            Some(&"-nick") => parser.nicks(),
+            Some(&"-kind") => KINDS.map(String::from).to_vec(),
+            _ => ["-i", "-r", "-nick", "-kind"].map(String::from).to_vec(),
+        });
+        result.completer("formatting", |_, args| {
+            if args.is_empty() {
+                ["show", "strip"].map(String::from).to_vec()
+            } else {
+                Vec::new()
+            }
+        });
+        result.completer("set", |parser, args| if args.is_empty() { parser.config.borrow().keys() } else { Vec::new() });
+
         result
     }
 
+    /// Candidates for the word being typed after `before`: command names at the start of the line,
+    /// what the completer of the command suggests for its arguments, and otherwise channels or the
+    /// nicks of the active buffer.
+    pub fn complete(&self, before: &str, word: &str) -> Vec<String> {
+        if before.is_empty() && word.starts_with('/') {
+            return matching(self.cmd_list.iter().map(|command| format!("/{}", command.name)), word);
+        }
+        if let Some(line) = before.strip_prefix('/') {
+            let mut chunks = line.split(' ').filter(|s| !s.is_empty());
+            let name = chunks.next().unwrap_or_default();
+            let args: Vec<&str> = chunks.collect();
+            if let Some(complete) = self.find_command(name).and_then(|command| command.complete) {
+                return matching(complete(self, &args), word);
+            }
+        }
+        if !word.is_empty() && self.client().borrow().is_channel(word) {
+            return matching(self.channels(), word);
+        }
+        let nicks = matching(self.nicks(), word);
+        if before.is_empty() {
+            // Addressing someone at the start of the line
+            let suffix = self.config.borrow().completion.nick_suffix.clone();
+            nicks.into_iter().map(|nick| nick + &suffix).collect()
+        } else {
+            nicks
+        }
+    }
+
+    /// Nicks in the active buffer, the most recent speakers first.
+    fn nicks(&self) -> Vec<String> {
+        let buffers = self.buffers.borrow();
+        let buffer = buffers.active();
+        match buffer.kind {
+            BufferKind::Channel => {
+                let client = self.client();
+                let client = client.borrow();
+                let Some(channel) = client.channel(buffer.name.as_str()) else {
+                    return Vec::new();
+                };
+                channel
+                    .nicks_by_activity()
+                    .into_iter()
+                    .filter(|nick| !client.same_nick(nick, client.nick()))
+                    .map(|nick| nick.to_string())
+                    .collect()
+            }
+            BufferKind::Query => vec![buffer.name.to_string()],
+            BufferKind::DccChat => vec![buffer.name.as_str().trim_start_matches('=').to_string()],
+            BufferKind::Server | BufferKind::Transfers => Vec::new(),
+        }
+    }
+
+    /// Channels with a buffer on the active network.
+    fn channels(&self) -> Vec<String> {
+        let buffers = self.buffers.borrow();
+        let network = buffers.active_network();
+        buffers
+            .list()
+            .iter()
+            .filter(|buffer| buffer.network == network && buffer.kind == BufferKind::Channel)
+            .map(|buffer| buffer.name.to_string())
+            .collect()
+    }
+
+    fn buffer_names(&self) -> Vec<String> {
+        let buffers = self.buffers.borrow();
+        let network = buffers.active_network();
+        buffers
+            .list()
+            .iter()
+            .filter(|buffer| buffer.network == network)
+            .map(|buffer| buffer.name.to_string())
+            .collect()
+    }
+
+    fn servers(&self) -> Vec<String> {
+        match &self.config.borrow().servers {
+            Some(servers) => servers.iter().map(|server| server.name().to_string()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The client of the network of the active buffer.
+    fn client(&self) -> Rc<RefCell<Client>> {
+        self.networks.borrow().get(self.buffers.borrow().active_network())
+    }
+
+    /// The client to connect with: the active network if it is idle, or a new network otherwise.
+    fn idle_client(&mut self) -> (usize, Rc<RefCell<Client>>) {
+        let network = self.buffers.borrow().active_network();
+        let client = self.client();
+        if !client.borrow().is_connected() {
+            return (network, client);
+        }
+        let network = self.networks.borrow_mut().add();
+        let mut buffers = self.buffers.borrow_mut();
+        let index = buffers.add_network(network);
+        buffers.set_active(index);
+        (network, self.networks.borrow().get(network))
+    }
+
     fn connect(&mut self, argument: &str) -> CommandResult {
-        if self.client.borrow().is_connected() {
-            return Err(AlreadyConnected);
+        let config = self.config.clone();
+        let config = config.borrow();
+        let chunks: Vec<&str> = argument.split(' ').filter(|s| !s.is_empty()).collect();
+        match chunks[..] {
+            [name] => {
+                let server = config
+                    .find_server(name, None)
+                    .ok_or(Failed(format!("No server called {name}, see /server list")))?;
+                self.open(server.address(), server.port(), server.tls().map_err(Failed)?, Some(server))
+                    .map_err(Failed)?;
+            }
+            [ip, port] => {
+                let (port, tls) = match port.strip_prefix('+') {
+                    Some(port) => (port, true),
+                    None => (port, false),
+                };
+                let port = port.parse::<u16>().map_err(|_| InvalidParameters)?;
+                let server = config.find_server(ip, Some(port));
+                let tls = match server {
+                    Some(server) => server.tls().map_err(Failed)?.or_else(|| tls.then(|| TlsConfig::new(TlsVerify::System))),
+                    None => tls.then(|| TlsConfig::new(TlsVerify::System)),
+                };
+                self.open(ip, port, tls, server).map_err(Failed)?;
+            }
+            _ => return Err(InvalidParameters),
+        }
+        Ok(Ran)
+    }
+
+    /// Connects the active network, or a new one when it is busy, with the identity, SASL
+    /// credentials, autojoin channels and commands of `server` when it is a configured one.
+    fn open(&mut self, host: &str, port: u16, tls: Option<TlsConfig>, server: Option<&Server>) -> Result<(), String> {
+        let config = self.config.clone();
+        let config = config.borrow();
+        let user = config.user.as_ref().ok_or("No user configured")?;
+        let user = server.map_or_else(|| user.clone(), |server| server.user(user));
+        let user_info = UserInfo::new(user.nicknames.first().cloned().unwrap_or_default(), user.username, user.realname).map_err(|e| e.to_string())?;
+        let credentials = match server.and_then(|server| server.sasl()) {
+            Some(sasl) => Some(sasl.credentials()?),
+            None => None,
+        };
+
+        let (active, busy) = {
+            let buffers = self.buffers.borrow();
+            (buffers.active_index(), buffers.active_network())
+        };
+        let (network, client) = self.idle_client();
+        let result = {
+            let mut client = client.borrow_mut();
+            client.set_user_info(user_info);
+            client.set_nicknames(user.nicknames);
+            client.set_sasl(credentials);
+            client.set_autojoin(server.map(|server| server.autojoin()).unwrap_or_default());
+            client.set_on_connect(server.map(|server| server.commands().to_vec()).unwrap_or_default());
+            client.connect(host, port, tls.as_ref())
+        };
+        if let Err(e) = result {
+            // A network opened for this connection has nothing to show, go back to where we were
+            if network != busy {
+                let mut buffers = self.buffers.borrow_mut();
+                buffers.remove_network(network);
+                buffers.set_active(active);
+                self.networks.borrow_mut().remove(network);
+            }
+            return Err(e);
+        }
+        if network == busy {
+            self.opened.remove(&network);
+        } else {
+            self.opened.insert(network);
+        }
+        self.buffers.borrow_mut().rename_server(network, server.map_or(host, |server| server.name()));
+        Ok(())
+    }
+
+    /// The connect of `network` went through, it stays open whatever happens next.
+    pub fn connect_succeeded(&mut self, network: NetworkId) {
+        self.opened.remove(&network);
+    }
+
+    /// The connect of `network` failed: a network opened just for it goes away again.
+    pub fn connect_failed(&mut self, network: NetworkId, reason: &str) {
+        if !self.opened.remove(&network) {
+            return;
+        }
+        let mut buffers = self.buffers.borrow_mut();
+        let name = buffers.list()[buffers.server(network)].name.to_string();
+        buffers.remove_network(network);
+        self.networks.borrow_mut().remove(network);
+        buffers.push_active(format!("Could not connect to {name}: {reason}").into());
+    }
+
+    /// Connects to every server marked to connect on startup, each on its own network.
+    pub fn autoconnect(&mut self) {
+        let servers: Vec<Server> = match &self.config.borrow().servers {
+            Some(servers) => servers.iter().filter(|server| server.autoconnect()).cloned().collect(),
+            None => return,
+        };
+        for server in &servers {
+            let result = server.tls().and_then(|tls| self.open(server.address(), server.port(), tls, Some(server)));
+            if let Err(reason) = result {
+                self.buffers
+                    .borrow_mut()
+                    .push_active(format!("Could not connect to {}: {reason}", server.name()).into());
+            }
         }
+    }
 
+    fn server(&mut self, argument: &str) -> CommandResult {
         let chunks: Vec<&str> = argument.split(' ').filter(|s| !s.is_empty()).collect();
-        match &chunks[..] {
-            &[ip, port] => {
-                let _ = self.client.borrow_mut().connect(format!("{ip}:{port}"));
+        match chunks[..] {
+            ["list"] => {
+                let lines: Vec<String> = match &self.config.borrow().servers {
+                    Some(servers) if !servers.is_empty() => servers.iter().map(|server| server.describe()).collect(),
+                    _ => return Ok(Print("No servers configured, add one with /server add".into())),
+                };
+                let mut buffers = self.buffers.borrow_mut();
+                for line in lines {
+                    buffers.push_active(line.into());
+                }
                 Ok(Ran)
             }
+            ["add", name, address, port, ref options @ ..] => {
+                let (port, tls) = match port.strip_prefix('+') {
+                    Some(port) => (port, true),
+                    None => (port, false),
+                };
+                let port = port.parse::<u16>().map_err(|_| InvalidParameters)?;
+                let mut server = Server::new(name.to_string(), address.to_string(), port);
+                server.set_tls(tls);
+                for option in options {
+                    match *option {
+                        "-auto" => server.set_autoconnect(true),
+                        channel if channel.starts_with(['#', '&', '+', '!']) => server.add_autojoin(channel, None),
+                        _ => return Err(InvalidParameters),
+                    }
+                }
+                let mut config = self.config.borrow_mut();
+                if !config.add_server(server) {
+                    return Err(Failed(format!("There is already a server called {name}")));
+                }
+                config.save();
+                Ok(Print(format!("Added server {name}")))
+            }
+            ["remove", name] => {
+                let mut config = self.config.borrow_mut();
+                if !config.remove_server(name) {
+                    return Err(Failed(format!("No server called {name}")));
+                }
+                config.save();
+                Ok(Print(format!("Removed server {name}")))
+            }
+            ["set", name, field, ..] => {
+                let mut config = self.config.borrow_mut();
+                let server = config.server_mut(name).ok_or(Failed(format!("No server called {name}")))?;
+                server.set(field, after_words(argument, 3)).map_err(Failed)?;
+                config.save();
+                Ok(Print(format!("Changed {field} of {name}")))
+            }
+            ["connect", name] => self.connect(name),
             _ => Err(InvalidParameters),
         }
     }
 
-    fn connect_quakenet(&mut self, _argument: &str) -> CommandResult {
-        if self.client.borrow().is_connected() {
-            return Err(AlreadyConnected);
+    fn join(&mut self, argument: &str) -> CommandResult {
+        let client = self.client();
+        if !client.borrow().is_connected() {
+            return Err(NotConnected);
         }
 
-        let _ = self.client.borrow_mut().connect(format!("irc.quakenet.org:6667"));
+        let chunks: Vec<&str> = argument.split(' ').filter(|s| !s.is_empty()).collect();
+        match chunks[..] {
+            [channel] => {
+                client.borrow_mut().join(channel, None);
+            }
+            [channel, key] => {
+                client.borrow_mut().join(channel, Some(key));
+            }
+            _ => {
+                return Err(InvalidParameters);
+            }
+        }
         Ok(Ran)
     }
 
-    fn join(&mut self, argument: &str) -> CommandResult {
-        if !self.client.borrow().is_connected() {
+    fn nick(&mut self, argument: &str) -> CommandResult {
+        let client = self.client();
+        if !client.borrow().is_connected() {
             return Err(NotConnected);
         }
+        match argument.split_whitespace().collect::<Vec<_>>()[..] {
+            [nick] => client.borrow_mut().change_nick(nick),
+            _ => return Err(InvalidParameters),
+        }
+        Ok(Ran)
+    }
+
+    fn buffer(&mut self, argument: &str) -> CommandResult {
+        let mut buffers = self.buffers.borrow_mut();
+        let index = match argument.trim() {
+            "" => return Err(InvalidParameters),
+            name => match name.parse::<usize>() {
+                Ok(number) => number.wrapping_sub(1),
+                Err(_) => buffers.find(buffers.active_network(), name).ok_or(InvalidParameters)?,
+            },
+        };
+        if buffers.set_active(index) {
+            Ok(Ran)
+        } else {
+            Err(InvalidParameters)
+        }
+    }
 
+    fn query(&mut self, argument: &str) -> CommandResult {
         let chunks: Vec<&str> = argument.split(' ').filter(|s| !s.is_empty()).collect();
-        match &chunks[..] {
-            &[channel] => {
-                self.client.borrow_mut().join(channel);
+        match chunks[..] {
+            [nick] if !self.client().borrow().is_channel(nick) => {
+                let mut buffers = self.buffers.borrow_mut();
+                let network = buffers.active_network();
+                let index = buffers.open(network, nick, BufferKind::Query);
+                buffers.set_active(index);
+                Ok(Ran)
             }
-            _ => {
-                return Err(InvalidParameters);
+            _ => Err(InvalidParameters),
+        }
+    }
+
+    fn close(&mut self, _: &str) -> CommandResult {
+        let client = self.client();
+        let mut client = client.borrow_mut();
+        let mut buffers = self.buffers.borrow_mut();
+        let index = buffers.active_index();
+        let buffer = buffers.active();
+        let network = buffer.network;
+        if buffer.kind == BufferKind::Server {
+            // The last network stays so there is always somewhere to type /connect
+            if self.networks.borrow().len() == 1 {
+                return Err(Failed("Cannot close the last network".into()));
             }
+            client.disconnect("Leaving");
+            buffers.remove_network(network);
+            self.networks.borrow_mut().remove(network);
+            return Ok(Ran);
+        } else if buffer.kind == BufferKind::Channel && client.is_connected() {
+            let channel = buffer.name.to_string();
+            client.part(&channel);
+        } else if buffer.kind == BufferKind::DccChat {
+            let nick = buffer.name.to_string();
+            client.dcc_close_chat(nick.trim_start_matches('='));
+        }
+        if buffers.close(index) {
+            Ok(Ran)
+        } else {
+            Err(InvalidParameters)
+        }
+    }
+
+    fn me(&mut self, argument: &str) -> CommandResult {
+        let text = argument.trim();
+        if text.is_empty() {
+            return Err(InvalidParameters);
+        }
+        let client = self.client();
+        let mut client = client.borrow_mut();
+        // DCC chats do not go through the server
+        if !client.is_connected() && self.buffers.borrow().active().kind != BufferKind::DccChat {
+            return Err(NotConnected);
+        }
+        let mut buffers = self.buffers.borrow_mut();
+        let buffer = buffers.active();
+        let target = buffer.name.to_string();
+        match buffer.kind {
+            BufferKind::Server | BufferKind::Transfers => return Err(Failed("Cannot send actions to this buffer".into())),
+            BufferKind::DccChat => client.dcc_say(target.trim_start_matches('='), text, true).map_err(Failed)?,
+            BufferKind::Channel | BufferKind::Query => client.send_action(&target, text),
+        }
+        buffers.push_active(Message::Action {
+            user: client.user_info().clone(),
+            text: text.to_string(),
+        });
+        Ok(Ran)
+    }
+
+    fn ctcp(&mut self, argument: &str) -> CommandResult {
+        let mut chunks = argument.trim().splitn(3, ' ');
+        let (Some(target), Some(command)) = (chunks.next().filter(|s| !s.is_empty()), chunks.next()) else {
+            return Err(InvalidParameters);
+        };
+        let client = self.client();
+        if !client.borrow().is_connected() {
+            return Err(NotConnected);
         }
+        client.borrow_mut().send_ctcp(target, command, chunks.next().unwrap_or_default().trim());
         Ok(Ran)
     }
 
+    fn dcc(&mut self, argument: &str) -> CommandResult {
+        let chunks: Vec<&str> = argument.split(' ').filter(|s| !s.is_empty()).collect();
+        let id = |id: &str| id.trim_start_matches('#').parse::<usize>().map_err(|_| InvalidParameters);
+        let client = self.client();
+        match chunks[..] {
+            ["chat", nick] => client.borrow_mut().dcc_chat(nick).map_err(Failed)?,
+            ["send", "-passive", nick, _, ..] => {
+                let file = argument.splitn(4, ' ').nth(3).unwrap_or_default().trim();
+                client.borrow_mut().dcc_send(nick, Path::new(file), true).map_err(Failed)?;
+            }
+            ["send", nick, _, ..] => {
+                // File names may have spaces, everything after the nick is the path
+                let file = argument.splitn(3, ' ').nth(2).unwrap_or_default().trim();
+                client.borrow_mut().dcc_send(nick, Path::new(file), false).map_err(Failed)?;
+            }
+            ["get", number] => client.borrow_mut().dcc_get(id(number)?).map_err(Failed)?,
+            ["close", number] => {
+                if !client.borrow_mut().dcc_close(id(number)?) {
+                    return Err(Failed(format!("No open DCC chat or transfer {number}")));
+                }
+            }
+            ["list"] => {
+                let mut buffers = self.buffers.borrow_mut();
+                let network = buffers.active_network();
+                let index = buffers.open_transfers(network, &client.borrow());
+                buffers.set_active(index);
+            }
+            _ => return Err(InvalidParameters),
+        }
+        Ok(Ran)
+    }
+
+    fn search(&mut self, argument: &str) -> CommandResult {
+        Ok(Search(argument.to_string()))
+    }
+
+    fn formatting(&mut self, argument: &str) -> CommandResult {
+        let mut buffers = self.buffers.borrow_mut();
+        let buffer = buffers.active_mut();
+        match argument.trim() {
+            "" => {}
+            "show" => buffer.strip_formatting = false,
+            "strip" => buffer.strip_formatting = true,
+            _ => return Err(InvalidParameters),
+        }
+        let state = if buffer.strip_formatting { "stripped" } else { "shown" };
+        Ok(Print(format!("Formatting is {state} in {}", buffer.name)))
+    }
+
+    fn ignore(&mut self, argument: &str) -> CommandResult {
+        let chunks: Vec<&str> = argument.split(' ').filter(|s| !s.is_empty()).collect();
+        let client = self.client();
+        let mut client = client.borrow_mut();
+        match chunks[..] {
+            [] => {
+                let mut nicks: Vec<&str> = client.ignored().map(|nick| nick.as_str()).collect();
+                nicks.sort_unstable();
+                if nicks.is_empty() {
+                    Ok(Print("No nicks ignored".into()))
+                } else {
+                    Ok(Print(format!("Ignoring: {}", nicks.join(" "))))
+                }
+            }
+            [nick] if !client.is_channel(nick) => {
+                client.ignore(nick);
+                Ok(Print(format!("Ignoring {nick}")))
+            }
+            _ => Err(InvalidParameters),
+        }
+    }
+
+    fn unignore(&mut self, argument: &str) -> CommandResult {
+        let chunks: Vec<&str> = argument.split(' ').filter(|s| !s.is_empty()).collect();
+        match chunks[..] {
+            [nick] if self.client().borrow_mut().unignore(nick) => Ok(Print(format!("No longer ignoring {nick}"))),
+            [nick] => Err(Failed(format!("{nick} is not ignored"))),
+            _ => Err(InvalidParameters),
+        }
+    }
+
+    fn cap(&mut self, _: &str) -> CommandResult {
+        let client = self.client();
+        let client = client.borrow();
+        if !client.is_connected() {
+            return Err(NotConnected);
+        }
+        let enabled: Vec<&str> = client.capabilities().enabled().map(|cap| cap.as_str()).collect();
+        if enabled.is_empty() {
+            Ok(Print("No capabilities enabled".into()))
+        } else {
+            Ok(Print(format!("Capabilities enabled: {}", enabled.join(" "))))
+        }
+    }
+
+    fn set(&mut self, argument: &str) -> CommandResult {
+        let mut config = self.config.borrow_mut();
+        let mut chunks = argument.trim().splitn(2, ' ');
+        match (chunks.next().filter(|s| !s.is_empty()), chunks.next().map(|value| value.trim())) {
+            (None, _) => Ok(Print(format!("Settings: {}", config.keys().join(" ")))),
+            (Some(key), None | Some("")) => match config.get(key) {
+                Some(value) => Ok(Print(format!("{key} = {value}"))),
+                None => Err(Failed(format!("Unknown setting {key}"))),
+            },
+            (Some(key), Some(value)) => {
+                config.set(key, value).map_err(Failed)?;
+                config.save();
+                Ok(Print(format!("{key} = {}", config.get(key).unwrap_or_default())))
+            }
+        }
+    }
+
     fn quit(&mut self, _: &str) -> CommandResult {
         Ok(Quit)
     }
 
     fn help(&mut self, argument: &str) -> CommandResult {
         let chunks: Vec<&str> = argument.split(' ').filter(|s| !s.is_empty()).collect();
-        match &chunks[..] {
-            &[command] => {
+        match chunks[..] {
+            [command] => {
                 if let Some(cmd) = self.find_command(command) {
                     return Ok(Help(cmd.description.into(), cmd.signature.into()));
                 }
             }
-            &[] => {
+            [] => {
                 todo!();
             }
             _ => {
@@ -140,21 +772,34 @@ impl CommandParser {
                 description,
                 signature,
                 run: run_function,
+                complete: None,
             });
         }
     }
 
+    /// Completes the arguments of a registered command with `complete`.
+    fn completer(&mut self, name: &str, complete: CompleteFunc) {
+        if let Some(cmd) = self.cmd_list.iter_mut().find(|command| command.name == name) {
+            cmd.complete = Some(complete);
+        }
+    }
+
     fn find_command(&self, name: &str) -> Option<&Command> {
         self.cmd_list.iter().find(|command| command.name == name)
     }
 
-    pub fn try_run(&mut self, prompt: &String) -> CommandResult {
-        if let Some(prompt) = prompt.strip_prefix(&['/']) {
-            let mut iter = prompt.splitn(2, |x| x == ' ');
+    /// How `/name` is used, like `/join <channel> [key]`.
+    pub fn signature(&self, name: &str) -> Option<&str> {
+        self.find_command(name).map(|cmd| cmd.signature)
+    }
+
+    pub fn try_run(&mut self, prompt: &str) -> CommandResult {
+        if let Some(prompt) = prompt.strip_prefix('/') {
+            let mut iter = prompt.splitn(2, ' ');
             let name = iter.next().unwrap_or(prompt);
             let argument = iter.next().unwrap_or("");
-            return if let Some(command) = self.find_command(&name) {
-                (command.run)(self, &argument)
+            return if let Some(command) = self.find_command(name) {
+                (command.run)(self, argument)
             } else {
                 Err(InvalidCommand(format!("/{name}")))
             };
@@ -162,3 +807,12 @@ impl CommandParser {
         Err(NotACommand)
     }
 }
+
+/// What follows the first `count` words of `argument`, spacing kept.
+fn after_words(argument: &str, count: usize) -> &str {
+    let mut rest = argument.trim_start();
+    for _ in 0..count {
+        rest = rest.split_once(' ').map_or("", |(_, rest)| rest.trim_start());
+    }
+    rest
+}
@@ -0,0 +1,89 @@
+/// Tab cycling through the candidates for the word at the cursor.
+#[derive(Debug)]
+pub struct Completion {
+    /// Where the completed word starts in the prompt, in characters.
+    start: usize,
+    /// Characters of the prompt taken by the word or by the candidate replacing it.
+    len: usize,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+impl Completion {
+    /// Starts completing the `len` characters at `start`, with the first candidate, or the last
+    /// one when going backwards. None when there is nothing to complete with.
+    pub fn new(start: usize, len: usize, candidates: Vec<String>, forward: bool) -> Option<Self> {
+        if candidates.is_empty() {
+            return None;
+        }
+        let index = if forward { 0 } else { candidates.len() - 1 };
+        Some(Self { start, len, candidates, index })
+    }
+
+    /// Moves to the next or previous candidate, wrapping around.
+    pub fn cycle(&mut self, forward: bool) {
+        let len = self.candidates.len();
+        self.index = if forward { (self.index + 1) % len } else { (self.index + len - 1) % len };
+    }
+
+    /// The candidate to show and the characters it replaces, `start..start + len`.
+    pub fn replace(&mut self) -> (usize, usize, &str) {
+        let candidate = &self.candidates[self.index];
+        let len = std::mem::replace(&mut self.len, candidate.chars().count());
+        (self.start, len, candidate)
+    }
+}
+
+/// Where the word ending at `cursor` starts.
+pub fn word_start(line: &[char], cursor: usize) -> usize {
+    line[..cursor].iter().rposition(|ch| ch.is_whitespace()).map_or(0, |space| space + 1)
+}
+
+/// The candidates starting with `word`, ignoring case, in their order and without repeats.
+pub fn matching(candidates: impl IntoIterator<Item = String>, word: &str) -> Vec<String> {
+    let word = word.to_lowercase();
+    let mut matches: Vec<String> = Vec::new();
+    for candidate in candidates {
+        if candidate.to_lowercase().starts_with(&word) && !matches.contains(&candidate) {
+            matches.push(candidate);
+        }
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_word_before_the_cursor() {
+        let line: Vec<char> = "hello wor".chars().collect();
+        assert_eq!(word_start(&line, 9), 6);
+        assert_eq!(word_start(&line, 5), 0);
+        assert_eq!(word_start(&line, 6), 6);
+    }
+
+    #[test]
+    fn matches_prefixes_in_order() {
+        let candidates = ["Bob", "alice", "bobby", "Bob", "carol"].map(String::from);
+        assert_eq!(matching(candidates, "bo"), ["Bob", "bobby"]);
+    }
+
+    #[test]
+    fn cycles_both_ways_and_tracks_the_replaced_text() {
+        let candidates = vec!["alice".to_string(), "al".to_string()];
+        assert!(Completion::new(0, 1, Vec::new(), true).is_none());
+
+        let mut completion = Completion::new(4, 1, candidates.clone(), true).unwrap();
+        assert_eq!(completion.replace(), (4, 1, "alice"));
+        completion.cycle(true);
+        assert_eq!(completion.replace(), (4, 5, "al"));
+        completion.cycle(true);
+        assert_eq!(completion.replace(), (4, 2, "alice"));
+
+        let mut completion = Completion::new(0, 1, candidates, false).unwrap();
+        assert_eq!(completion.replace(), (0, 1, "al"));
+        completion.cycle(false);
+        assert_eq!(completion.replace(), (0, 2, "alice"));
+    }
+}
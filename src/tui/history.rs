@@ -0,0 +1,253 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{app, config};
+
+/// Services that are sent passwords in private messages.
+const SERVICES: &[&str] = &["NickServ", "ChanServ", "AuthServ", "Q"];
+
+/// Lines sent from the prompt, per buffer and across every buffer, newest last.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct InputHistory {
+    global: VecDeque<String>,
+    buffers: HashMap<String, VecDeque<String>>,
+    /// Lines kept in every list, the oldest going first.
+    #[serde(skip)]
+    size: usize,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    #[serde(skip)]
+    browsing: Option<Browsing>,
+}
+
+/// Where Up and Down are in a list, and what was typed before the first Up.
+#[derive(Debug)]
+struct Browsing {
+    /// The buffer whose list is browsed, None for the global one.
+    buffer: Option<String>,
+    /// Position counted from the newest line, 0 being the newest.
+    index: usize,
+    draft: String,
+}
+
+impl InputHistory {
+    /// Reads the history saved in `path`, starting empty when there is none. [`InputHistory::save`]
+    /// writes it back there.
+    pub fn load(path: &Path, size: usize) -> Self {
+        let mut history = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<InputHistory>(&content).ok())
+            .unwrap_or_default();
+        history.size = size;
+        history.path = Some(path.to_path_buf());
+        history.trim();
+        history
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        app::write_private(path, json.as_bytes()).map_err(|e| e.to_string())
+    }
+
+    fn trim(&mut self) {
+        let size = self.size;
+        for lines in std::iter::once(&mut self.global).chain(self.buffers.values_mut()) {
+            while lines.len() > size {
+                lines.pop_front();
+            }
+        }
+        self.buffers.retain(|_, lines| !lines.is_empty());
+    }
+
+    /// Remembers a line sent in `buffer`, unless it repeats the previous one or carries a secret.
+    pub fn add(&mut self, buffer: &str, line: &str) {
+        self.browsing = None;
+        if line.trim().is_empty() || self.size == 0 || is_secret(buffer, line) {
+            return;
+        }
+        for lines in [&mut self.global, self.buffers.entry(buffer.to_string()).or_default()] {
+            if lines.back().map(|last| last.as_str()) != Some(line) {
+                lines.push_back(line.to_string());
+            }
+        }
+        self.trim();
+    }
+
+    fn lines(&self, buffer: Option<&str>) -> Option<&VecDeque<String>> {
+        match buffer {
+            Some(buffer) => self.buffers.get(buffer),
+            None => Some(&self.global),
+        }
+    }
+
+    /// The line before the one shown, from the list of `buffer` or the global one. `draft` is
+    /// what the prompt holds, given back by [`InputHistory::next`] once past the newest line.
+    pub fn previous(&mut self, buffer: Option<&str>, draft: &str) -> Option<&str> {
+        let index = match &self.browsing {
+            Some(browsing) if browsing.buffer.as_deref() == buffer => browsing.index + 1,
+            _ => 0,
+        };
+        let len = self.lines(buffer).map_or(0, |lines| lines.len());
+        if index >= len {
+            return None;
+        }
+        match &mut self.browsing {
+            Some(browsing) if browsing.buffer.as_deref() == buffer => browsing.index = index,
+            _ => {
+                self.browsing = Some(Browsing {
+                    buffer: buffer.map(|buffer| buffer.to_string()),
+                    index,
+                    draft: draft.to_string(),
+                })
+            }
+        }
+        self.lines(buffer).map(|lines| lines[len - 1 - index].as_str())
+    }
+
+    /// The line after the one shown, or the draft once past the newest line.
+    pub fn next(&mut self, buffer: Option<&str>) -> Option<String> {
+        let browsing = self.browsing.take_if(|browsing| browsing.buffer.as_deref() == buffer)?;
+        if browsing.index == 0 {
+            return Some(browsing.draft);
+        }
+        let index = browsing.index - 1;
+        self.browsing = Some(Browsing { index, ..browsing });
+        let lines = self.lines(buffer)?;
+        lines.get(lines.len() - 1 - index).cloned()
+    }
+
+    /// Stops browsing, the next Up starting from the newest line again.
+    pub fn reset(&mut self) {
+        self.browsing = None;
+    }
+
+    /// The newest line containing `query` among those older than `before`, positions counted
+    /// from the newest line of the global list.
+    pub fn search(&self, query: &str, before: Option<usize>) -> Option<(usize, &str)> {
+        let start = before.map_or(0, |before| before + 1);
+        self.global
+            .iter()
+            .rev()
+            .enumerate()
+            .skip(start)
+            .find(|(_, line)| line.contains(query))
+            .map(|(index, line)| (index, line.as_str()))
+    }
+}
+
+/// Whether `line`, sent in `buffer`, holds a password, a channel key or an on-connect command:
+/// those are not written to the history file.
+fn is_secret(buffer: &str, line: &str) -> bool {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match &words[..] {
+        ["/join" | "/j", _, _, ..] => true,
+        ["/set", key, ..] => config::is_secret_setting(key, line.trim().splitn(3, ' ').nth(2).unwrap_or("").trim()),
+        ["/server", "set", _, "command" | "sasl", ..] => true,
+        ["/server", "set", _, "join", _, _, ..] => true,
+        [word, ..] if !word.starts_with('/') => SERVICES.iter().any(|service| service.eq_ignore_ascii_case(buffer)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(size: usize) -> InputHistory {
+        InputHistory {
+            size,
+            ..InputHistory::default()
+        }
+    }
+
+    #[test]
+    fn browsing_keeps_the_draft() {
+        let mut history = history(10);
+        history.add("#a", "one");
+        history.add("#b", "two");
+        history.add("#a", "three");
+
+        assert_eq!(history.previous(Some("#a"), "draft"), Some("three"));
+        assert_eq!(history.previous(Some("#a"), "three"), Some("one"));
+        assert_eq!(history.previous(Some("#a"), "one"), None);
+        assert_eq!(history.next(Some("#a")).as_deref(), Some("three"));
+        assert_eq!(history.next(Some("#a")).as_deref(), Some("draft"));
+        assert_eq!(history.next(Some("#a")), None);
+
+        assert_eq!(history.previous(None, ""), Some("three"));
+        assert_eq!(history.previous(None, "three"), Some("two"));
+    }
+
+    #[test]
+    fn size_caps_every_list_and_repeats_are_skipped() {
+        let mut history = history(2);
+        for line in ["a", "b", "b", "c"] {
+            history.add("#a", line);
+        }
+        assert_eq!(history.global, ["b", "c"]);
+        assert_eq!(history.buffers["#a"], ["b", "c"]);
+    }
+
+    #[test]
+    fn search_goes_back_from_the_last_match() {
+        let mut history = history(10);
+        for line in ["/join #rust", "hello", "/join #irc", "bye"] {
+            history.add("#a", line);
+        }
+        assert_eq!(history.search("join", None), Some((1, "/join #irc")));
+        assert_eq!(history.search("join", Some(1)), Some((3, "/join #rust")));
+        assert_eq!(history.search("join", Some(3)), None);
+        assert_eq!(history.search("", None), Some((0, "bye")));
+    }
+
+    #[test]
+    fn survives_a_restart() {
+        let path = std::env::temp_dir().join(format!("crust-history-{}", std::process::id()));
+        let mut history = InputHistory::load(&path, 10);
+        history.add("#a", "one");
+        history.add("#b", "two");
+        history.save().unwrap();
+        let mode = std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&path).unwrap().permissions());
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut history = InputHistory::load(&path, 1);
+        let _ = fs::remove_file(&path);
+        assert_eq!(history.global, ["two"]);
+        assert_eq!(history.previous(Some("#b"), ""), Some("two"));
+        history.reset();
+        assert_eq!(history.previous(Some("#a"), ""), Some("one"));
+    }
+
+    #[test]
+    fn leaves_out_secrets() {
+        let mut history = history(10);
+        for line in [
+            "/join #rust hunter2",
+            "/j #irc key",
+            "/set servers.0.password hunter2",
+            r##"/set servers.0.autojoin [{"channel": "#a", "key": "k"}]"##,
+            "/server set libera sasl plain nick hunter2",
+            "/server set libera command PRIVMSG NickServ :IDENTIFY hunter2",
+            "/server set libera join #a k",
+        ] {
+            history.add("#a", line);
+        }
+        history.add("NickServ", "IDENTIFY hunter2");
+        assert!(history.global.is_empty());
+
+        for line in ["/join #rust", "/set nickname crust", "/server set libera join #a"] {
+            history.add("#a", line);
+        }
+        history.add("NickServ", "/query ChanServ");
+        assert_eq!(
+            history.global,
+            ["/join #rust", "/set nickname crust", "/server set libera join #a", "/query ChanServ"]
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::time::Instant;
+
+use mio::{Registry, Token};
+
+use crate::client::{Client, UserInfo};
+use crate::config::Config;
+
+/// Numbers every network for as long as it is open, buffers use it to say where they belong.
+pub type NetworkId = usize;
+
+/// One client per network, each with its own connection, identity, channels and DCC sessions.
+pub struct Networks {
+    clients: BTreeMap<NetworkId, Rc<RefCell<Client>>>,
+    next_id: NetworkId,
+    /// Event loop the clients register their sockets with, network `n` under `first_token + n`.
+    reactor: Option<(Registry, Token)>,
+    config: Rc<RefCell<Config>>,
+}
+
+impl Networks {
+    /// Starts with one network, not connected yet.
+    pub fn new(config: Rc<RefCell<Config>>) -> Self {
+        let mut networks = Self {
+            clients: BTreeMap::new(),
+            next_id: 0,
+            reactor: None,
+            config,
+        };
+        networks.add();
+        networks
+    }
+
+    /// Opens a network with a client set up from the config.
+    pub fn add(&mut self) -> NetworkId {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut client = new_client(&self.config.borrow());
+        if let Some((registry, token)) = &self.reactor {
+            if let Ok(registry) = registry.try_clone() {
+                client.set_reactor(registry, Token(token.0 + id));
+            }
+        }
+        self.clients.insert(id, Rc::new(RefCell::new(client)));
+        id
+    }
+
+    pub fn remove(&mut self, id: NetworkId) -> bool {
+        self.clients.remove(&id).is_some()
+    }
+
+    /// The client of network `id`, which has to be open.
+    pub fn get(&self, id: NetworkId) -> Rc<RefCell<Client>> {
+        self.clients[&id].clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NetworkId, &Rc<RefCell<Client>>)> {
+        self.clients.iter().map(|(id, client)| (*id, client))
+    }
+
+    /// Registers the sockets of every client, now and later, with an event loop.
+    pub fn set_reactor(&mut self, registry: Registry, first_token: Token) {
+        for (id, client) in &self.clients {
+            if let Ok(registry) = registry.try_clone() {
+                client.borrow_mut().set_reactor(registry, Token(first_token.0 + id));
+            }
+        }
+        self.reactor = Some((registry, first_token));
+    }
+
+    /// The earliest time any client has timed work to do.
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        self.clients.values().filter_map(|client| client.borrow().next_wakeup()).min()
+    }
+}
+
+fn new_client(config: &Config) -> Client {
+    let user = config.user.as_ref().expect("the config was checked to have a user");
+    let mut client =
+        Client::new(UserInfo::new(user.nicknames[0].clone(), user.username.clone(), user.realname.clone()).expect("the user was checked to be complete"));
+    if let Some(capabilities) = &config.capabilities {
+        client.set_capabilities(capabilities.clone());
+    }
+    client.set_nicknames(user.nicknames.clone());
+    client.set_reconnect_policy(config.reconnect.policy());
+    client.set_flood_policy(config.flood.policy());
+    client.set_lag_policy(config.ping.policy());
+    client.set_ctcp_policy(config.ctcp.policy());
+    client.set_dcc_policy(config.dcc.policy());
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientEvent;
+    use crate::config::User;
+    use crate::tui::buffers::Buffers;
+    use crate::tui::commands::{CmdErr, CommandParser};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    struct Setup {
+        networks: Rc<RefCell<Networks>>,
+        buffers: Rc<RefCell<Buffers>>,
+        parser: CommandParser,
+    }
+
+    fn setup() -> Setup {
+        let user = User::new(vec!["me".into()], "user".into(), "Real Name".into());
+        let config = Rc::new(RefCell::new(Config::new(user, None).unwrap()));
+        let networks = Rc::new(RefCell::new(Networks::new(config.clone())));
+        let buffers = Rc::new(RefCell::new(Buffers::new(0, 100)));
+        let parser = CommandParser::new(networks.clone(), buffers.clone(), config);
+        Setup { networks, buffers, parser }
+    }
+
+    /// A port nothing listens on.
+    fn closed_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    /// Polls `network` until its connect fails, returning the reason.
+    fn connect_failure(networks: &Rc<RefCell<Networks>>, network: NetworkId) -> String {
+        let client = networks.borrow().get(network);
+        for _ in 0..500 {
+            let failed = client.borrow_mut().poll().into_iter().find_map(|event| match event {
+                ClientEvent::ConnectFailed { reason } => Some(reason),
+                _ => None,
+            });
+            if let Some(reason) = failed {
+                return reason;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the connect of network {network} did not fail");
+    }
+
+    #[test]
+    fn numbers_networks_without_reusing_ids() {
+        let setup = setup();
+        let mut networks = setup.networks.borrow_mut();
+        assert_eq!(networks.len(), 1);
+        assert_eq!(networks.add(), 1);
+        assert_eq!(networks.add(), 2);
+        assert!(networks.remove(1));
+        assert!(!networks.remove(1));
+        assert_eq!(networks.iter().map(|(id, _)| id).collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(networks.add(), 3);
+    }
+
+    #[test]
+    fn connects_on_a_new_network_only_when_the_active_one_is_busy() {
+        let mut setup = setup();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert!(setup.parser.try_run(&format!("/connect 127.0.0.1 {port}")).is_ok());
+        assert_eq!(setup.networks.borrow().len(), 1);
+        assert!(setup.parser.try_run(&format!("/connect 127.0.0.1 {port}")).is_ok());
+        assert_eq!(setup.networks.borrow().len(), 2);
+        let buffers = setup.buffers.borrow();
+        assert_eq!(buffers.active_network(), 1);
+        assert_eq!(buffers.active().name.as_str(), "127.0.0.1");
+    }
+
+    #[test]
+    fn commands_go_to_the_network_of_the_active_buffer() {
+        let mut setup = setup();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        for _ in 0..2 {
+            assert!(setup.parser.try_run(&format!("/connect 127.0.0.1 {port}")).is_ok());
+        }
+        let queued = |network| setup.networks.borrow().get(network).borrow().queued();
+
+        // Lines wait in the queue until the connection is open
+        assert!(setup.parser.try_run("/join #rust").is_ok());
+        assert_eq!((queued(0), queued(1)), (0, 1));
+        let server = setup.buffers.borrow().server(0);
+        setup.buffers.borrow_mut().set_active(server);
+        assert!(setup.parser.try_run("/join #crust").is_ok());
+        assert_eq!((queued(0), queued(1)), (1, 1));
+    }
+
+    #[test]
+    fn closing_a_server_buffer_drops_its_network_but_not_the_last() {
+        let mut setup = setup();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        for _ in 0..2 {
+            assert!(setup.parser.try_run(&format!("/connect 127.0.0.1 {port}")).is_ok());
+        }
+        assert!(setup.parser.try_run("/close").is_ok());
+        assert_eq!(setup.networks.borrow().len(), 1);
+        assert!(setup.buffers.borrow().list().iter().all(|buffer| buffer.network == 0));
+        assert!(matches!(setup.parser.try_run("/close"), Err(CmdErr::Failed(_))));
+        assert_eq!(setup.networks.borrow().len(), 1);
+    }
+
+    #[test]
+    fn a_failed_connect_drops_the_network_opened_for_it() {
+        let mut setup = setup();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(setup.parser.try_run(&format!("/connect 127.0.0.1 {port}")).is_ok());
+        assert!(setup.parser.try_run(&format!("/connect 127.0.0.1 {}", closed_port())).is_ok());
+        assert_eq!(setup.networks.borrow().len(), 2);
+
+        let reason = connect_failure(&setup.networks, 1);
+        setup.parser.connect_failed(1, &reason);
+        assert_eq!(setup.networks.borrow().len(), 1);
+        let buffers = setup.buffers.borrow();
+        assert!(buffers.list().iter().all(|buffer| buffer.network == 0));
+        assert!(buffers.active().messages.last().unwrap().text().starts_with("Could not connect to 127.0.0.1"));
+    }
+
+    #[test]
+    fn a_failed_connect_keeps_a_network_that_was_already_open() {
+        let mut setup = setup();
+        assert!(setup.parser.try_run(&format!("/connect 127.0.0.1 {}", closed_port())).is_ok());
+        let reason = connect_failure(&setup.networks, 0);
+        setup.parser.connect_failed(0, &reason);
+        assert_eq!(setup.networks.borrow().len(), 1);
+    }
+}
@@ -56,6 +56,7 @@ impl Point {
         (x, self.y).into()
     }
 
+    #[allow(dead_code)]
     pub fn set_y(&self, y: u16) -> Self {
         (self.x, y).into()
     }
@@ -0,0 +1,176 @@
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::client::CaseMapping;
+use crate::tui::widgets::chat::format;
+use crate::tui::widgets::chat::message::{Message, KINDS};
+
+/// What the text of a message is matched against.
+#[derive(Debug)]
+enum Pattern {
+    Text(String),
+    /// Case-insensitive text is an escaped regex too.
+    Regex(Regex),
+}
+
+/// A search through the scrollback of a buffer, and the match shown.
+#[derive(Debug)]
+pub struct Search {
+    pattern: Pattern,
+    nick: Option<String>,
+    kind: Option<&'static str>,
+    /// Index of the message matched last.
+    pub current: Option<usize>,
+    /// How the network of the searched buffer compares nicks, for `-nick`.
+    pub mapping: CaseMapping,
+}
+
+impl Search {
+    /// Reads `[-i] [-r] [-nick <nick>] [-kind <kind>] [text]`: `-i` ignores case, `-r` takes the
+    /// text as a regex, and the others only keep messages from `nick` or of one of [`KINDS`].
+    pub fn parse(args: &str) -> Result<Self, String> {
+        let (mut ignore_case, mut regex, mut nick, mut kind) = (false, false, None, None);
+        let mut rest = args.trim_start();
+        loop {
+            let (flag, after) = rest.split_once(' ').unwrap_or((rest, ""));
+            match flag {
+                "-i" => ignore_case = true,
+                "-r" => regex = true,
+                "-nick" | "-kind" => {
+                    let (value, after) = after.trim_start().split_once(' ').unwrap_or((after.trim_start(), ""));
+                    if value.is_empty() {
+                        return Err(format!("{flag} needs a value"));
+                    }
+                    if flag == "-nick" {
+                        nick = Some(value.to_string());
+                    } else {
+                        let Some(name) = KINDS.iter().find(|name| **name == value) else {
+                            return Err(format!("Unknown kind {value}, one of: {}", KINDS.join(" ")));
+                        };
+                        kind = Some(*name);
+                    }
+                    rest = after.trim_start();
+                    continue;
+                }
+                _ => break,
+            }
+            rest = after.trim_start();
+        }
+        let pattern = if regex || ignore_case {
+            let source = if regex { rest.to_string() } else { regex::escape(rest) };
+            let regex = RegexBuilder::new(&source).case_insensitive(ignore_case).build();
+            Pattern::Regex(regex.map_err(|e| format!("Invalid regex: {e}"))?)
+        } else {
+            Pattern::Text(rest.to_string())
+        };
+        Ok(Self {
+            pattern,
+            nick,
+            kind,
+            current: None,
+            mapping: CaseMapping::default(),
+        })
+    }
+
+    pub fn matches(&self, message: &Message) -> bool {
+        if self.kind.is_some_and(|kind| kind != message.kind()) {
+            return false;
+        }
+        if let Some(nick) = &self.nick {
+            if !message.nick().is_some_and(|from| self.mapping.equals(from, nick)) {
+                return false;
+            }
+        }
+        // Matched as shown, without formatting codes
+        let shown = format::strip(&message.text());
+        match &self.pattern {
+            Pattern::Text(text) => shown.contains(text.as_str()),
+            Pattern::Regex(regex) => regex.is_match(&shown),
+        }
+    }
+
+    /// Where the pattern matches in `line`, in bytes. An empty pattern matches nowhere.
+    pub fn ranges(&self, line: &str) -> Vec<Range<usize>> {
+        match &self.pattern {
+            Pattern::Text(text) if text.is_empty() => Vec::new(),
+            Pattern::Text(text) => line.match_indices(text.as_str()).map(|(start, text)| start..start + text.len()).collect(),
+            Pattern::Regex(regex) => regex.find_iter(line).map(|found| found.range()).filter(|range| !range.is_empty()).collect(),
+        }
+    }
+
+    /// The closest match older than `index`, or newer than it.
+    pub fn next(&self, messages: &[Message], index: usize, older: bool) -> Option<usize> {
+        if older {
+            (0..index.min(messages.len())).rev().find(|i| self.matches(&messages[*i]))
+        } else {
+            (index + 1..messages.len()).find(|i| self.matches(&messages[*i]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::UserInfo;
+
+    fn said(nick: &str, text: &str) -> Message {
+        Message::FromUser {
+            user: UserInfo::from_nick(nick.to_string()),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_flags_before_the_text() {
+        let search = Search::parse("-i -nick Bob  hello  world").unwrap();
+        assert_eq!(search.nick.as_deref(), Some("Bob"));
+        assert!(matches!(search.pattern, Pattern::Regex(_)));
+        assert!(search.matches(&said("bob", "HELLO  WORLD")));
+        assert!(!search.matches(&said("alice", "hello  world")));
+
+        let search = Search::parse("-kind join").unwrap();
+        assert_eq!(search.kind, Some("join"));
+        assert!(matches!(&search.pattern, Pattern::Text(text) if text.is_empty()));
+
+        assert!(Search::parse("-kind chat").is_err());
+        assert!(Search::parse("-nick").is_err());
+        assert!(Search::parse("-r (").is_err());
+        assert!(matches!(Search::parse("-x").unwrap().pattern, Pattern::Text(text) if text == "-x"));
+    }
+
+    #[test]
+    fn plain_text_keeps_case_and_regex_finds_ranges() {
+        let search = Search::parse("Rust").unwrap();
+        assert!(search.matches(&said("bob", "I like Rust")));
+        assert!(!search.matches(&said("bob", "I like rust")));
+        assert!(search.matches(&said("bob", "I like \x02Ru\x0304st")));
+
+        let search = Search::parse("-r -i r[a-z]+t").unwrap();
+        assert_eq!(search.ranges("Rust or rot"), [0..4, 8..11]);
+        assert!(Search::parse("").unwrap().ranges("anything").is_empty());
+    }
+
+    #[test]
+    fn moves_between_matches_both_ways() {
+        let messages = vec![said("bob", "a"), said("alice", "b"), "info".into(), said("bob", "c")];
+        let search = Search::parse("-nick bob").unwrap();
+        assert_eq!(search.next(&messages, messages.len(), true), Some(3));
+        assert_eq!(search.next(&messages, 3, true), Some(0));
+        assert_eq!(search.next(&messages, 0, true), None);
+        assert_eq!(search.next(&messages, 0, false), Some(3));
+        assert_eq!(search.next(&messages, 3, false), None);
+
+        let search = Search::parse("-kind info").unwrap();
+        assert_eq!(search.next(&messages, 10, true), Some(2));
+    }
+
+    #[test]
+    fn compares_nicks_with_the_casemapping() {
+        let mut search = Search::parse("-nick Foo[").unwrap();
+        assert!(search.matches(&said("foo{", "hi")));
+        search.mapping = CaseMapping::Ascii;
+        assert!(!search.matches(&said("foo{", "hi")));
+        assert!(search.matches(&said("FOO[", "hi")));
+    }
+}
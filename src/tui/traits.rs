@@ -12,13 +12,14 @@ pub trait Resize {
 
 pub trait Dirty {
     fn dirty(&mut self);
+    #[allow(dead_code)]
     fn clean(&mut self);
 }
 
 #[macro_export]
 macro_rules! impl_resize {
     (for $struct_name:ident) => {
-        use crate::tui::traits::Resize;
+        use $crate::tui::traits::Resize;
         impl Resize for $struct_name {
             fn resize(&mut self, pos: Point, size: Size) {
                 self.size = size;
@@ -31,7 +32,7 @@ macro_rules! impl_resize {
 #[macro_export]
 macro_rules! impl_dirty {
     (for $struct_name:ident) => {
-        use crate::tui::traits::Dirty;
+        use $crate::tui::traits::Dirty;
         impl Dirty for $struct_name {
             fn dirty(&mut self) {
                 self.dirty = true;
@@ -1,9 +1,12 @@
+use std::cell::RefCell;
 use std::io;
+use std::rc::Rc;
 
 use crossterm::cursor::MoveTo;
 use crossterm::style::{Color, Print, Stylize};
 use crossterm::QueueableCommand;
 
+use crate::tui::buffers::{BufferKind, Buffers};
 use crate::tui::constants::MIN_BUFFER_LIST_WIDTH;
 use crate::tui::position::{Point, Size};
 use crate::tui::traits::Draw;
@@ -12,16 +15,16 @@ use crate::{impl_dirty, impl_resize};
 pub struct BufferList {
     pub pos: Point,
     pub size: Size,
-    list: Vec<String>,
+    buffers: Rc<RefCell<Buffers>>,
     dirty: bool,
 }
 
 impl BufferList {
-    pub fn new(_width: u16, height: u16) -> Self {
+    pub fn new(_width: u16, height: u16, buffers: Rc<RefCell<Buffers>>) -> Self {
         BufferList {
             pos: (0, 0).into(),
             size: (MIN_BUFFER_LIST_WIDTH, height).into(),
-            list: Vec::new(),
+            buffers,
             dirty: true,
         }
     }
@@ -31,9 +34,28 @@ impl Draw for BufferList {
     fn draw(&mut self, out: &mut impl QueueableCommand) -> io::Result<()> {
         if self.dirty {
             self.dirty = false;
-            for (i, text) in self.list.iter().enumerate() {
+            let width = self.size.width as usize;
+            let buffers = self.buffers.borrow();
+            for i in 0..self.size.height as usize {
                 out.queue(MoveTo(self.pos.x, self.pos.y + i as u16))?;
-                out.queue(Print(text.clone().with(Color::Red)))?;
+                let Some(buffer) = buffers.list().get(i) else {
+                    out.queue(Print(format!("{:width$}", "")))?;
+                    continue;
+                };
+                // Buffers of a network are indented under its server buffer
+                let indent = if buffer.kind == BufferKind::Server { "" } else { " " };
+                let mut text = format!("{indent}{}.{}", i + 1, buffer.name);
+                if buffer.unread > 0 {
+                    text = format!("{text} ({})", buffer.unread);
+                }
+                let text = format!("{:width$.width$}", text);
+                if i == buffers.active_index() {
+                    out.queue(Print(text.with(Color::White).on(Color::Blue)))?;
+                } else if buffer.unread > 0 {
+                    out.queue(Print(text.with(Color::Yellow)))?;
+                } else {
+                    out.queue(Print(text.with(Color::Red)))?;
+                }
             }
         }
         Ok(())
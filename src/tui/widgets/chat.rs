@@ -1,35 +1,191 @@
+use crate::tui::buffers::{Buffer, Buffers};
 use crate::tui::constants::{MIN_BUFFER_LIST_WIDTH, MIN_NICK_LIST_WIDTH};
 use crate::tui::position::{Point, Size};
+use crate::tui::search::Search;
 use crate::tui::traits::Draw;
+use crate::tui::widgets::chat::format::{push_text, Span, Style};
+use crate::tui::widgets::chat::message::Message;
 use crate::{impl_dirty, impl_resize};
 use crossterm::cursor::MoveTo;
-use crossterm::style::Print;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print};
 use crossterm::QueueableCommand;
-use message::Message;
+use std::cell::RefCell;
 use std::io;
+use std::rc::Rc;
 
+pub mod format;
 pub mod message;
 
+/// How the view of the active buffer moves through its scrollback.
+pub enum Scroll {
+    /// Towards older messages, by about a screen.
+    PageUp,
+    PageDown,
+    /// Towards older messages, by a number of them.
+    Up(usize),
+    Down(usize),
+    /// To the oldest message.
+    Top,
+    /// Back to following the newest message.
+    Bottom,
+}
+
 pub struct Chat {
     pub pos: Point,
     size: Size,
-    content: Vec<Message>,
+    buffers: Rc<RefCell<Buffers>>,
     dirty: bool,
+    /// Right after a search, when n and N move between its matches.
+    browsing: bool,
 }
 
 impl Chat {
-    pub fn new(width: u16, height: u16) -> Self {
+    pub fn new(width: u16, height: u16, buffers: Rc<RefCell<Buffers>>) -> Self {
         Self {
             pos: (MIN_BUFFER_LIST_WIDTH + 1, 1).into(),
             size: (width - MIN_BUFFER_LIST_WIDTH - MIN_NICK_LIST_WIDTH - 2, height - 3).into(),
-            content: Vec::new(),
+            buffers,
             dirty: true,
+            browsing: false,
         }
     }
 
-    pub fn append(&mut self, text: String) {
+    fn rows(&self, message: &Message) -> usize {
+        split_lines_with_max_len(&format::parse(&message.text()), self.size.width as usize).len()
+    }
+
+    /// How many of `messages`, taken in order, fill the view. At least one.
+    fn fitting<'a>(&self, messages: impl Iterator<Item = &'a Message>) -> usize {
+        let mut rows = 0;
+        let count = messages
+            .take_while(|message| {
+                rows += self.rows(message);
+                rows <= self.size.height as usize
+            })
+            .count();
+        count.max(1)
+    }
+
+    /// The most the view scrolls, with the oldest message at its top.
+    fn top(&self, messages: &[Message]) -> usize {
+        messages.len().saturating_sub(self.fitting(messages.iter()))
+    }
+
+    /// Index of the message after the last one in view.
+    fn end(buffer: &Buffer) -> usize {
+        buffer.messages.len() - buffer.scroll.min(buffer.messages.len())
+    }
+
+    /// Scrolls message `index` to the bottom of the view, unless it is already in view.
+    fn show(&self, buffers: &mut Buffers, index: usize) {
+        let buffer = buffers.active();
+        let end = Self::end(buffer);
+        let start = end.saturating_sub(self.fitting(buffer.messages[..end].iter().rev()));
+        if index < start || index >= end {
+            let scroll = (buffer.messages.len() - 1 - index).min(self.top(&buffer.messages));
+            buffers.set_scroll(scroll);
+        }
+    }
+
+    pub fn scroll(&mut self, scroll: Scroll) {
+        let mut buffers = self.buffers.borrow_mut();
+        let messages = &buffers.active().messages;
+        let len = messages.len();
+        let current = buffers.active().scroll.min(len);
+        let value = match scroll {
+            Scroll::PageUp => current + self.fitting(messages[..len - current].iter().rev()),
+            Scroll::PageDown => current.saturating_sub(self.fitting(messages[len - current..].iter())),
+            Scroll::Up(count) => current + count,
+            Scroll::Down(count) => current.saturating_sub(count),
+            Scroll::Top => usize::MAX,
+            Scroll::Bottom => 0,
+        };
+        // No further than the oldest message at the top of the view
+        let top = self.top(messages);
+        buffers.set_scroll(value.min(top));
+        self.dirty = true;
+    }
+
+    /// Starts searching the active buffer from the bottom of the view, going to the closest
+    /// match above it or else below it. False when nothing matches.
+    pub fn search(&mut self, mut search: Search) -> bool {
+        let mut buffers = self.buffers.borrow_mut();
+        let buffer = buffers.active();
+        let end = Self::end(buffer);
+        search.mapping = buffers.mapping(buffer.network);
+        search.current = search
+            .next(&buffer.messages, end, true)
+            .or_else(|| search.next(&buffer.messages, end.saturating_sub(1), false));
+        let found = search.current;
+        buffers.active_mut().search = Some(search);
+        if let Some(index) = found {
+            self.show(&mut buffers, index);
+        }
+        self.dirty = true;
+        found.is_some()
+    }
+
+    /// Goes to the next older or newer match of the search in the active buffer. False when
+    /// there is no search or no further match.
+    pub fn next_match(&mut self, older: bool) -> bool {
+        let mut buffers = self.buffers.borrow_mut();
+        let buffer = buffers.active();
+        let Some(search) = &buffer.search else {
+            return false;
+        };
+        let found = match search.current {
+            Some(current) => search.next(&buffer.messages, current, older),
+            None if older => search.next(&buffer.messages, Self::end(buffer), true),
+            None => search.next(&buffer.messages, Self::end(buffer).saturating_sub(1), false),
+        };
+        let Some(index) = found else {
+            return false;
+        };
+        if let Some(search) = &mut buffers.active_mut().search {
+            search.current = Some(index);
+        }
+        self.show(&mut buffers, index);
+        self.dirty = true;
+        true
+    }
+
+    /// Lets n and N move between the matches of the search in the active buffer, until another key.
+    pub fn browse_matches(&mut self) {
+        self.browsing = self.has_search();
+    }
+
+    pub fn stop_browsing(&mut self) {
+        self.browsing = false;
+    }
+
+    /// While browsing matches, n and N go to the older and newer one and Esc stops the search.
+    /// Any other key stops browsing and is left to the caller, as is every key when not browsing.
+    pub fn browse(&mut self, event: KeyEvent) -> bool {
+        if !std::mem::take(&mut self.browsing) || !self.has_search() {
+            return false;
+        }
+        let plain = !event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+        match event.code {
+            KeyCode::Char('n') if plain => self.next_match(true),
+            KeyCode::Char('N') if plain => self.next_match(false),
+            KeyCode::Esc => {
+                self.clear_search();
+                return true;
+            }
+            _ => return false,
+        };
+        self.browsing = true;
+        true
+    }
+
+    pub fn has_search(&self) -> bool {
+        self.buffers.borrow().active().search.is_some()
+    }
+
+    pub fn clear_search(&mut self) {
+        self.buffers.borrow_mut().active_mut().search = None;
         self.dirty = true;
-        self.content.push(text.into());
     }
 }
 
@@ -37,30 +193,30 @@ impl Draw for Chat {
     fn draw(&mut self, out: &mut impl QueueableCommand) -> io::Result<()> {
         if self.dirty {
             self.dirty = false;
-            let mut screen: Vec<String> = Vec::new();
-            for item in self.content.clone().iter_mut() {
-                match item {
-                    Message::Info { message } => {
-                        let mut lines: Vec<String> = split_lines_with_max_len(&message, self.size.width as usize);
-                        screen.append(&mut lines);
-                    }
-                    Message::FromUser { .. } => {}
-                    Message::Join { .. } => {}
-                    Message::Leave { .. } => {}
-                    Message::Quick { .. } => {}
-                    Message::ChangeDay { .. } => {}
-                    Message::Mode { .. } => {}
+            // Every line with the index of its message
+            let mut screen: Vec<(usize, Vec<Span>)> = Vec::new();
+            let width = self.size.width as usize;
+            let buffers = self.buffers.borrow();
+            let buffer = buffers.active();
+            let end = Self::end(buffer);
+            // Only the messages that reach the view, newest first
+            for (index, item) in buffer.messages[..end].iter().enumerate().rev() {
+                let lines = split_lines_with_max_len(&spans(item, buffer.strip_formatting), width)
+                    .into_iter()
+                    .map(|line| (index, line));
+                screen.splice(0..0, lines);
+                if screen.len() >= self.size.height as usize {
+                    break;
                 }
             }
-            if screen.len() > self.size.height as usize {
-                for i in (0..self.size.height).rev() {
-                    out.queue(MoveTo(self.pos.x, self.pos.y + i))?;
-                    out.queue(Print(screen.pop().unwrap()))?;
-                }
-            } else {
-                for i in 0..screen.len() {
-                    out.queue(MoveTo(self.pos.x, self.pos.y + i as u16))?;
-                    out.queue(Print(screen.get(i).unwrap()))?;
+            let skipped = screen.len().saturating_sub(self.size.height as usize);
+            for i in 0..self.size.height as usize {
+                out.queue(MoveTo(self.pos.x, self.pos.y + i as u16))?;
+                match screen.get(skipped + i) {
+                    Some((index, line)) => print_line(out, line, *index, &buffer.messages[*index], buffer.search.as_ref())?,
+                    None => {
+                        out.queue(Print(format!("{:width$}", "")))?;
+                    }
                 }
             }
         }
@@ -68,24 +224,128 @@ impl Draw for Chat {
     }
 }
 
-fn split_lines_with_max_len(input: &str, max_len: usize) -> Vec<String> {
+/// The text of a message in the styles of its formatting codes, or plain when they are stripped.
+fn spans(message: &Message, strip: bool) -> Vec<Span> {
+    if strip {
+        vec![Span {
+            text: format::strip(&message.text()),
+            style: Style::default(),
+        }]
+    } else {
+        format::parse(&message.text())
+    }
+}
+
+/// Prints a line of a message, the current match of `search` reversed and the text matched in
+/// other matches highlighted.
+fn print_line(out: &mut impl QueueableCommand, line: &[Span], index: usize, message: &Message, search: Option<&Search>) -> io::Result<()> {
+    let search = search.filter(|search| search.matches(message));
+    let current = search.is_some_and(|search| search.current == Some(index));
+    let text: String = line.iter().map(|span| span.text.as_str()).collect();
+    let ranges = match search {
+        Some(search) if !current => search.ranges(&text),
+        _ => Vec::new(),
+    };
+    let mut start = 0;
+    for span in line {
+        let end = start + span.text.len();
+        let mut style = span.style.content_style();
+        if current {
+            style.attributes.toggle(Attribute::Reverse);
+        }
+        let mut printed = start;
+        for range in ranges.iter().filter(|range| range.start < end && range.end > start) {
+            let (from, to) = (range.start.max(start), range.end.min(end));
+            out.queue(Print(style.apply(&text[printed..from])))?;
+            let mut highlight = style;
+            highlight.foreground_color = Some(Color::Black);
+            highlight.background_color = Some(Color::Yellow);
+            out.queue(Print(highlight.apply(&text[from..to])))?;
+            printed = to;
+        }
+        out.queue(Print(style.apply(&text[printed..end])))?;
+        start = end;
+    }
+    Ok(())
+}
+
+/// The words of styled text, each with the style of the whitespace before it.
+fn words(input: &[Span]) -> Vec<(Style, Vec<Span>)> {
+    let mut words: Vec<(Style, Vec<Span>)> = Vec::new();
+    let mut space: Option<Style> = None;
+    let mut in_word = false;
+    for span in input {
+        for ch in span.text.chars() {
+            if ch.is_whitespace() {
+                space.get_or_insert(span.style);
+                in_word = false;
+                continue;
+            }
+            if !in_word {
+                words.push((space.take().unwrap_or_default(), Vec::new()));
+                in_word = true;
+            }
+            if let Some((_, word)) = words.last_mut() {
+                push_text(word, ch.encode_utf8(&mut [0; 4]), span.style);
+            }
+        }
+    }
+    words
+}
+
+/// Cuts a word into pieces of `max_len` characters with their lengths, the last one maybe shorter.
+fn cut_word(word: Vec<Span>, max_len: usize) -> Vec<(Vec<Span>, usize)> {
+    let mut pieces: Vec<(Vec<Span>, usize)> = vec![(Vec::new(), 0)];
+    for span in word {
+        for ch in span.text.chars() {
+            if pieces.last().is_some_and(|(_, len)| *len >= max_len) {
+                pieces.push((Vec::new(), 0));
+            }
+            if let Some((piece, len)) = pieces.last_mut() {
+                push_text(piece, ch.encode_utf8(&mut [0; 4]), span.style);
+                *len += 1;
+            }
+        }
+    }
+    pieces
+}
+
+/// Wraps styled text into lines of `max_len` characters padded with plain spaces, a style
+/// going past the end of a line going on at the start of the next one. Words longer than a
+/// line are cut at its end.
+fn split_lines_with_max_len(input: &[Span], max_len: usize) -> Vec<Vec<Span>> {
+    fn finish(mut line: Vec<Span>, len: usize, max_len: usize) -> Vec<Span> {
+        if len < max_len {
+            push_text(&mut line, &" ".repeat(max_len - len), Style::default());
+        }
+        line
+    }
+
+    let max_len = max_len.max(1);
     let mut result = Vec::new();
-    let mut current_line = String::with_capacity(max_len);
+    let mut current_line: Vec<Span> = Vec::new();
+    let mut current_len = 0;
 
-    for word in input.split_whitespace() {
-        if current_line.is_empty() {
-            current_line.push_str(word);
-        } else if current_line.len() + 1 + word.len() <= max_len {
-            current_line.push_str(&format!(" {}", word));
-        } else {
-            result.push(format!("{:max_len$}", current_line));
-            current_line.clear();
-            current_line.push_str(word);
+    for (space, word) in words(input) {
+        let len: usize = word.iter().map(|span| span.text.chars().count()).sum();
+        if current_len > 0 && current_len + 1 + len <= max_len {
+            push_text(&mut current_line, " ", space);
+            for span in word {
+                push_text(&mut current_line, &span.text, span.style);
+            }
+            current_len += 1 + len;
+            continue;
+        }
+        if current_len > 0 {
+            result.push(finish(std::mem::take(&mut current_line), current_len, max_len));
         }
+        let mut pieces = cut_word(word, max_len);
+        (current_line, current_len) = pieces.pop().unwrap_or_default();
+        result.extend(pieces.into_iter().map(|(piece, len)| finish(piece, len, max_len)));
     }
 
-    if !current_line.is_empty() {
-        result.push(format!("{:max_len$}", current_line));
+    if current_len > 0 {
+        result.push(finish(current_line, current_len, max_len));
     }
 
     result
@@ -93,3 +353,105 @@ fn split_lines_with_max_len(input: &str, max_len: usize) -> Vec<String> {
 
 impl_resize!(for Chat);
 impl_dirty!(for Chat);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tui::widgets::chat::format::parse;
+
+    fn span(text: &str, style: Style) -> Span {
+        Span { text: text.to_string(), style }
+    }
+
+    #[test]
+    fn wraps_styles_over_line_ends() {
+        let bold = Style {
+            bold: true,
+            ..Style::default()
+        };
+        let red = Style {
+            foreground: Some(Color::Red),
+            ..Style::default()
+        };
+        let bold_red = Style { bold: true, ..red };
+        let lines = split_lines_with_max_len(&parse("aa \x02bb \x034cc\x02 dd\x03"), 6);
+        assert_eq!(
+            lines,
+            [
+                vec![span("aa ", Style::default()), span("bb", bold), span(" ", Style::default())],
+                vec![span("cc", bold_red), span(" dd", red), span(" ", Style::default())],
+            ]
+        );
+        for line in &lines {
+            assert_eq!(line.iter().map(|span| span.text.chars().count()).sum::<usize>(), 6);
+        }
+    }
+
+    #[test]
+    fn cuts_words_longer_than_a_line() {
+        let bold = Style {
+            bold: true,
+            ..Style::default()
+        };
+        let lines = split_lines_with_max_len(&parse("ab \x02cdefghi\x02jklm n"), 4);
+        assert_eq!(
+            lines,
+            [
+                vec![span("ab  ", Style::default())],
+                vec![span("cdef", bold)],
+                vec![span("ghi", bold), span("j", Style::default())],
+                vec![span("klm ", Style::default())],
+                vec![span("n   ", Style::default())],
+            ]
+        );
+        for line in lines.iter().chain(&split_lines_with_max_len(&parse("\x02abcdefghij"), 4)) {
+            assert_eq!(line.iter().map(|span| span.text.chars().count()).sum::<usize>(), 4);
+        }
+    }
+
+    #[test]
+    fn words_take_the_style_of_the_space_before_them() {
+        let bold = Style {
+            bold: true,
+            ..Style::default()
+        };
+        assert_eq!(
+            words(&parse("a\x02 b\x02c")),
+            [
+                (Style::default(), vec![span("a", Style::default())]),
+                (bold, vec![span("b", bold), span("c", Style::default())]),
+            ]
+        );
+    }
+
+    #[test]
+    fn n_and_shift_n_move_between_matches_until_another_key() {
+        let buffers = Rc::new(RefCell::new(Buffers::new(0, 100)));
+        for text in ["match one", "other", "match two", "match three"] {
+            buffers.borrow_mut().push_active(text.into());
+        }
+        let mut chat = Chat::new(80, 24, buffers.clone());
+        let current = || buffers.borrow().active().search.as_ref().and_then(|search| search.current);
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+
+        assert!(!chat.browse(key(KeyCode::Char('n'))));
+        assert!(chat.search(Search::parse("match").unwrap()));
+        chat.browse_matches();
+        assert_eq!(current(), Some(3));
+        assert!(chat.browse(key(KeyCode::Char('n'))));
+        assert!(chat.browse(key(KeyCode::Char('n'))));
+        assert_eq!(current(), Some(0));
+        assert!(chat.browse(KeyEvent::new(KeyCode::Char('N'), KeyModifiers::SHIFT)));
+        assert_eq!(current(), Some(2));
+
+        // Typing starts a message, n included
+        assert!(!chat.browse(key(KeyCode::Char('h'))));
+        assert!(!chat.browse(key(KeyCode::Char('n'))));
+        assert_eq!(current(), Some(2));
+
+        chat.browse_matches();
+        assert!(chat.browse(key(KeyCode::Esc)));
+        assert!(!chat.has_search());
+        assert!(!chat.browse(key(KeyCode::Char('n'))));
+    }
+}
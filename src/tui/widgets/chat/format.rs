@@ -0,0 +1,253 @@
+use crossterm::style::{Attribute, Color, ContentStyle};
+
+const BOLD: char = '\x02';
+const COLOR: char = '\x03';
+const HEX_COLOR: char = '\x04';
+const MONOSPACE: char = '\x11';
+const REVERSE: char = '\x16';
+const ITALIC: char = '\x1D';
+const STRIKETHROUGH: char = '\x1E';
+const UNDERLINE: char = '\x1F';
+const RESET: char = '\x0F';
+
+/// mIRC colours 0 to 15, named so they follow the palette of the terminal.
+const COLORS: [Color; 16] = [
+    Color::White,
+    Color::Black,
+    Color::DarkBlue,
+    Color::DarkGreen,
+    Color::Red,
+    Color::DarkRed,
+    Color::DarkMagenta,
+    Color::DarkYellow,
+    Color::Yellow,
+    Color::Green,
+    Color::DarkCyan,
+    Color::Cyan,
+    Color::Blue,
+    Color::Magenta,
+    Color::DarkGrey,
+    Color::Grey,
+];
+
+/// The extended mIRC colours 16 to 98.
+const EXTENDED_COLORS: [u32; 83] = [
+    0x470000, 0x472100, 0x474700, 0x324700, 0x004700, 0x00472c, 0x004747, 0x002747, 0x000047, 0x2e0047, 0x470047, 0x47002a, 0x740000, 0x743a00, 0x747400,
+    0x517400, 0x007400, 0x007449, 0x007474, 0x004074, 0x000074, 0x4b0074, 0x740074, 0x740045, 0xb50000, 0xb56300, 0xb5b500, 0x7db500, 0x00b500, 0x00b571,
+    0x00b5b5, 0x0063b5, 0x0000b5, 0x7500b5, 0xb500b5, 0xb5006b, 0xff0000, 0xff8c00, 0xffff00, 0xb2ff00, 0x00ff00, 0x00ffa0, 0x00ffff, 0x008cff, 0x0000ff,
+    0xa500ff, 0xff00ff, 0xff0098, 0xff5959, 0xffb459, 0xffff71, 0xcfff60, 0x6fff6f, 0x65ffc9, 0x6dffff, 0x59b4ff, 0x5959ff, 0xc459ff, 0xff66ff, 0xff59bc,
+    0xff9c9c, 0xffd39c, 0xffff9c, 0xe2ff9c, 0x9cff9c, 0x9cffdb, 0x9cffff, 0x9cd3ff, 0x9c9cff, 0xdc9cff, 0xff9cff, 0xff94d3, 0x000000, 0x131313, 0x282828,
+    0x363636, 0x4d4d4d, 0x656565, 0x818181, 0x9f9f9f, 0xbcbcbc, 0xe2e2e2, 0xffffff,
+];
+
+/// How a piece of text looks, as set by the formatting codes before it.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Style {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    /// Terminals are monospace already, kept so the rest of the style survives it.
+    pub monospace: bool,
+    pub reverse: bool,
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+}
+
+impl Style {
+    pub fn content_style(&self) -> ContentStyle {
+        let mut style = ContentStyle {
+            foreground_color: self.foreground,
+            background_color: self.background,
+            ..ContentStyle::default()
+        };
+        for (on, attribute) in [
+            (self.bold, Attribute::Bold),
+            (self.italic, Attribute::Italic),
+            (self.underline, Attribute::Underlined),
+            (self.strikethrough, Attribute::CrossedOut),
+            (self.reverse, Attribute::Reverse),
+        ] {
+            if on {
+                style.attributes.set(attribute);
+            }
+        }
+        style
+    }
+}
+
+/// Text in a single style.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+/// Appends `text` in `style`, growing the last span when it has the same style.
+pub fn push_text(spans: &mut Vec<Span>, text: &str, style: Style) {
+    match spans.last_mut() {
+        Some(span) if span.style == style => span.text.push_str(text),
+        _ => spans.push(Span { text: text.to_string(), style }),
+    }
+}
+
+fn rgb(value: u32) -> Color {
+    Color::Rgb {
+        r: (value >> 16) as u8,
+        g: (value >> 8) as u8,
+        b: value as u8,
+    }
+}
+
+/// Colour number `number` of mIRC, None for 99 which is the default colour.
+fn color(number: u8) -> Option<Color> {
+    match number {
+        0..=15 => Some(COLORS[number as usize]),
+        16..=98 => Some(rgb(EXTENDED_COLORS[number as usize - 16])),
+        _ => None,
+    }
+}
+
+/// Reads up to two digits of a colour number.
+fn color_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<u8> {
+    let mut number = None;
+    for _ in 0..2 {
+        match chars.peek().and_then(|ch| ch.to_digit(10)) {
+            Some(digit) => {
+                number = Some(number.unwrap_or(0) * 10 + digit as u8);
+                chars.next();
+            }
+            None => break,
+        }
+    }
+    number
+}
+
+/// Reads six hex digits of a colour, leaving them when there are fewer.
+fn hex_color(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<Color> {
+    let digits: String = chars.clone().take(6).collect();
+    if digits.len() != 6 || !digits.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        return None;
+    }
+    chars.nth(5);
+    u32::from_str_radix(&digits, 16).ok().map(rgb)
+}
+
+/// Splits text with mIRC formatting codes into the spans they style, without the codes.
+pub fn parse(text: &str) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    let mut style = Style::default();
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            BOLD => style.bold = !style.bold,
+            ITALIC => style.italic = !style.italic,
+            UNDERLINE => style.underline = !style.underline,
+            STRIKETHROUGH => style.strikethrough = !style.strikethrough,
+            MONOSPACE => style.monospace = !style.monospace,
+            REVERSE => style.reverse = !style.reverse,
+            RESET => style = Style::default(),
+            COLOR => match color_number(&mut chars) {
+                Some(foreground) => {
+                    style.foreground = color(foreground);
+                    // The comma only belongs to the code when a background follows it
+                    let mut after = chars.clone();
+                    if after.next() == Some(',') {
+                        if let Some(background) = color_number(&mut after) {
+                            style.background = color(background);
+                            chars = after;
+                        }
+                    }
+                }
+                None => (style.foreground, style.background) = (None, None),
+            },
+            HEX_COLOR => match hex_color(&mut chars) {
+                Some(foreground) => {
+                    style.foreground = Some(foreground);
+                    let mut after = chars.clone();
+                    if after.next() == Some(',') {
+                        if let Some(background) = hex_color(&mut after) {
+                            style.background = Some(background);
+                            chars = after;
+                        }
+                    }
+                }
+                None => (style.foreground, style.background) = (None, None),
+            },
+            _ => push_text(&mut spans, ch.encode_utf8(&mut [0; 4]), style),
+        }
+    }
+    spans
+}
+
+/// The text without its formatting codes.
+pub fn strip(text: &str) -> String {
+    parse(text).into_iter().map(|span| span.text).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(text: &str, style: Style) -> Span {
+        Span { text: text.to_string(), style }
+    }
+
+    #[test]
+    fn toggles_and_resets_attributes() {
+        let bold = Style {
+            bold: true,
+            ..Style::default()
+        };
+        let bold_underline = Style { underline: true, ..bold };
+        assert_eq!(
+            parse("a\x02b\x1Fc\x0Fd\x02\x02e"),
+            [
+                span("a", Style::default()),
+                span("b", bold),
+                span("c", bold_underline),
+                span("de", Style::default())
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_colour_numbers_and_backgrounds() {
+        let red = Style {
+            foreground: Some(Color::Red),
+            ..Style::default()
+        };
+        assert_eq!(parse("\x034red\x03 plain"), [span("red", red), span(" plain", Style::default())]);
+        assert_eq!(
+            parse("\x0304,2x"),
+            [span(
+                "x",
+                Style {
+                    background: Some(Color::DarkBlue),
+                    ..red
+                }
+            )]
+        );
+        // A comma without a background is text, and only two digits make the number
+        assert_eq!(parse("\x034,x"), [span(",x", red)]);
+        assert_eq!(parse("\x030412"), [span("12", red)]);
+        assert_eq!(parse("\x0399,99x"), [span("x", Style::default())]);
+        assert_eq!(parse("\x0352x")[0].style.foreground, Some(Color::Rgb { r: 0xff, g: 0, b: 0 }));
+    }
+
+    #[test]
+    fn reads_hex_colours() {
+        let style = Style {
+            foreground: Some(Color::Rgb { r: 0xff, g: 0x80, b: 0 }),
+            background: Some(Color::Rgb { r: 0, g: 0, b: 0x10 }),
+            ..Style::default()
+        };
+        assert_eq!(parse("\x04FF8000,000010x"), [span("x", style)]);
+        assert_eq!(parse("\x04FF80x"), [span("FF80x", Style::default())]);
+    }
+
+    #[test]
+    fn strips_every_code() {
+        assert_eq!(strip("\x02\x1D\x1F\x1E\x11\x16bo\x0F\x0312,01ld\x04abcdef!"), "bold!");
+    }
+}
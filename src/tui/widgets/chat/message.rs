@@ -1,16 +1,89 @@
-use crate::client::UserInfo;
+use crate::client::{ClientEvent, UserInfo};
 
 #[derive(Clone)]
+#[allow(dead_code)]
 pub enum Message {
     FromUser { user: UserInfo, text: String },
+    Notice { user: UserInfo, text: String },
+    Action { user: UserInfo, text: String },
     Join { user: UserInfo },
     Leave { user: UserInfo, reason: String },
     Quick { user: UserInfo, kicked_by: UserInfo, reason: String },
+    NickChange { user: UserInfo, new_nick: String },
     ChangeDay { date: time::Date },
     Mode { user: UserInfo, mode: String, changed_by: UserInfo },
+    Topic { user: Option<UserInfo>, topic: String },
     Info { message: String },
 }
 
+/// Names of the kinds of messages, one per variant of [`Message`].
+pub const KINDS: [&str; 11] = ["message", "notice", "action", "join", "leave", "kick", "nick", "day", "mode", "topic", "info"];
+
+impl Message {
+    /// Which of [`KINDS`] the message is.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::FromUser { .. } => "message",
+            Message::Notice { .. } => "notice",
+            Message::Action { .. } => "action",
+            Message::Join { .. } => "join",
+            Message::Leave { .. } => "leave",
+            Message::Quick { .. } => "kick",
+            Message::NickChange { .. } => "nick",
+            Message::ChangeDay { .. } => "day",
+            Message::Mode { .. } => "mode",
+            Message::Topic { .. } => "topic",
+            Message::Info { .. } => "info",
+        }
+    }
+
+    /// Nick of who said or did what the message shows.
+    pub fn nick(&self) -> Option<&str> {
+        match self {
+            Message::FromUser { user, .. }
+            | Message::Notice { user, .. }
+            | Message::Action { user, .. }
+            | Message::Join { user }
+            | Message::Leave { user, .. }
+            | Message::NickChange { user, .. }
+            | Message::Topic { user: Some(user), .. } => Some(user.nick()),
+            Message::Quick { kicked_by, .. } => Some(kicked_by.nick()),
+            Message::Mode { changed_by, .. } => Some(changed_by.nick()),
+            Message::ChangeDay { .. } | Message::Topic { user: None, .. } | Message::Info { .. } => None,
+        }
+    }
+
+    /// The text shown in the chat for this message, before it is wrapped to the chat width.
+    pub fn text(&self) -> String {
+        fn with_reason(text: String, reason: &str) -> String {
+            if reason.is_empty() {
+                text
+            } else {
+                format!("{text} ({reason})")
+            }
+        }
+
+        match self {
+            Message::FromUser { user, text } => format!("<{}> {text}", user.nick()),
+            Message::Notice { user, text } => format!("-{}- {text}", user.nick()),
+            Message::Action { user, text } => format!("* {} {text}", user.nick()),
+            Message::Join { user } => match user.mask() {
+                Some(mask) => format!("--> {} ({mask}) has joined", user.nick()),
+                None => format!("--> {} has joined", user.nick()),
+            },
+            Message::Leave { user, reason } => with_reason(format!("<-- {} has left", user.nick()), reason),
+            Message::Quick { user, kicked_by, reason } => with_reason(format!("<-- {} was kicked by {}", user.nick(), kicked_by.nick()), reason),
+            Message::NickChange { user, new_nick } => format!("-- {} is now known as {new_nick}", user.nick()),
+            Message::ChangeDay { date } => format!("-- Day changed to {date}"),
+            Message::Mode { user, mode, changed_by } => format!("-- {} sets mode {mode} on {}", changed_by.nick(), user.nick()),
+            Message::Topic { user: Some(user), topic } => format!("-- {} changed the topic to: {topic}", user.nick()),
+            Message::Topic { user: None, topic } if topic.is_empty() => "-- No topic is set".to_string(),
+            Message::Topic { user: None, topic } => format!("-- Topic: {topic}"),
+            Message::Info { message } => message.clone(),
+        }
+    }
+}
+
 impl From<&str> for Message {
     fn from(value: &str) -> Self {
         Message::Info { message: value.to_string() }
@@ -22,3 +95,67 @@ impl From<String> for Message {
         Message::Info { message: value.to_string() }
     }
 }
+
+impl From<ClientEvent> for Message {
+    fn from(value: ClientEvent) -> Self {
+        match value {
+            ClientEvent::Info(message) => Message::Info { message },
+            ClientEvent::Message { from, text, .. } => Message::FromUser { user: from, text },
+            ClientEvent::Notice { from, text, .. } => Message::Notice { user: from, text },
+            ClientEvent::Action { from, text, .. } => Message::Action { user: from, text },
+            ClientEvent::Ctcp { from, command, params } if params.is_empty() => Message::Info {
+                message: format!("-- {} requested CTCP {command}", from.nick()),
+            },
+            ClientEvent::Ctcp { from, command, params } => Message::Info {
+                message: format!("-- {} requested CTCP {command} {params}", from.nick()),
+            },
+            ClientEvent::CtcpReply { from, command, params } => Message::Info {
+                message: format!("-- CTCP {command} reply from {}: {params}", from.nick()),
+            },
+            ClientEvent::DccChat { nick, text, action: false } => Message::FromUser {
+                user: UserInfo::from_nick(nick),
+                text,
+            },
+            ClientEvent::DccChat { nick, text, action: true } => Message::Action {
+                user: UserInfo::from_nick(nick),
+                text,
+            },
+            ClientEvent::DccChatOpened { nick } => Message::Info {
+                message: format!("-- DCC chat with {nick} open"),
+            },
+            ClientEvent::DccChatClosed { nick, reason } => Message::Info {
+                message: format!("-- DCC chat with {nick} closed: {reason}"),
+            },
+            ClientEvent::DccTransfers => Message::Info {
+                message: "-- DCC transfers changed, /dcc list to see them".into(),
+            },
+            ClientEvent::Join { user, .. } => Message::Join { user },
+            ClientEvent::Part { user, reason, .. } => Message::Leave { user, reason },
+            ClientEvent::Quit { user, reason, .. } => Message::Leave { user, reason },
+            ClientEvent::Kick { user, kicked_by, reason, .. } => Message::Quick { user, kicked_by, reason },
+            ClientEvent::Nick { user, new_nick, .. } => Message::NickChange { user, new_nick },
+            ClientEvent::Mode { target, mode, changed_by } => Message::Mode {
+                user: UserInfo::from_nick(target),
+                mode,
+                changed_by,
+            },
+            ClientEvent::Topic { topic, set_by, .. } => Message::Topic { user: set_by, topic },
+            ClientEvent::ChannelInfo { text, .. } => Message::Info { message: format!("-- {text}") },
+            ClientEvent::ConnectFailed { reason } => Message::Info {
+                message: format!("-- Could not connect: {reason}"),
+            },
+            ClientEvent::Disconnected { reason } => Message::Info {
+                message: format!("-- Disconnected: {reason}"),
+            },
+            ClientEvent::Registered { nick } => Message::Info {
+                message: format!("-- Registered as {nick}"),
+            },
+            ClientEvent::Network { name } => Message::Info {
+                message: format!("-- Connected to the {name} network"),
+            },
+            ClientEvent::Reconnected => Message::Info {
+                message: "-- Reconnected".into(),
+            },
+        }
+    }
+}
@@ -1,25 +1,31 @@
+use crate::tui::buffers::Buffers;
 use crate::tui::constants::MIN_NICK_LIST_WIDTH;
+use crate::tui::networks::Networks;
 use crate::tui::position::{Point, Size};
 use crate::tui::traits::Draw;
 use crate::{impl_dirty, impl_resize};
 use crossterm::cursor::MoveTo;
 use crossterm::style::{Color, Print, Stylize};
 use crossterm::QueueableCommand;
+use std::cell::RefCell;
 use std::io;
+use std::rc::Rc;
 
 pub struct NickList {
     pub pos: Point,
     pub size: Size,
-    list: Vec<String>,
+    networks: Rc<RefCell<Networks>>,
+    buffers: Rc<RefCell<Buffers>>,
     dirty: bool,
 }
 
 impl NickList {
-    pub fn new(width: u16, height: u16) -> Self {
+    pub fn new(width: u16, height: u16, networks: Rc<RefCell<Networks>>, buffers: Rc<RefCell<Buffers>>) -> Self {
         Self {
             pos: (width - MIN_NICK_LIST_WIDTH, 1).into(),
             size: (MIN_NICK_LIST_WIDTH, height - 3).into(),
-            list: Vec::new(),
+            networks,
+            buffers,
             dirty: true,
         }
     }
@@ -29,9 +35,27 @@ impl Draw for NickList {
     fn draw(&mut self, out: &mut impl QueueableCommand) -> io::Result<()> {
         if self.dirty {
             self.dirty = false;
-            for (i, text) in self.list.iter().enumerate() {
+            let width = self.size.width as usize;
+            let buffers = self.buffers.borrow();
+            let client = self.networks.borrow().get(buffers.active_network());
+            let client = client.borrow();
+            let members = match client.channel(buffers.active().name.as_str()) {
+                Some(channel) => channel.members(client.prefix_modes()),
+                None => Vec::new(),
+            };
+            for i in 0..self.size.height as usize {
                 out.queue(MoveTo(self.pos.x, self.pos.y + i as u16))?;
-                out.queue(Print(text.clone().with(Color::Cyan)))?;
+                let Some(member) = members.get(i) else {
+                    out.queue(Print(format!("{:width$}", "")))?;
+                    continue;
+                };
+                let prefix = member.highest_prefix().unwrap_or(' ');
+                let text = format!("{:width$.width$}", format!("{prefix}{}", member.user.nick()));
+                match prefix {
+                    ' ' => out.queue(Print(text.with(Color::Cyan)))?,
+                    '+' => out.queue(Print(text.with(Color::Yellow)))?,
+                    _ => out.queue(Print(text.with(Color::Green)))?,
+                };
             }
         }
         Ok(())
@@ -7,7 +7,9 @@ use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use crossterm::style::{Color, Print, Stylize};
 use crossterm::QueueableCommand;
 
+use crate::tui::completion::{word_start, Completion};
 use crate::tui::constants::MIN_BUFFER_LIST_WIDTH;
+use crate::tui::history::InputHistory;
 use crate::tui::position::{Point, Size};
 use crate::tui::traits::Draw;
 use crate::{impl_dirty, impl_resize};
@@ -60,6 +62,17 @@ impl Cursor {
     }
 }
 
+/// Ctrl+R in progress: what is searched and the line it matched.
+struct Search {
+    query: String,
+    /// Position of the match in the global history, counted from the newest line.
+    found: Option<usize>,
+    /// Nothing older matches the query.
+    failed: bool,
+    /// The prompt before the search, back on Esc.
+    original: Vec<char>,
+}
+
 pub struct Prompt {
     pos: Point,
     pub size: Size,
@@ -67,12 +80,18 @@ pub struct Prompt {
     dirty: bool,
     buffer: Vec<char>,
     prev_buffer_len: usize,
+    prev_label_len: usize,
+    /// Shown instead of `text` while the chat is searched.
+    label: Option<String>,
     cursor: Cursor,
+    history: InputHistory,
+    search: Option<Search>,
+    completion: Option<Completion>,
     // scroll: u16,
 }
 
 impl Prompt {
-    pub fn new(width: u16, height: u16) -> Self {
+    pub fn new(width: u16, height: u16, history: InputHistory) -> Self {
         Self {
             pos: (MIN_BUFFER_LIST_WIDTH + 1, height - 1).into(),
             size: (width - MIN_BUFFER_LIST_WIDTH - 1, 1).into(),
@@ -80,20 +99,94 @@ impl Prompt {
             dirty: true,
             buffer: Vec::with_capacity(1024),
             prev_buffer_len: 0,
+            prev_label_len: 0,
+            label: None,
             cursor: Cursor { pos: 0, dirty: true },
+            history,
+            search: None,
+            completion: None,
             // scroll: 0,
         }
     }
 
-    pub fn key_press(&mut self, event: KeyEvent) -> Option<String> {
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Whether Ctrl+R is searching the history.
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    pub fn line(&self) -> String {
+        self.buffer.iter().collect()
+    }
+
+    pub fn set_label(&mut self, label: Option<String>) {
+        self.label = label;
+        self.dirty = true;
+    }
+
+    pub fn history(&self) -> &InputHistory {
+        &self.history
+    }
+
+    /// Completes the word at the cursor, or moves to the next or previous candidate when the
+    /// last key already completed it. `candidates` gets the text before the word and the word.
+    pub fn complete(&mut self, forward: bool, candidates: impl FnOnce(&str, &str) -> Vec<String>) {
+        match &mut self.completion {
+            Some(completion) => completion.cycle(forward),
+            None => {
+                let start = word_start(&self.buffer, self.cursor.pos);
+                let before: String = self.buffer[..start].iter().collect();
+                let word: String = self.buffer[start..self.cursor.pos].iter().collect();
+                self.completion = Completion::new(start, self.cursor.pos - start, candidates(&before, &word), forward);
+            }
+        }
+        if let Some(completion) = &mut self.completion {
+            let (start, len, text) = completion.replace();
+            self.buffer.splice(start..start + len, text.chars());
+            self.cursor.set(start + text.chars().count());
+            self.dirty = true;
+        }
+    }
+
+    /// Handles a key typed in `buffer`, returning the line once Enter sends it.
+    pub fn key_press(&mut self, event: KeyEvent, buffer: &str) -> Option<String> {
+        self.completion = None;
+        if self.search.is_some() && self.search_key(event) {
+            return None;
+        }
+        let control = event.modifiers.contains(KeyModifiers::CONTROL);
         match event.code {
+            KeyCode::Char('r') if control => {
+                self.history.reset();
+                self.search = Some(Search {
+                    query: String::new(),
+                    found: None,
+                    failed: false,
+                    original: self.buffer.clone(),
+                });
+                self.dirty = true;
+            }
             KeyCode::Char(ch) => {
                 self.dirty = true;
                 self.buffer.insert(self.cursor.pos, ch);
                 self.cursor += 1;
             }
-            KeyCode::Up => { /* Todo - implement prompt history */ }
-            KeyCode::Down => { /* Todo - implement prompt history */ }
+            // Up and Down go through what was sent in this buffer, with Ctrl through everything sent
+            KeyCode::Up => {
+                let draft = self.buffer.iter().collect::<String>();
+                if let Some(line) = self.history.previous((!control).then_some(buffer), &draft) {
+                    let line = line.to_string();
+                    self.set_line(&line);
+                }
+            }
+            KeyCode::Down => {
+                if let Some(line) = self.history.next((!control).then_some(buffer)) {
+                    self.set_line(&line);
+                }
+            }
             KeyCode::Left => {
                 self.key_left(event);
             }
@@ -114,6 +207,7 @@ impl Prompt {
             }
             KeyCode::Enter => {
                 let result = self.buffer.iter().collect::<String>();
+                self.history.add(buffer, &result);
                 self.buffer.clear();
                 self.cursor.set(0);
                 self.dirty = true;
@@ -124,6 +218,71 @@ impl Prompt {
         None
     }
 
+    pub fn set_line(&mut self, line: &str) {
+        self.buffer = line.chars().collect();
+        self.cursor.set(self.buffer.len());
+        self.dirty = true;
+    }
+
+    /// Keys of a reverse search, false when the key ends the search and still has to be handled,
+    /// like Enter sending the line found.
+    fn search_key(&mut self, event: KeyEvent) -> bool {
+        let Some(search) = &mut self.search else {
+            return false;
+        };
+        let control = event.modifiers.contains(KeyModifiers::CONTROL);
+        self.dirty = true;
+        let found = match event.code {
+            KeyCode::Char('r') if control => self.history.search(&search.query, search.found),
+            KeyCode::Char('g') if control => {
+                self.cancel_search();
+                return true;
+            }
+            KeyCode::Esc => {
+                self.cancel_search();
+                return true;
+            }
+            KeyCode::Char(ch) if !control => {
+                search.query.push(ch);
+                // The line shown may still match, readline only moves on when it does not
+                self.history.search(&search.query, search.found.and_then(|found| found.checked_sub(1)))
+            }
+            KeyCode::Backspace => {
+                search.query.pop();
+                self.history.search(&search.query, None)
+            }
+            _ => {
+                // Anything else keeps the line found and acts on it
+                self.search = None;
+                self.cursor.set(self.buffer.len());
+                return false;
+            }
+        };
+        search.failed = found.is_none();
+        if let Some((index, line)) = found {
+            search.found = Some(index);
+            self.buffer = line.chars().collect();
+            self.cursor.set(self.buffer.len());
+        }
+        true
+    }
+
+    fn cancel_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            self.buffer = search.original;
+            self.cursor.set(self.buffer.len());
+        }
+    }
+
+    /// What is shown before the typed text.
+    fn label(&self) -> String {
+        match &self.search {
+            Some(search) if search.failed => format!("(failed reverse-i-search)`{}':", search.query),
+            Some(search) => format!("(reverse-i-search)`{}':", search.query),
+            None => self.label.clone().unwrap_or_else(|| self.text.clone()),
+        }
+    }
+
     fn key_delete(&mut self, event: KeyEvent) {
         fn remove_chars(prompt: &mut Prompt, when: impl Fn(char) -> bool) {
             while prompt.cursor.pos < prompt.buffer.len() && when(prompt.safe_at(prompt.cursor.isize())) {
@@ -182,7 +341,7 @@ impl Prompt {
 
     fn safe_at(&self, idx: isize) -> char {
         let idx = max(idx, 0) as usize;
-        self.buffer.get(idx).unwrap_or(&' ').clone()
+        *self.buffer.get(idx).unwrap_or(&' ')
     }
 }
 
@@ -191,19 +350,21 @@ impl Draw for Prompt {
         if self.dirty {
             self.dirty = false;
             self.cursor.dirty = true;
-            let str = format!("{} ", self.text).with(Color::DarkGreen);
+            let label = self.label();
+            let label_len = label.chars().count();
             out.queue(MoveTo(self.pos.x, self.pos.y))?;
-            out.queue(Print(str))?;
+            out.queue(Print(format!("{label} ").with(Color::DarkGreen)))?;
             out.queue(Print(self.buffer.iter().collect::<String>()))?;
-            if self.buffer.len() != self.prev_buffer_len {
-                out.queue(Print(" ".repeat(self.prev_buffer_len)))?;
+            if self.buffer.len() != self.prev_buffer_len || label_len != self.prev_label_len {
+                out.queue(Print(" ".repeat(self.prev_buffer_len + self.prev_label_len.saturating_sub(label_len))))?;
                 self.prev_buffer_len = self.buffer.len();
+                self.prev_label_len = label_len;
             }
         }
 
         self.cursor.sync(self.buffer.len());
 
-        if let Some(col) = self.cursor.should_move(self.pos.x, self.text.len()) {
+        if let Some(col) = self.cursor.should_move(self.pos.x, self.prev_label_len) {
             out.queue(MoveToColumn(col))?;
         }
         Ok(())
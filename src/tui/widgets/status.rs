@@ -1,28 +1,62 @@
+use crate::tui::buffers::Buffers;
 use crate::tui::constants::MIN_BUFFER_LIST_WIDTH;
+use crate::tui::networks::Networks;
 use crate::tui::position::{Point, Size};
 use crate::tui::traits::Draw;
 use crate::{impl_dirty, impl_resize};
 use crossterm::cursor::MoveTo;
 use crossterm::style::{Color, Print, Stylize};
 use crossterm::QueueableCommand;
+use std::cell::RefCell;
 use std::io;
+use std::rc::Rc;
 
 pub struct Status {
     pos: Point,
     pub size: Size,
     text: String,
+    networks: Rc<RefCell<Networks>>,
+    buffers: Rc<RefCell<Buffers>>,
     dirty: bool,
 }
 
 impl Status {
-    pub fn new(width: u16, height: u16) -> Self {
+    pub fn new(width: u16, height: u16, networks: Rc<RefCell<Networks>>, buffers: Rc<RefCell<Buffers>>) -> Self {
         Self {
             pos: (MIN_BUFFER_LIST_WIDTH + 1, height - 2).into(),
             size: (width - MIN_BUFFER_LIST_WIDTH - 1, 1).into(),
-            text: "STATUS BAR".into(),
+            text: String::new(),
+            networks,
+            buffers,
             dirty: true,
         }
     }
+
+    /// Refreshes the text from the client of the active network, redrawing only when it changed.
+    pub fn update(&mut self) {
+        let buffers = self.buffers.borrow();
+        let client = self.networks.borrow().get(buffers.active_network());
+        let client = client.borrow();
+        let mut text = format!(" {}", client.nick());
+        if let Some((address, port)) = client.server_address() {
+            text += &format!(" | {address}:{port}");
+        }
+        if let Some(lag) = client.lag() {
+            text += &format!(" | lag: {:.1}s", lag.as_secs_f32());
+        }
+        let queued = client.queued();
+        if queued > 0 {
+            text += &format!(" | queued: {queued}");
+        }
+        let scroll = buffers.active().scroll;
+        if scroll > 0 {
+            text += &format!(" | -- MORE ({scroll}) --");
+        }
+        if text != self.text {
+            self.text = text;
+            self.dirty = true;
+        }
+    }
 }
 
 impl Draw for Status {
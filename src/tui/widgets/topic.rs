@@ -1,25 +1,32 @@
+use crate::tui::buffers::Buffers;
 use crate::tui::constants::MIN_BUFFER_LIST_WIDTH;
+use crate::tui::networks::Networks;
 use crate::tui::position::{Point, Size};
 use crate::tui::traits::Draw;
+use crate::tui::widgets::chat::format;
 use crate::{impl_dirty, impl_resize};
 use crossterm::cursor::MoveTo;
 use crossterm::style::{Color, Print, Stylize};
 use crossterm::QueueableCommand;
+use std::cell::RefCell;
 use std::io;
+use std::rc::Rc;
 
 pub struct Topic {
     pub pos: Point,
     pub size: Size,
-    text: String,
+    networks: Rc<RefCell<Networks>>,
+    buffers: Rc<RefCell<Buffers>>,
     dirty: bool,
 }
 
 impl Topic {
-    pub fn new(width: u16, _height: u16) -> Self {
+    pub fn new(width: u16, _height: u16, networks: Rc<RefCell<Networks>>, buffers: Rc<RefCell<Buffers>>) -> Self {
         Self {
             pos: (MIN_BUFFER_LIST_WIDTH + 1, 0).into(),
             size: (width - MIN_BUFFER_LIST_WIDTH - 1, 1).into(),
-            text: "Topic".into(),
+            networks,
+            buffers,
             dirty: true,
         }
     }
@@ -29,9 +36,19 @@ impl Draw for Topic {
     fn draw(&mut self, out: &mut impl QueueableCommand) -> io::Result<()> {
         if self.dirty {
             self.dirty = false;
-            let str = format!("{:width$}", self.text, width = self.size.width as usize)
-                .with(Color::White)
-                .on(Color::Blue);
+            let buffers = self.buffers.borrow();
+            let client = self.networks.borrow().get(buffers.active_network());
+            let client = client.borrow();
+            let buffer = buffers.active();
+            let text = match client.channel(buffer.name.as_str()) {
+                Some(channel) if !channel.mode_string().is_empty() => {
+                    format!("{} [{}]: {}", buffer.name, channel.mode_string(), format::strip(channel.topic()))
+                }
+                Some(channel) => format!("{}: {}", buffer.name, format::strip(channel.topic())),
+                None => buffer.name.to_string(),
+            };
+            let width = self.size.width as usize;
+            let str = format!("{:width$.width$}", text).with(Color::White).on(Color::Blue);
             out.queue(MoveTo(self.pos.x, self.pos.y))?;
             out.queue(Print(str))?;
         }